@@ -0,0 +1,24 @@
+extern crate sophon_wasm;
+
+use std::env;
+use std::process;
+use sophon_wasm::analysis::diff::ModuleDiff;
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        println!("Usage: {} old.wasm new.wasm", args[0]);
+        return;
+    }
+
+    let old = sophon_wasm::deserialize_file(&args[1]).expect("Failed to load old module");
+    let new = sophon_wasm::deserialize_file(&args[2]).expect("Failed to load new module");
+    let diff = ModuleDiff::new(&old, &new).expect("Failed to compare modules");
+
+    if diff.is_empty() {
+        println!("Modules are structurally identical");
+        return;
+    }
+    print!("{}", diff);
+    process::exit(1);
+}
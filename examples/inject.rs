@@ -4,23 +4,16 @@ use std::env;
 
 use sophon_wasm::elements;
 use sophon_wasm::builder;
+use sophon_wasm::elements::{VisitorMut, Editor};
 
-pub fn inject_nop(opcodes: &mut elements::Opcodes) {
-    use sophon_wasm::elements::Opcode::*;
-    let opcodes = opcodes.elements_mut();
-    let mut position = 0;
-    loop {
-        let need_inject = match &opcodes[position] {
-            &Block(_) | &If(_) => true,
-            _ => false,
-        };
-        if need_inject {
-            opcodes.insert(position + 1, Nop);
-        }
+struct InjectNop;
 
-        position += 1;
-        if position >= opcodes.len() {
-            break;
+impl VisitorMut for InjectNop {
+    fn visit_control(&mut self, editor: &mut Editor, opcode: &mut elements::Opcode) {
+        use sophon_wasm::elements::Opcode::*;
+        match *opcode {
+            Block(_) | If(_) => editor.insert_after(Nop),
+            _ => { },
         }
     }
 }
@@ -34,16 +27,7 @@ fn main() {
 
     let mut module = sophon_wasm::deserialize_file(&args[1]).unwrap();
 
-    for section in module.sections_mut() {
-        match section {
-            &mut elements::Section::Code(ref mut code_section) => {
-                for ref mut func_body in code_section.bodies_mut() {
-                    inject_nop(func_body.code_mut());
-                }
-            },
-            _ => { }
-        }
-    }
+    InjectNop.visit_module(&mut module);
 
     let mut build = builder::from_module(module);
     let import_sig = build.push_signature(
@@ -56,7 +40,7 @@ fn main() {
     let build = build.import()
         .module("env")
         .field("log")
-        .external().func(import_sig)
+        .external().func(import_sig.index())
         .build();
 
     sophon_wasm::serialize_to_file(&args[2], build.build()).unwrap();
@@ -0,0 +1,24 @@
+extern crate sophon_wasm;
+
+use std::env;
+use sophon_wasm::transform::Reducer;
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() < 4 {
+        println!("Usage: {} input.wasm output.wasm command [args...]", args[0]);
+        println!("    The module is reduced while `command [args...] candidate.wasm` exits successfully.");
+        return;
+    }
+
+    let mut module = sophon_wasm::deserialize_file(&args[1]).expect("Failed to load module");
+    let report = Reducer::new()
+        .reduce_with_command(&mut module, &args[3], &args[4..])
+        .expect("Failed to reduce module");
+    sophon_wasm::serialize_to_file(&args[2], module).expect("Failed to write module");
+
+    println!(
+        "Reduced {} bytes to {} bytes ({} of {} candidates kept in {} rounds)",
+        report.original_size, report.reduced_size, report.reductions, report.attempts, report.rounds,
+    );
+}
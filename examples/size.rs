@@ -0,0 +1,23 @@
+extern crate sophon_wasm;
+
+use std::env;
+use sophon_wasm::analysis::size::SizeProfile;
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    let json = args.iter().skip(1).any(|a| a == "--json");
+    let files = args.iter().skip(1).filter(|a| *a != "--json").collect::<Vec<_>>();
+    if files.len() != 1 {
+        println!("Usage: {} [--json] somefile.wasm", args[0]);
+        return;
+    }
+
+    let module = sophon_wasm::deserialize_file(files[0]).expect("Failed to load module");
+    let profile = SizeProfile::new(&module).expect("Failed to profile module");
+
+    if json {
+        println!("{}", profile.to_json());
+    } else {
+        print!("{}", profile);
+    }
+}
@@ -0,0 +1,41 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sophon_wasm;
+
+use sophon_wasm::elements::{self, Module, Internal, Type, ValueType};
+use sophon_wasm::generator::{Generator, Config};
+use sophon_wasm::interpreter::{ProgramInstance, ModuleInstanceInterface, ExecutionParams, RuntimeValue};
+
+fuzz_target!(|data: &[u8]| {
+    // imports cannot be resolved and the start function may trap, so both are disabled
+    let generator = Generator::new(Config::new().with_max_imports(0).with_start(false));
+    let module = generator.from_bytes(data);
+
+    let bytes = elements::serialize(module.clone()).expect("serialize generated module");
+    let decoded: Module = elements::deserialize_buffer(bytes.clone())
+        .expect("deserialize generated module, indicating possible bug in serializer or deserializer");
+    assert_eq!(elements::serialize(decoded).expect("serialize decoded module"), bytes);
+
+    let program = ProgramInstance::new();
+    let instance = program.add_module("main", module.clone(), None)
+        .expect("instantiate generated module, indicating possible bug in validator or generator");
+
+    let types = module.type_section().map(|s| s.types().to_vec()).unwrap_or_default();
+    let functions = module.function_section().map(|s| s.entries().to_vec()).unwrap_or_default();
+    for export in module.export_section().map(|s| s.entries()).unwrap_or(&[]) {
+        let index = match *export.internal() {
+            Internal::Function(index) => index as usize,
+            _ => continue,
+        };
+        let Type::Function(ref signature) = types[functions[index].type_ref() as usize];
+        let args: Vec<RuntimeValue> = signature.params().iter().map(|param| match *param {
+            ValueType::I32 => RuntimeValue::I32(0),
+            ValueType::I64 => RuntimeValue::I64(0),
+            ValueType::F32 => RuntimeValue::F32(0.0),
+            ValueType::F64 => RuntimeValue::F64(0.0),
+        }).collect();
+        // traps are fine, panics are not
+        let _ = instance.execute_export(export.field(), ExecutionParams::from(args));
+    }
+});
@@ -0,0 +1,283 @@
+//! Call graph of the module.
+//!
+//! Nodes are functions of the function index space (imported functions
+//! included). Direct edges come from `call`. Indirect edges come from
+//! `call_indirect` and conservatively lead to every function placed into
+//! the table by element segments whose signature matches the call.
+
+use std::collections::BTreeSet;
+use elements::{Module, Opcode, External, Internal, FunctionType, Type, Visitor, Location};
+
+/// Call graph of the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    imported: u32,
+    direct: Vec<BTreeSet<u32>>,
+    indirect: Vec<BTreeSet<u32>>,
+    roots: BTreeSet<u32>,
+}
+
+struct CallCollector<'a> {
+    graph: &'a mut CallGraph,
+    signatures: &'a [Option<FunctionType>],
+    table_members: &'a BTreeSet<u32>,
+    function_types: &'a [Option<FunctionType>],
+}
+
+impl<'a> Visitor for CallCollector<'a> {
+    fn visit_call(&mut self, location: Location, opcode: &Opcode) {
+        let len = self.graph.len() as u32;
+        match *opcode {
+            Opcode::Call(callee) if callee < len => {
+                self.graph.direct[location.func as usize].insert(callee);
+            },
+            Opcode::CallIndirect(type_ref, _) => {
+                let signature = match self.signatures.get(type_ref as usize) {
+                    Some(Some(signature)) => signature,
+                    _ => return,
+                };
+                for &member in self.table_members {
+                    if self.function_types[member as usize].as_ref() == Some(signature) {
+                        self.graph.indirect[location.func as usize].insert(member);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl CallGraph {
+    /// Build call graph of the module.
+    ///
+    /// Roots are exported functions, the start function and, when the table is
+    /// imported or exported (and so can be called from the outside), all table members.
+    pub fn new(module: &Module) -> Self {
+        let signatures: Vec<Option<FunctionType>> = module.type_section()
+            .map(|section| section.types().iter().map(|t| match *t {
+                Type::Function(ref function_type) => Some(function_type.clone()),
+            }).collect())
+            .unwrap_or_default();
+        let signature = |type_ref: u32| signatures.get(type_ref as usize).cloned().unwrap_or(None);
+
+        let mut function_types = Vec::new();
+        let mut external_table = false;
+        if let Some(import_section) = module.import_section() {
+            for entry in import_section.entries() {
+                match *entry.external() {
+                    External::Function(type_ref) => function_types.push(signature(type_ref)),
+                    External::Table(_) => external_table = true,
+                    _ => {},
+                }
+            }
+        }
+        let imported = function_types.len() as u32;
+        if let Some(function_section) = module.function_section() {
+            function_types.extend(function_section.entries().iter().map(|func| signature(func.type_ref())));
+        }
+        let len = function_types.len();
+
+        let mut roots = BTreeSet::new();
+        if let Some(export_section) = module.export_section() {
+            for entry in export_section.entries() {
+                match *entry.internal() {
+                    Internal::Function(index) => { roots.insert(index); },
+                    Internal::Table(_) => external_table = true,
+                    _ => {},
+                }
+            }
+        }
+        if let Some(start) = module.start_section() {
+            roots.insert(start);
+        }
+
+        let table_members: BTreeSet<u32> = module.elements_section()
+            .map(|section| section.entries().iter()
+                .flat_map(|segment| segment.members().iter().cloned())
+                .filter(|member| (*member as usize) < len)
+                .collect())
+            .unwrap_or_default();
+        if external_table {
+            roots.extend(table_members.iter().cloned());
+        }
+        roots.retain(|root| (*root as usize) < len);
+
+        let mut graph = CallGraph {
+            imported,
+            direct: vec![BTreeSet::new(); len],
+            indirect: vec![BTreeSet::new(); len],
+            roots,
+        };
+
+        CallCollector {
+            graph: &mut graph,
+            signatures: &signatures,
+            table_members: &table_members,
+            function_types: &function_types,
+        }.visit_module(module);
+
+        graph
+    }
+
+    /// Number of functions in the function index space.
+    pub fn len(&self) -> usize { self.direct.len() }
+
+    /// Is function index space empty.
+    pub fn is_empty(&self) -> bool { self.direct.is_empty() }
+
+    /// Number of imported functions (they occupy the first indices).
+    pub fn imported_functions(&self) -> u32 { self.imported }
+
+    /// Roots of the graph.
+    pub fn roots(&self) -> &BTreeSet<u32> { &self.roots }
+
+    /// Functions called by `func` with `call`.
+    pub fn direct_callees(&self, func: u32) -> &BTreeSet<u32> { &self.direct[func as usize] }
+
+    /// Functions possibly called by `func` with `call_indirect`.
+    pub fn indirect_callees(&self, func: u32) -> &BTreeSet<u32> { &self.indirect[func as usize] }
+
+    /// All functions possibly called by `func`.
+    pub fn callees(&self, func: u32) -> BTreeSet<u32> {
+        self.direct_callees(func).union(self.indirect_callees(func)).cloned().collect()
+    }
+
+    /// All functions possibly calling `func`.
+    pub fn callers(&self, func: u32) -> BTreeSet<u32> {
+        (0..self.len() as u32)
+            .filter(|caller| self.direct_callees(*caller).contains(&func) || self.indirect_callees(*caller).contains(&func))
+            .collect()
+    }
+
+    /// Functions reachable from the roots (roots included).
+    pub fn reachable(&self) -> BTreeSet<u32> {
+        self.reachable_from(self.roots.iter().cloned())
+    }
+
+    /// Functions reachable from the given functions (given functions included).
+    pub fn reachable_from<I: IntoIterator<Item=u32>>(&self, from: I) -> BTreeSet<u32> {
+        let mut reachable = BTreeSet::new();
+        let mut stack: Vec<u32> = from.into_iter().filter(|func| (*func as usize) < self.len()).collect();
+        while let Some(func) = stack.pop() {
+            if !reachable.insert(func) {
+                continue;
+            }
+            stack.extend(self.direct_callees(func).iter().cloned());
+            stack.extend(self.indirect_callees(func).iter().cloned());
+        }
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::{module, signature};
+    use elements::{Opcode, Opcodes, ExportEntry, Internal, ImportEntry, External, TableType};
+    use super::CallGraph;
+
+    fn body(opcodes: Vec<Opcode>) -> Opcodes {
+        Opcodes::new(opcodes)
+    }
+
+    #[test]
+    fn direct_and_indirect() {
+        use elements::Opcode::*;
+
+        let module = module()
+            // type 1 is () -> ()
+            .with_signatures(vec![signature().param().i32().build_sig(), signature().build_sig()])
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            // 1: exported, calls 2 directly and the table indirectly
+            .function()
+                .signature().build()
+                .body().with_opcodes(body(vec![Call(2), I32Const(0), CallIndirect(1, false), End])).build()
+                .build()
+            // 2: calls the import
+            .function()
+                .signature().build()
+                .body().with_opcodes(body(vec![Call(0), End])).build()
+                .build()
+            // 3: table member, signature () -> ()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            // 4: table member with other signature
+            .function()
+                .signature().param().i32().build()
+                .body().build()
+                .build()
+            // 5: dead
+            .function()
+                .signature().build()
+                .body().with_opcodes(body(vec![Call(5), End])).build()
+                .build()
+            .table()
+                .with_min(2)
+                .with_element(0, vec![3, 4])
+                .build()
+            .with_export(ExportEntry::new("call".into(), Internal::Function(1)))
+            .build();
+
+        let graph = CallGraph::new(&module);
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.imported_functions(), 1);
+        assert_eq!(graph.roots().iter().cloned().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(graph.direct_callees(1).iter().cloned().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(graph.indirect_callees(1).iter().cloned().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(graph.callers(5).iter().cloned().collect::<Vec<_>>(), vec![5]);
+        assert_eq!(graph.reachable().into_iter().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn imported_table() {
+        let module = module()
+            .with_import(ImportEntry::new("env".into(), "table".into(), External::Table(TableType::new(1, None))))
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_section(::elements::Section::Element(::elements::ElementSection::with_entries(vec![
+                ::elements::ElementSegment::new(
+                    0,
+                    ::elements::InitExpr::new(vec![Opcode::I32Const(0), Opcode::End]),
+                    vec![0],
+                ),
+            ])))
+            .build();
+
+        let graph = CallGraph::new(&module);
+        assert!(graph.roots().contains(&0));
+    }
+
+    #[test]
+    fn exported_table() {
+        let exported = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .table()
+                .with_min(1)
+                .with_element(0, vec![0])
+                .build()
+            .with_export(ExportEntry::new("table".into(), Internal::Table(0)))
+            .build();
+
+        let graph = CallGraph::new(&exported);
+        assert!(graph.roots().contains(&0));
+
+        let internal = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .table()
+                .with_min(1)
+                .with_element(0, vec![0])
+                .build()
+            .build();
+        assert!(CallGraph::new(&internal).roots().is_empty());
+    }
+}
@@ -0,0 +1,561 @@
+//! Control-flow graph of the function body.
+//!
+//! Basic blocks are ranges of the flat opcode sequence. Every graph has a
+//! virtual exit block (without opcodes), which is the successor of blocks
+//! leaving the function by `Return`, `Unreachable`, a branch to the function
+//! label or the final `End`.
+
+use std::collections::BTreeMap;
+use elements::{Opcode, Opcodes, FuncBody};
+use super::Error;
+
+/// Index of the basic block in `Cfg::blocks`.
+pub type BlockId = usize;
+
+/// How the control leaves the basic block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Terminator {
+    /// Falls through to the next block.
+    Fallthrough,
+    /// Unconditional branch.
+    Br,
+    /// Conditional branch, falls through otherwise.
+    BrIf,
+    /// Branch table.
+    BrTable,
+    /// Return from the function.
+    Return,
+    /// Trap.
+    Unreachable,
+    /// Enters either the `then` or the `else` branch of the if.
+    If,
+    /// End of the `then` branch, continues after the if.
+    Else,
+    /// Virtual exit block.
+    Exit,
+}
+
+/// Basic block of the function body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    start: usize,
+    end: usize,
+    terminator: Terminator,
+    successors: Vec<BlockId>,
+    predecessors: Vec<BlockId>,
+}
+
+impl BasicBlock {
+    /// Position of the first opcode of the block.
+    pub fn start(&self) -> usize { self.start }
+
+    /// Position after the last opcode of the block.
+    pub fn end(&self) -> usize { self.end }
+
+    /// Terminator of the block.
+    pub fn terminator(&self) -> Terminator { self.terminator }
+
+    /// Blocks which the control can be transferred to from this block.
+    pub fn successors(&self) -> &[BlockId] { &self.successors }
+
+    /// Blocks which can transfer the control to this block.
+    pub fn predecessors(&self) -> &[BlockId] { &self.predecessors }
+}
+
+/// Control-flow graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+}
+
+/// Branch target of the structured block.
+#[derive(Clone, Copy)]
+enum Target {
+    Position(usize),
+    Exit,
+}
+
+impl Cfg {
+    /// Build graph from the opcode sequence of the function body.
+    pub fn from_opcodes(opcodes: &Opcodes) -> Result<Self, Error> {
+        let code = opcodes.elements();
+        let structure = Structure::new(code)?;
+
+        // first opcode, branch targets and opcodes following terminators start new blocks
+        let mut terminators = BTreeMap::new();
+        let mut labels = vec![Target::Exit];
+        let mut leaders = vec![0];
+        for (position, opcode) in code.iter().enumerate() {
+            let resolve = |labels: &[Target], depth: u32| {
+                labels.len().checked_sub(depth as usize + 1)
+                    .map(|index| labels[index])
+                    .ok_or(Error::InvalidBranchDepth { position, depth })
+            };
+
+            let (terminator, targets) = match *opcode {
+                Opcode::Block(_) | Opcode::If(_) => {
+                    let end = structure.ends[&position];
+                    labels.push(Target::Position(end));
+                    if let Opcode::If(_) = *opcode {
+                        let otherwise = structure.elses.get(&position).map(|e| e + 1).unwrap_or(end);
+                        (Terminator::If, vec![Target::Position(position + 1), Target::Position(otherwise)])
+                    } else {
+                        continue;
+                    }
+                },
+                Opcode::Loop(_) => {
+                    labels.push(Target::Position(position));
+                    leaders.push(position);
+                    continue;
+                },
+                Opcode::Else => {
+                    let end = match labels.last() {
+                        Some(&Target::Position(end)) => end,
+                        _ => return Err(Error::UnexpectedElse(position)),
+                    };
+                    (Terminator::Else, vec![Target::Position(end)])
+                },
+                Opcode::End => {
+                    labels.pop();
+                    continue;
+                },
+                Opcode::Br(depth) => (Terminator::Br, vec![resolve(&labels, depth)?]),
+                Opcode::BrIf(depth) => (Terminator::BrIf, vec![resolve(&labels, depth)?, Target::Position(position + 1)]),
+                Opcode::BrTable(ref table, default) => {
+                    let mut targets = table.iter()
+                        .map(|depth| resolve(&labels, *depth))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    targets.push(resolve(&labels, default)?);
+                    (Terminator::BrTable, targets)
+                },
+                Opcode::Return => (Terminator::Return, vec![Target::Exit]),
+                Opcode::Unreachable => (Terminator::Unreachable, vec![Target::Exit]),
+                _ => continue,
+            };
+
+            for target in &targets {
+                if let Target::Position(target) = *target {
+                    leaders.push(target);
+                }
+            }
+            leaders.push(position + 1);
+            terminators.insert(position, (terminator, targets));
+        }
+
+        leaders.sort();
+        leaders.dedup();
+
+        let exit = leaders.len();
+        let block_of = |position: usize| leaders.binary_search(&position)
+            .expect("every branch target is a leader; qed");
+
+        let mut blocks: Vec<BasicBlock> = leaders.iter().enumerate().map(|(id, &start)| {
+            let end = leaders.get(id + 1).cloned().unwrap_or(code.len());
+            let (terminator, successors) = match terminators.get(&(end - 1)) {
+                Some(&(terminator, ref targets)) => {
+                    let mut successors: Vec<BlockId> = targets.iter()
+                        .map(|target| match *target {
+                            Target::Position(position) => block_of(position),
+                            Target::Exit => exit,
+                        })
+                        .collect();
+                    successors.sort();
+                    successors.dedup();
+                    (terminator, successors)
+                },
+                None => (Terminator::Fallthrough, vec![id + 1]),
+            };
+            BasicBlock { start, end, terminator, successors, predecessors: Vec::new() }
+        }).collect();
+
+        blocks.push(BasicBlock {
+            start: code.len(),
+            end: code.len(),
+            terminator: Terminator::Exit,
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        });
+
+        for id in 0..blocks.len() {
+            for successor in blocks[id].successors.clone() {
+                blocks[successor].predecessors.push(id);
+            }
+        }
+
+        Ok(Cfg { blocks })
+    }
+
+    /// Build graph from the code of the function body.
+    pub fn from_func_body(body: &FuncBody) -> Result<Self, Error> {
+        Cfg::from_opcodes(body.code())
+    }
+
+    /// All blocks of the graph, including the exit block.
+    pub fn blocks(&self) -> &[BasicBlock] { &self.blocks }
+
+    /// Entry block.
+    pub fn entry(&self) -> BlockId { 0 }
+
+    /// Virtual exit block.
+    pub fn exit(&self) -> BlockId { self.blocks.len() - 1 }
+
+    /// Block containing the opcode at `position`.
+    pub fn block_at(&self, position: usize) -> Option<BlockId> {
+        let exit = self.exit();
+        match self.blocks[..exit].binary_search_by_key(&position, |block| block.start) {
+            Ok(id) => Some(id),
+            Err(0) => None,
+            Err(id) if position < self.blocks[id - 1].end => Some(id - 1),
+            Err(_) => None,
+        }
+    }
+
+    /// Dominator tree rooted at the entry block.
+    pub fn dominators(&self) -> Dominators {
+        Dominators::compute(self.entry(), self.blocks.len(), |id| &self.blocks[id].successors, |id| &self.blocks[id].predecessors)
+    }
+
+    /// Post-dominator tree rooted at the exit block.
+    /// Blocks which never reach the exit (infinite loops) have no post-dominators.
+    pub fn post_dominators(&self) -> Dominators {
+        Dominators::compute(self.exit(), self.blocks.len(), |id| &self.blocks[id].predecessors, |id| &self.blocks[id].successors)
+    }
+
+    /// Natural loops of the graph, one per loop header, ordered by header.
+    pub fn natural_loops(&self) -> Vec<NaturalLoop> {
+        let dominators = self.dominators();
+        let mut loops: BTreeMap<BlockId, NaturalLoop> = BTreeMap::new();
+
+        for (tail, block) in self.blocks.iter().enumerate() {
+            for &header in &block.successors {
+                if !dominators.dominates(header, tail) {
+                    continue;
+                }
+
+                let natural_loop = loops.entry(header).or_insert_with(|| NaturalLoop {
+                    header,
+                    back_edges: Vec::new(),
+                    body: vec![header],
+                });
+                natural_loop.back_edges.push(tail);
+
+                let mut stack = vec![tail];
+                while let Some(id) = stack.pop() {
+                    if natural_loop.body.contains(&id) {
+                        continue;
+                    }
+                    natural_loop.body.push(id);
+                    stack.extend(self.blocks[id].predecessors.iter().cloned());
+                }
+            }
+        }
+
+        loops.into_values().map(|mut natural_loop| {
+            natural_loop.body.sort();
+            natural_loop
+        }).collect()
+    }
+}
+
+/// Natural loop of the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NaturalLoop {
+    header: BlockId,
+    back_edges: Vec<BlockId>,
+    body: Vec<BlockId>,
+}
+
+impl NaturalLoop {
+    /// Loop header, which dominates every block of the loop.
+    pub fn header(&self) -> BlockId { self.header }
+
+    /// Blocks with the edge back to the header.
+    pub fn back_edges(&self) -> &[BlockId] { &self.back_edges }
+
+    /// All blocks of the loop (including the header), sorted.
+    pub fn body(&self) -> &[BlockId] { &self.body }
+
+    /// Is block part of the loop.
+    pub fn contains(&self, id: BlockId) -> bool {
+        self.body.binary_search(&id).is_ok()
+    }
+}
+
+/// Dominator (or post-dominator) tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dominators {
+    root: BlockId,
+    idom: Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    /// Dominator tree of the graph of `len` nodes rooted at `root`, given by
+    /// the successors and predecessors of every node.
+    ///
+    /// Not limited to the basic blocks: any graph with nodes numbered from zero will do.
+    // Cooper, Harvey, Kennedy: "A Simple, Fast Dominance Algorithm".
+    pub fn compute<'a, S, P>(root: BlockId, len: usize, successors: S, predecessors: P) -> Self
+        where S: Fn(BlockId) -> &'a [BlockId], P: Fn(BlockId) -> &'a [BlockId]
+    {
+        // postorder numbering of blocks reachable from the root
+        let mut postorder = Vec::with_capacity(len);
+        let mut order: Vec<Option<usize>> = vec![None; len];
+        let mut visited = vec![false; len];
+        let mut stack = vec![(root, 0)];
+        visited[root] = true;
+        while let Some((id, next)) = stack.pop() {
+            match successors(id).get(next) {
+                Some(&successor) => {
+                    stack.push((id, next + 1));
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                },
+                None => {
+                    order[id] = Some(postorder.len());
+                    postorder.push(id);
+                },
+            }
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; len];
+        idom[root] = Some(root);
+
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while order[a] < order[b] {
+                    a = idom[a].expect("processed blocks have dominator; qed");
+                }
+                while order[b] < order[a] {
+                    b = idom[b].expect("processed blocks have dominator; qed");
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in postorder.iter().rev() {
+                if id == root {
+                    continue;
+                }
+                let mut new_idom = None;
+                for &predecessor in predecessors(id) {
+                    if idom[predecessor].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(current) => intersect(&idom, predecessor, current),
+                    });
+                }
+                if new_idom.is_some() && idom[id] != new_idom {
+                    idom[id] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idom[root] = None;
+        Dominators { root, idom }
+    }
+
+    /// Root of the tree.
+    pub fn root(&self) -> BlockId { self.root }
+
+    /// Immediate dominator of the block. `None` for the root and for unreachable blocks.
+    pub fn immediate_dominator(&self, id: BlockId) -> Option<BlockId> {
+        self.idom[id]
+    }
+
+    /// Is block reachable from the root.
+    pub fn is_reachable(&self, id: BlockId) -> bool {
+        id == self.root || self.idom[id].is_some()
+    }
+
+    /// Does block `a` dominate block `b` (every block dominates itself).
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        let mut current = b;
+        loop {
+            if current == a {
+                return true;
+            }
+            match self.idom[current] {
+                Some(next) => current = next,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Matching `Else` and `End` positions of structured blocks.
+struct Structure {
+    ends: BTreeMap<usize, usize>,
+    elses: BTreeMap<usize, usize>,
+}
+
+impl Structure {
+    fn new(code: &[Opcode]) -> Result<Self, Error> {
+        let mut ends = BTreeMap::new();
+        let mut elses = BTreeMap::new();
+        let mut stack = Vec::new();
+        for (position, opcode) in code.iter().enumerate() {
+            match *opcode {
+                Opcode::Block(_) | Opcode::Loop(_) | Opcode::If(_) => stack.push(position),
+                Opcode::Else => {
+                    let start = *stack.last().ok_or(Error::UnexpectedElse(position))?;
+                    match code[start] {
+                        Opcode::If(_) if !elses.contains_key(&start) => { elses.insert(start, position); },
+                        _ => return Err(Error::UnexpectedElse(position)),
+                    }
+                },
+                Opcode::End => match stack.pop() {
+                    Some(start) => { ends.insert(start, position); },
+                    None if position + 1 == code.len() => return Ok(Structure { ends, elses }),
+                    None => return Err(Error::UnexpectedEnd(position)),
+                },
+                _ => {},
+            }
+        }
+        Err(Error::MissingEnd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use elements::{Opcodes, BlockType, deserialize_file};
+    use super::{Cfg, Terminator};
+
+    #[test]
+    fn straight() {
+        use elements::Opcode::*;
+
+        let cfg = Cfg::from_opcodes(&Opcodes::new(vec![I32Const(1), Drop, End])).expect("cfg to be built");
+        assert_eq!(cfg.blocks().len(), 2);
+        assert_eq!(cfg.blocks()[0].successors(), &[cfg.exit()]);
+        assert_eq!(cfg.dominators().immediate_dominator(cfg.exit()), Some(0));
+        assert_eq!(cfg.post_dominators().immediate_dominator(0), Some(cfg.exit()));
+    }
+
+    #[test]
+    fn diamond() {
+        use elements::Opcode::*;
+
+        let cfg = Cfg::from_opcodes(&Opcodes::new(vec![
+            GetLocal(0),                // 0: block 0
+            If(BlockType::NoResult),
+                Nop,                    // 2: block 1
+            Else,
+                Nop,                    // 4: block 2
+            End,                        // 5: block 3
+            End,
+        ])).expect("cfg to be built");
+
+        assert_eq!(cfg.blocks().len(), 5);
+        assert_eq!(cfg.blocks()[0].terminator(), Terminator::If);
+        assert_eq!(cfg.blocks()[0].successors(), &[1, 2]);
+        assert_eq!(cfg.blocks()[1].successors(), &[3]);
+        assert_eq!(cfg.blocks()[2].successors(), &[3]);
+        assert_eq!(cfg.blocks()[3].predecessors(), &[1, 2]);
+
+        let dominators = cfg.dominators();
+        assert_eq!(dominators.immediate_dominator(3), Some(0));
+        assert!(dominators.dominates(0, 2));
+        assert!(!dominators.dominates(1, 3));
+
+        let post_dominators = cfg.post_dominators();
+        assert_eq!(post_dominators.immediate_dominator(0), Some(3));
+        assert!(post_dominators.dominates(3, 1));
+
+        assert!(cfg.natural_loops().is_empty());
+        assert_eq!(cfg.block_at(4), Some(2));
+        assert_eq!(cfg.block_at(6), Some(3));
+        assert_eq!(cfg.block_at(7), None);
+    }
+
+    #[test]
+    fn natural_loop() {
+        use elements::Opcode::*;
+
+        let cfg = Cfg::from_opcodes(&Opcodes::new(vec![
+            I32Const(10),               // 0: block 0
+            SetLocal(0),
+            Loop(BlockType::NoResult),  // 2: block 1 (header)
+                GetLocal(0),
+                I32Const(1),
+                I32Sub,
+                TeeLocal(0),
+                BrIf(0),
+            End,                        // 8: block 2
+            End,
+        ])).expect("cfg to be built");
+
+        assert_eq!(cfg.blocks()[1].terminator(), Terminator::BrIf);
+        assert_eq!(cfg.blocks()[1].successors(), &[1, 2]);
+
+        let loops = cfg.natural_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].header(), 1);
+        assert_eq!(loops[0].back_edges(), &[1]);
+        assert_eq!(loops[0].body(), &[1]);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        use elements::Opcode::*;
+
+        let cfg = Cfg::from_opcodes(&Opcodes::new(vec![
+            Block(BlockType::NoResult),
+                Br(0),
+                Nop,                    // 2: unreachable block
+            End,
+            Unreachable,
+            End,
+        ])).expect("cfg to be built");
+
+        let dominators = cfg.dominators();
+        let dead = cfg.block_at(2).expect("block to exist");
+        assert!(!dominators.is_reachable(dead));
+        assert_eq!(cfg.blocks()[dead].successors(), &[dead + 1]);
+
+        let trap = cfg.block_at(4).expect("block to exist");
+        assert_eq!(trap, dead + 1);
+        assert_eq!(cfg.blocks()[trap].terminator(), Terminator::Unreachable);
+        assert_eq!(cfg.blocks()[trap].successors(), &[cfg.exit()]);
+    }
+
+    #[test]
+    fn infinite_loop() {
+        use elements::Opcode::*;
+
+        let cfg = Cfg::from_opcodes(&Opcodes::new(vec![
+            Loop(BlockType::NoResult),
+                Br(0),
+            End,
+            End,
+        ])).expect("cfg to be built");
+
+        assert_eq!(cfg.natural_loops().len(), 1);
+        assert!(!cfg.post_dominators().is_reachable(0));
+    }
+
+    #[test]
+    fn cases() {
+        let module = deserialize_file("./res/cases/v1/test5.wasm").expect("Should be deserialized");
+        for body in module.code_section().expect("code section to exist").bodies() {
+            let cfg = Cfg::from_func_body(body).expect("cfg to be built");
+            let dominators = cfg.dominators();
+            for id in 0..cfg.blocks().len() {
+                if dominators.is_reachable(id) {
+                    assert!(dominators.dominates(cfg.entry(), id));
+                }
+            }
+        }
+    }
+}
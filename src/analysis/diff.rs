@@ -0,0 +1,455 @@
+//! Structural difference of two modules.
+//!
+//! Imports are matched by their module and field, exports by their field,
+//! globals and data segments by their position. Defined functions are
+//! matched by the name from the name section, or by the export name, so
+//! functions moved to other indices are still compared with each other.
+//! Calls in the bodies are compared by the callee names as well, and
+//! indirect calls by the signatures, so renumbering alone is not reported
+//! as a change. Sections are only reported when added or removed, except
+//! for custom sections other than the name section, whose payloads are
+//! compared as they are.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use elements::{
+    self, Module, Section, Opcode, Type, FunctionType, ImportEntry, ExportEntry,
+    GlobalEntry, DataSegment, External, Internal, NameSection, FuncBody,
+};
+
+/// Change of the item between two modules.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<T> {
+    /// Item is only in the new module.
+    Added(T),
+    /// Item is only in the old module.
+    Removed(T),
+    /// Item differs between the modules.
+    Changed {
+        /// Item in the old module.
+        old: T,
+        /// Item in the new module.
+        new: T,
+    },
+}
+
+/// Defined function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// Name the function is matched by.
+    pub name: String,
+    /// Function index, imported functions included.
+    pub index: u32,
+    /// Function signature.
+    pub signature: FunctionType,
+    /// Number of opcodes in the body.
+    pub opcodes: usize,
+}
+
+/// Differences between two modules.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ModuleDiff {
+    sections: Vec<Change<String>>,
+    imports: Vec<Change<ImportEntry>>,
+    exports: Vec<Change<ExportEntry>>,
+    globals: Vec<Change<(u32, GlobalEntry)>>,
+    functions: Vec<Change<Function>>,
+    data: Vec<Change<(usize, DataSegment)>>,
+}
+
+impl ModuleDiff {
+    /// Compare the modules.
+    pub fn new(old: &Module, new: &Module) -> Result<Self, elements::Error> {
+        let sections = diff(sections(old)?, sections(new)?, |old, new| old.1 == new.1)
+            .into_iter()
+            .map(|change| match change {
+                Change::Added((name, _)) => Change::Added(name),
+                Change::Removed((name, _)) => Change::Removed(name),
+                Change::Changed { old: (old, _), new: (new, _) } => Change::Changed { old, new },
+            })
+            .collect();
+
+        let imports = |module: &Module| module.import_section()
+            .map(|s| s.entries().iter().map(|e| ((e.module().to_owned(), e.field().to_owned()), e.clone())).collect())
+            .unwrap_or_default();
+        let imports = diff(imports(old), imports(new), |old, new| old.external() == new.external());
+
+        let old_functions = Functions::new(old);
+        let new_functions = Functions::new(new);
+
+        let exports = |module: &Module| module.export_section()
+            .map(|s| s.entries().iter().map(|e| (e.field().to_owned(), e.clone())).collect())
+            .unwrap_or_default();
+        let exports = diff(exports(old), exports(new), |old, new| match (*old.internal(), *new.internal()) {
+            (Internal::Function(old), Internal::Function(new)) =>
+                old_functions.name(old) == new_functions.name(new),
+            (old, new) => old == new,
+        });
+
+        let globals = |module: &Module| module.global_section()
+            .map(|s| s.entries().iter().cloned().enumerate().map(|(i, g)| (i, (i as u32, g))).collect())
+            .unwrap_or_default();
+        let globals = diff(globals(old), globals(new), |old, new| old.1 == new.1);
+
+        let functions = diff(
+            old_functions.defined(),
+            new_functions.defined(),
+            |old, new| old.signature == new.signature && bodies_equal(
+                &old_functions, old_functions.body(old.index),
+                &new_functions, new_functions.body(new.index),
+            ),
+        );
+
+        let data = |module: &Module| module.data_section()
+            .map(|s| s.entries().iter().cloned().enumerate().map(|(i, d)| (i, (i, d))).collect())
+            .unwrap_or_default();
+        let data = diff(data(old), data(new), |old, new| old.1 == new.1);
+
+        Ok(ModuleDiff { sections, imports, exports, globals, functions, data })
+    }
+
+    /// Whether the modules have no differences.
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty() && self.imports.is_empty() && self.exports.is_empty() &&
+            self.globals.is_empty() && self.functions.is_empty() && self.data.is_empty()
+    }
+
+    /// Added and removed sections, and custom sections with changed payloads, by the section name.
+    pub fn sections(&self) -> &[Change<String>] { &self.sections }
+
+    /// Added, removed and changed imports.
+    pub fn imports(&self) -> &[Change<ImportEntry>] { &self.imports }
+
+    /// Added, removed and changed exports.
+    pub fn exports(&self) -> &[Change<ExportEntry>] { &self.exports }
+
+    /// Added, removed and changed globals with their indices in the global section.
+    pub fn globals(&self) -> &[Change<(u32, GlobalEntry)>] { &self.globals }
+
+    /// Added, removed and changed defined functions.
+    pub fn functions(&self) -> &[Change<Function>] { &self.functions }
+
+    /// Added, removed and changed data segments with their positions in the data section.
+    pub fn data(&self) -> &[Change<(usize, DataSegment)>] { &self.data }
+}
+
+impl fmt::Display for ModuleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_changes<T, F>(f: &mut fmt::Formatter, kind: &str, changes: &[Change<T>], describe: F) -> fmt::Result
+            where F: Fn(&T) -> String
+        {
+            for change in changes {
+                match *change {
+                    Change::Added(ref item) => writeln!(f, "+ {} {}", kind, describe(item))?,
+                    Change::Removed(ref item) => writeln!(f, "- {} {}", kind, describe(item))?,
+                    Change::Changed { ref old, ref new } => {
+                        let (old, new) = (describe(old), describe(new));
+                        if old == new {
+                            writeln!(f, "~ {} {}", kind, old)?
+                        } else {
+                            writeln!(f, "~ {} {} -> {}", kind, old, new)?
+                        }
+                    },
+                }
+            }
+            Ok(())
+        }
+
+        write_changes(f, "section", &self.sections, |name| name.clone())?;
+        write_changes(f, "import", &self.imports, |e| format!("{}.{} {:?}", e.module(), e.field(), e.external()))?;
+        write_changes(f, "export", &self.exports, |e| format!("{} {:?}", e.field(), e.internal()))?;
+        write_changes(f, "global", &self.globals, |&(index, ref g)| {
+            format!("{} {:?} {:?}", index, g.global_type(), g.init_expr().code())
+        })?;
+        write_changes(f, "function", &self.functions, |func| {
+            format!("{} [{}] {:?} ({} opcodes)", func.name, func.index, func.signature, func.opcodes)
+        })?;
+        write_changes(f, "data", &self.data, |&(index, ref segment)| {
+            format!("{} {:?} ({} bytes)", index, segment.offset().code(), segment.value().len())
+        })
+    }
+}
+
+/// Changes between the keyed items, in the old order followed by the added items in the new order.
+fn diff<K: Ord + Clone, T: Clone, F: Fn(&T, &T) -> bool>(old: Vec<(K, T)>, new: Vec<(K, T)>, same: F) -> Vec<Change<T>> {
+    let new_by_key: BTreeMap<K, T> = new.iter().cloned().collect();
+    let old_by_key: BTreeMap<K, T> = old.iter().cloned().collect();
+    let mut changes = Vec::new();
+    for (key, old_item) in old {
+        match new_by_key.get(&key) {
+            None => changes.push(Change::Removed(old_item)),
+            Some(new_item) => if !same(&old_item, new_item) {
+                changes.push(Change::Changed { old: old_item, new: new_item.clone() });
+            },
+        }
+    }
+    for (key, new_item) in new {
+        if !old_by_key.contains_key(&key) {
+            changes.push(Change::Added(new_item));
+        }
+    }
+    changes
+}
+
+/// Section name with the payload of the opaque section, keyed by the name and the position among the sections of the same name.
+type KeyedSection = ((String, usize), (String, Option<Vec<u8>>));
+
+/// Sections keyed by their names and positions among the sections of the same name.
+///
+/// Only custom sections other than the name section and unknown sections keep
+/// their serialized payloads, the contents of the others are compared item by item.
+fn sections(module: &Module) -> Result<Vec<KeyedSection>, elements::Error> {
+    let mut occurrences: BTreeMap<String, usize> = BTreeMap::new();
+    let mut result = Vec::new();
+    for section in module.sections() {
+        let name = match *section {
+            Section::Custom(ref custom) => format!("custom \"{}\"", custom.name()),
+            Section::Unparsed { id, .. } => format!("unknown {}", id),
+            Section::Type(_) => "type".to_owned(),
+            Section::Import(_) => "import".to_owned(),
+            Section::Function(_) => "function".to_owned(),
+            Section::Table(_) => "table".to_owned(),
+            Section::Memory(_) => "memory".to_owned(),
+            Section::Global(_) => "global".to_owned(),
+            Section::Export(_) => "export".to_owned(),
+            Section::Start(_) => "start".to_owned(),
+            Section::Element(_) => "element".to_owned(),
+            Section::Code(_) => "code".to_owned(),
+            Section::Data(_) => "data".to_owned(),
+        };
+        let occurrence = occurrences.entry(name.clone()).or_insert(0);
+        let payload = match *section {
+            Section::Custom(ref custom) if custom.name() != "name" => Some(elements::serialize(section.clone())?),
+            Section::Unparsed { .. } => Some(elements::serialize(section.clone())?),
+            _ => None,
+        };
+        result.push(((name.clone(), *occurrence), (name, payload)));
+        *occurrence += 1;
+    }
+    Ok(result)
+}
+
+/// Functions of the module with the names they are matched by.
+struct Functions<'a> {
+    names: Vec<String>,
+    imported: usize,
+    signatures: Vec<FunctionType>,
+    types: Vec<FunctionType>,
+    bodies: &'a [FuncBody],
+}
+
+impl<'a> Functions<'a> {
+    fn new(module: &'a Module) -> Self {
+        let debug_names = module.sections().iter()
+            .filter_map(|s| match *s { Section::Custom(ref c) => NameSection::from_custom(c), _ => None })
+            .filter_map(|n| n.ok())
+            .next()
+            .and_then(|n| n.functions().cloned());
+        let mut export_names = BTreeMap::new();
+        for entry in module.export_section().map(|s| s.entries()).unwrap_or(&[]) {
+            if let Internal::Function(index) = *entry.internal() {
+                export_names.entry(index).or_insert_with(|| entry.field().to_owned());
+            }
+        }
+
+        let types = module.type_section().map(|s| s.types()).unwrap_or(&[]);
+        let signature = |type_ref: u32| match types.get(type_ref as usize) {
+            Some(Type::Function(signature)) => signature.clone(),
+            None => FunctionType::default(),
+        };
+        let mut names = Vec::new();
+        let mut signatures = Vec::new();
+        for entry in module.import_section().map(|s| s.entries()).unwrap_or(&[]) {
+            if let External::Function(type_ref) = *entry.external() {
+                names.push(format!("{}.{}", entry.module(), entry.field()));
+                signatures.push(signature(type_ref));
+            }
+        }
+        let imported = names.len();
+        for (index, func) in module.function_section().map(|s| s.entries()).unwrap_or(&[]).iter().enumerate() {
+            let index = (imported + index) as u32;
+            names.push(export_names.get(&index).cloned().unwrap_or_else(|| format!("func[{}]", index)));
+            signatures.push(signature(func.type_ref()));
+        }
+        if let Some(debug_names) = debug_names {
+            for (&index, name) in debug_names.names() {
+                if let Some(slot) = names.get_mut(index as usize) {
+                    *slot = name.clone();
+                }
+            }
+        }
+
+        Functions {
+            names,
+            imported,
+            signatures,
+            types: types.iter().map(|t| match *t { Type::Function(ref signature) => signature.clone() }).collect(),
+            bodies: module.code_section().map(|s| s.bodies()).unwrap_or(&[]),
+        }
+    }
+
+    fn name(&self, index: u32) -> Option<&str> {
+        self.names.get(index as usize).map(|n| n.as_str())
+    }
+
+    fn signature(&self, type_ref: u32) -> Option<&FunctionType> {
+        self.types.get(type_ref as usize)
+    }
+
+    fn body(&self, index: u32) -> Option<&'a FuncBody> {
+        (index as usize).checked_sub(self.imported).and_then(|i| self.bodies.get(i))
+    }
+
+    fn defined(&self) -> Vec<(String, Function)> {
+        let mut seen = BTreeMap::new();
+        (self.imported..self.names.len()).map(|index| {
+            let name = self.names[index].clone();
+            // the same name given to several functions is disambiguated by the occurrence
+            let occurrence = seen.entry(name.clone()).or_insert(0);
+            *occurrence += 1;
+            let key = if *occurrence == 1 { name.clone() } else { format!("{}#{}", name, occurrence) };
+            (key, Function {
+                name,
+                index: index as u32,
+                signature: self.signatures[index].clone(),
+                opcodes: self.body(index as u32).map(|b| b.code().elements().len()).unwrap_or(0),
+            })
+        }).collect()
+    }
+}
+
+fn bodies_equal(old_functions: &Functions, old: Option<&FuncBody>, new_functions: &Functions, new: Option<&FuncBody>) -> bool {
+    let (old, new) = match (old, new) {
+        (Some(old), Some(new)) => (old, new),
+        (old, new) => return old.is_none() && new.is_none(),
+    };
+    old.locals() == new.locals() &&
+        old.code().elements().len() == new.code().elements().len() &&
+        old.code().elements().iter().zip(new.code().elements()).all(|pair| match pair {
+            (&Opcode::Call(old), &Opcode::Call(new)) => old_functions.name(old) == new_functions.name(new),
+            (&Opcode::CallIndirect(old, old_reserved), &Opcode::CallIndirect(new, new_reserved)) =>
+                old_reserved == new_reserved && old_functions.signature(old) == new_functions.signature(new),
+            (old, new) => old == new,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::{module, signature};
+    use elements::{Module, Opcodes, Opcode, ExportEntry, Internal, ImportEntry, External, Section, CustomSection};
+    use super::{ModuleDiff, Change};
+
+    fn base() -> ::builder::ModuleBuilder {
+        module()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Nop, Opcode::End])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Call(0), Opcode::End])).build()
+                .build()
+            .with_export(ExportEntry::new("helper".into(), Internal::Function(0)))
+            .with_export(ExportEntry::new("main".into(), Internal::Function(1)))
+    }
+
+    #[test]
+    fn identical() {
+        let module: Module = base().build();
+        let diff = ModuleDiff::new(&module, &module.clone()).expect("diff to succeed");
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "");
+    }
+
+    #[test]
+    fn shifted_indices() {
+        let old = base().build();
+        // an imported function shifts all defined functions by one
+        let new = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Nop, Opcode::End])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Call(1), Opcode::Call(0), Opcode::End])).build()
+                .build()
+            .with_export(ExportEntry::new("helper".into(), Internal::Function(1)))
+            .with_export(ExportEntry::new("main".into(), Internal::Function(2)))
+            .build();
+
+        let diff = ModuleDiff::new(&old, &new).expect("diff to succeed");
+        assert_eq!(diff.imports().len(), 1);
+        assert!(matches!(diff.imports()[0], Change::Added(ref e) if e.field() == "log"));
+        assert!(diff.exports().is_empty());
+        assert_eq!(diff.functions().len(), 1);
+        match diff.functions()[0] {
+            Change::Changed { ref old, ref new } => {
+                assert_eq!(old.name, "main");
+                assert_eq!((old.index, new.index), (1, 2));
+                assert_eq!((old.opcodes, new.opcodes), (2, 3));
+            },
+            ref other => panic!("unexpected change {:?}", other),
+        }
+        assert_eq!(diff.sections(), &[Change::Added("import".to_owned())][..]);
+        assert!(diff.to_string().contains("~ function main [1]"));
+    }
+
+    #[test]
+    fn indirect_call_by_signature() {
+        let caller = |type_ref: u32| Opcodes::new(vec![Opcode::I32Const(0), Opcode::CallIndirect(type_ref, false), Opcode::End]);
+        let old = module()
+            .with_signatures(vec![signature().build_sig(), signature().param().i32().build_sig()])
+            .function()
+                .signature().build()
+                .body().with_opcodes(caller(1)).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(0)))
+            .build();
+        // the same signature under another type index
+        let new = module()
+            .with_signatures(vec![signature().param().i32().build_sig(), signature().build_sig()])
+            .function()
+                .signature().build()
+                .body().with_opcodes(caller(0)).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(0)))
+            .build();
+        let changed = module()
+            .with_signatures(vec![signature().build_sig(), signature().param().i32().build_sig()])
+            .function()
+                .signature().build()
+                .body().with_opcodes(caller(0)).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(0)))
+            .build();
+
+        assert!(ModuleDiff::new(&old, &new).expect("diff to succeed").is_empty());
+        let diff = ModuleDiff::new(&old, &changed).expect("diff to succeed");
+        assert_eq!(diff.functions().len(), 1);
+        assert!(diff.sections().is_empty());
+    }
+
+    #[test]
+    fn duplicate_custom_sections() {
+        let custom = |payload: Vec<u8>| Section::Custom(CustomSection::new("producers".into(), payload));
+        let old = base()
+            .with_section(custom(vec![1]))
+            .with_section(custom(vec![2]))
+            .build();
+        let new = base()
+            .with_section(custom(vec![1]))
+            .with_section(custom(vec![3]))
+            .with_section(custom(vec![4]))
+            .build();
+
+        let diff = ModuleDiff::new(&old, &new).expect("diff to succeed");
+        let producers = "custom \"producers\"".to_owned();
+        assert_eq!(diff.sections(), &[
+            Change::Changed { old: producers.clone(), new: producers.clone() },
+            Change::Added(producers),
+        ][..]);
+    }
+}
@@ -0,0 +1,45 @@
+//! Analyses over the elements of the module.
+
+use std::fmt;
+
+pub mod tree;
+pub mod cfg;
+pub mod call_graph;
+pub mod size;
+pub mod diff;
+
+/// Analysis error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// `Else` at the given position does not belong to an `If` block.
+    UnexpectedElse(usize),
+    /// `End` at the given position closes the function body before its last opcode.
+    UnexpectedEnd(usize),
+    /// Opcode sequence ends before all blocks are closed.
+    MissingEnd,
+    /// Branch at the given position refers to the label out of scope.
+    InvalidBranchDepth {
+        /// Position of the branch opcode.
+        position: usize,
+        /// Relative depth of the branch target.
+        depth: u32,
+    },
+    /// Label is referenced outside of the block it belongs to.
+    UnresolvedLabel(tree::Label),
+    /// Structured control opcode found where a plain opcode is expected.
+    StructuralOpcode(::elements::Opcode),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnexpectedElse(position) => write!(f, "Unexpected else at {}", position),
+            Error::UnexpectedEnd(position) => write!(f, "Unexpected end of function at {}", position),
+            Error::MissingEnd => write!(f, "Missing end of block"),
+            Error::InvalidBranchDepth { position, depth } =>
+                write!(f, "Branch at {} to depth {} is out of scope", position, depth),
+            Error::UnresolvedLabel(label) => write!(f, "Label {} is not in scope", label.0),
+            Error::StructuralOpcode(ref opcode) => write!(f, "Structured opcode {} is not allowed here", opcode),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Size attribution of the module.
+//!
+//! Every section, function body, data segment and custom section is
+//! attributed its serialized size. Functions additionally get the retained
+//! size: the size of the function together with all functions reachable only
+//! through it, computed with the dominator tree of the call graph rooted at
+//! the exports, the start function and the table.
+
+use std::fmt;
+use elements::{self, Module, Section, NameSection};
+use super::call_graph::CallGraph;
+use super::cfg::Dominators;
+
+/// Size of the section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionSize {
+    /// Section name, the name of the custom section for custom sections.
+    pub name: String,
+    /// Serialized size including the section header, in bytes.
+    pub size: usize,
+}
+
+/// Size of the function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSize {
+    /// Function index, imported functions included.
+    pub index: u32,
+    /// Function name from the name section, if any.
+    pub name: Option<String>,
+    /// Serialized size of the function body, zero for imported functions.
+    pub size: usize,
+    /// Size of the function and all functions it dominates in the call graph.
+    pub retained: usize,
+    /// Immediate dominator, `None` for roots and unreachable functions.
+    pub dominator: Option<u32>,
+}
+
+/// Size of the data segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSize {
+    /// Position of the segment in the data section.
+    pub index: usize,
+    /// Serialized size of the segment, in bytes.
+    pub size: usize,
+}
+
+/// Size attribution report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeProfile {
+    total: usize,
+    sections: Vec<SectionSize>,
+    functions: Vec<FunctionSize>,
+    data: Vec<DataSize>,
+    custom: Vec<SectionSize>,
+}
+
+impl SizeProfile {
+    /// Profile the module.
+    pub fn new(module: &Module) -> Result<Self, elements::Error> {
+        // magic and version
+        let mut total = 8;
+        let mut sections = Vec::new();
+        let mut custom = Vec::new();
+        let mut names = None;
+        for section in module.sections() {
+            let size = elements::serialize(section.clone())?.len();
+            total += size;
+            let name = match *section {
+                Section::Custom(ref custom_section) => {
+                    if let Some(Ok(name_section)) = NameSection::from_custom(custom_section) {
+                        names = Some(name_section);
+                    }
+                    custom.push(SectionSize { name: custom_section.name().to_owned(), size });
+                    "custom"
+                },
+                Section::Unparsed { .. } => "unknown",
+                Section::Type(_) => "type",
+                Section::Import(_) => "import",
+                Section::Function(_) => "function",
+                Section::Table(_) => "table",
+                Section::Memory(_) => "memory",
+                Section::Global(_) => "global",
+                Section::Export(_) => "export",
+                Section::Start(_) => "start",
+                Section::Element(_) => "element",
+                Section::Code(_) => "code",
+                Section::Data(_) => "data",
+            };
+            sections.push(SectionSize { name: name.to_owned(), size });
+        }
+
+        let imported = module.import_section().map(|s| s.functions()).unwrap_or(0);
+        let mut sizes = vec![0; imported];
+        for body in module.code_section().map(|s| s.bodies()).unwrap_or(&[]) {
+            sizes.push(elements::serialize(body.clone())?.len());
+        }
+
+        let data = module.data_section()
+            .map(|s| s.entries())
+            .unwrap_or(&[])
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| Ok(DataSize { index, size: elements::serialize(segment.clone())?.len() }))
+            .collect::<Result<Vec<_>, elements::Error>>()?;
+
+        let call_graph = CallGraph::new(module);
+        if call_graph.len() != sizes.len() {
+            return Err(elements::Error::InconsistentLength { expected: call_graph.len(), actual: sizes.len() });
+        }
+        let dominators = dominators(&call_graph);
+        let mut retained = sizes.clone();
+        // functions follow their dominators in the order, so accumulate backwards
+        for &func in dominators.order.iter().rev() {
+            if let Some(dominator) = dominators.idom[func as usize] {
+                retained[dominator as usize] += retained[func as usize];
+            }
+        }
+
+        let function_names = names.as_ref().and_then(|n| n.functions());
+        let functions = (0..sizes.len() as u32).map(|index| FunctionSize {
+            index,
+            name: function_names.and_then(|n| n.get(index)).map(|n| n.to_owned()),
+            size: sizes[index as usize],
+            retained: retained[index as usize],
+            dominator: dominators.idom[index as usize],
+        }).collect();
+
+        Ok(SizeProfile { total, sections, functions, data, custom })
+    }
+
+    /// Size of the whole module, in bytes.
+    pub fn total(&self) -> usize { self.total }
+
+    /// Sizes of the sections in the module order.
+    pub fn sections(&self) -> &[SectionSize] { &self.sections }
+
+    /// Sizes of the functions by their index.
+    pub fn functions(&self) -> &[FunctionSize] { &self.functions }
+
+    /// Sizes of the data segments.
+    pub fn data(&self) -> &[DataSize] { &self.data }
+
+    /// Sizes of the custom sections.
+    pub fn custom(&self) -> &[SectionSize] { &self.custom }
+
+    /// Report as the JSON object.
+    pub fn to_json(&self) -> String {
+        let sections = |sections: &[SectionSize]| sections.iter()
+            .map(|s| format!("{{\"name\":{},\"size\":{}}}", json_string(&s.name), s.size))
+            .collect::<Vec<_>>()
+            .join(",");
+        let functions = self.functions.iter()
+            .map(|f| format!(
+                "{{\"index\":{},\"name\":{},\"size\":{},\"retained\":{},\"dominator\":{}}}",
+                f.index,
+                f.name.as_ref().map(|n| json_string(n)).unwrap_or_else(|| "null".to_owned()),
+                f.size,
+                f.retained,
+                f.dominator.map(|d| d.to_string()).unwrap_or_else(|| "null".to_owned()),
+            ))
+            .collect::<Vec<_>>()
+            .join(",");
+        let data = self.data.iter()
+            .map(|d| format!("{{\"index\":{},\"size\":{}}}", d.index, d.size))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"total\":{},\"sections\":[{}],\"functions\":[{}],\"data\":[{}],\"custom\":[{}]}}",
+            self.total, sections(&self.sections), functions, data, sections(&self.custom),
+        )
+    }
+}
+
+impl fmt::Display for SizeProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Total: {} bytes", self.total)?;
+        writeln!(f)?;
+        writeln!(f, "{:>10}  Section", "Size")?;
+        for section in self.sections.iter().filter(|s| s.name != "custom") {
+            writeln!(f, "{:>10}  {}", section.size, section.name)?;
+        }
+        for section in &self.custom {
+            writeln!(f, "{:>10}  custom \"{}\"", section.size, section.name)?;
+        }
+
+        let mut functions: Vec<&FunctionSize> = self.functions.iter().filter(|f| f.size > 0).collect();
+        functions.sort_by(|a, b| b.retained.cmp(&a.retained).then(a.index.cmp(&b.index)));
+        writeln!(f)?;
+        writeln!(f, "{:>10}  {:>10}  {:>8}  Function", "Retained", "Size", "Index")?;
+        for func in functions {
+            let name = func.name.clone().unwrap_or_else(|| format!("func[{}]", func.index));
+            writeln!(f, "{:>10}  {:>10}  {:>8}  {}", func.retained, func.size, func.index, name)?;
+        }
+
+        if !self.data.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:>10}  Data segment", "Size")?;
+            for segment in &self.data {
+                writeln!(f, "{:>10}  {}", segment.size, segment.index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+struct CallDominators {
+    /// Immediate dominator of every function, `None` for the roots and unreachable functions.
+    idom: Vec<Option<u32>>,
+    /// Reachable functions, every function after its immediate dominator.
+    order: Vec<u32>,
+}
+
+/// Dominator tree of the call graph, rooted at the virtual entry calling every root.
+fn dominators(call_graph: &CallGraph) -> CallDominators {
+    let len = call_graph.len();
+    let entry = len;
+    let mut successors: Vec<Vec<usize>> = (0..len as u32)
+        .map(|func| call_graph.callees(func).into_iter().map(|f| f as usize).collect())
+        .collect();
+    successors.push(call_graph.roots().iter().map(|&f| f as usize).collect());
+    let mut predecessors = vec![Vec::new(); len + 1];
+    for (node, callees) in successors.iter().enumerate() {
+        for &callee in callees {
+            predecessors[callee].push(node);
+        }
+    }
+
+    let tree = Dominators::compute(entry, len + 1, |node| &successors[node], |node| &predecessors[node]);
+    let idom: Vec<Option<usize>> = (0..len).map(|func| tree.immediate_dominator(func)).collect();
+
+    // walk the tree from the entry so that every function follows its dominator
+    let mut children = vec![Vec::new(); len + 1];
+    for (func, dominator) in idom.iter().enumerate() {
+        if let Some(dominator) = *dominator {
+            children[dominator].push(func);
+        }
+    }
+    let mut order = Vec::new();
+    let mut stack = vec![entry];
+    while let Some(node) = stack.pop() {
+        if node != entry {
+            order.push(node as u32);
+        }
+        stack.extend(children[node].iter().rev().cloned());
+    }
+
+    CallDominators {
+        idom: idom.into_iter()
+            .map(|d| d.and_then(|d| if d == entry { None } else { Some(d as u32) }))
+            .collect(),
+        order,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{self, Opcodes, Opcode, ExportEntry, Internal, DataSegment, InitExpr, Section, NameSection, NameMap};
+    use super::SizeProfile;
+
+    #[test]
+    fn retained_sizes() {
+        // 0 exported calls 1 and 2, 1 calls 3, 2 calls 3, 3 calls 4; 5 is unreachable
+        let calls: Vec<Vec<u32>> = vec![vec![1, 2], vec![3], vec![3], vec![4], vec![], vec![]];
+        let mut builder = module();
+        for callees in &calls {
+            let mut code: Vec<Opcode> = callees.iter().map(|&f| Opcode::Call(f)).collect();
+            code.push(Opcode::End);
+            builder = builder.function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(code)).build()
+                .build();
+        }
+        let mut module = builder
+            .with_export(ExportEntry::new("main".into(), Internal::Function(0)))
+            .with_data_segment(DataSegment::new(0, InitExpr::new(vec![Opcode::I32Const(0), Opcode::End]), vec![1, 2, 3]))
+            .build();
+        let mut names = NameSection::default();
+        *names.functions_mut() = Some(NameMap::new(vec![(4, "leaf".to_owned())].into_iter().collect()));
+        module.sections_mut().push(Section::Custom(names.into_custom().unwrap()));
+
+        let profile = SizeProfile::new(&module).expect("profiling to succeed");
+        assert_eq!(profile.total(), elements::serialize(module.clone()).unwrap().len());
+        assert_eq!(profile.total(), 8 + profile.sections().iter().map(|s| s.size).sum::<usize>());
+
+        let functions = profile.functions();
+        let size = |f: usize| functions[f].size;
+        let dominators: Vec<_> = functions.iter().map(|f| f.dominator).collect();
+        assert_eq!(dominators, vec![None, Some(0), Some(0), Some(0), Some(3), None]);
+        assert_eq!(functions[3].retained, size(3) + size(4));
+        assert_eq!(functions[1].retained, size(1));
+        assert_eq!(functions[0].retained, (0..5).map(size).sum::<usize>());
+        assert_eq!(functions[5].retained, size(5));
+        assert_eq!(functions[4].name, Some("leaf".to_owned()));
+
+        assert_eq!(profile.data().len(), 1);
+        assert_eq!(profile.custom().len(), 1);
+        assert_eq!(profile.custom()[0].name, "name");
+        let json = profile.to_json();
+        assert!(json.starts_with(&format!("{{\"total\":{},", profile.total())));
+        assert!(json.contains("\"name\":\"leaf\""));
+    }
+
+    #[test]
+    fn section_length_mismatch() {
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(0)))
+            .build();
+        for section in module.sections_mut() {
+            if let Section::Code(ref mut code_section) = *section {
+                code_section.bodies_mut().clear();
+            }
+        }
+
+        match SizeProfile::new(&module) {
+            Err(elements::Error::InconsistentLength { expected: 1, actual: 0 }) => {},
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! Structured (tree) representation of the function body.
+//!
+//! Blocks, loops and ifs own their bodies, and branches refer to the
+//! `Label` of their target block instead of a relative depth. Conversion
+//! from and back into `Opcodes` is lossless.
+
+use elements::{Opcode, Opcodes, BlockType, FuncBody};
+use super::Error;
+
+/// Label of the block, unique inside one `Tree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(pub u32);
+
+/// Node of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Plain opcode without block structure or branch target.
+    Opcode(Opcode),
+    /// `block ... end`
+    Block(Block),
+    /// `loop ... end`, branches to its label continue the loop.
+    Loop(Block),
+    /// `if ... else ... end`
+    If(If),
+    /// Unconditional branch.
+    Br(Label),
+    /// Conditional branch.
+    BrIf(Label),
+    /// Branch table with targets and default target.
+    BrTable(Vec<Label>, Label),
+}
+
+/// Block or loop with its body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    /// Label of the block.
+    pub label: Label,
+    /// Result type of the block.
+    pub block_type: BlockType,
+    /// Nodes of the block body.
+    pub body: Vec<Node>,
+}
+
+/// If with its branches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    /// Label of the block.
+    pub label: Label,
+    /// Result type of the block.
+    pub block_type: BlockType,
+    /// Nodes executed when the condition is non-zero.
+    pub then_body: Vec<Node>,
+    /// Nodes executed otherwise; `None` when there is no `Else`.
+    pub else_body: Option<Vec<Node>>,
+}
+
+/// Structured function body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    body: Vec<Node>,
+    next_label: u32,
+}
+
+/// Label of the implicit block of the function body itself.
+/// Branching to it is equivalent to `return`.
+pub const FUNCTION_LABEL: Label = Label(0);
+
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else(Vec<Node>),
+}
+
+struct Frame {
+    kind: FrameKind,
+    label: Label,
+    block_type: BlockType,
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// New tree with given nodes of the function body.
+    /// Labels allocated later with `fresh_label` do not clash with the labels used by the nodes.
+    pub fn new(body: Vec<Node>) -> Self {
+        let next_label = max_label(&body).unwrap_or(FUNCTION_LABEL).0 + 1;
+        Tree { body, next_label }
+    }
+
+    /// Build tree from the opcode sequence of the function body.
+    pub fn from_opcodes(opcodes: &Opcodes) -> Result<Self, Error> {
+        let mut tree = Tree::new(Vec::new());
+        let mut stack = vec![Frame {
+            kind: FrameKind::Function,
+            label: FUNCTION_LABEL,
+            block_type: BlockType::NoResult,
+            nodes: Vec::new(),
+        }];
+
+        let elements = opcodes.elements();
+        for (position, opcode) in elements.iter().enumerate() {
+            let resolve = |stack: &[Frame], depth: u32| {
+                stack.len().checked_sub(depth as usize + 1)
+                    .map(|index| stack[index].label)
+                    .ok_or(Error::InvalidBranchDepth { position, depth })
+            };
+
+            let node = match *opcode {
+                Opcode::Block(block_type) | Opcode::Loop(block_type) | Opcode::If(block_type) => {
+                    let kind = match *opcode {
+                        Opcode::Block(_) => FrameKind::Block,
+                        Opcode::Loop(_) => FrameKind::Loop,
+                        _ => FrameKind::If,
+                    };
+                    let label = tree.fresh_label();
+                    stack.push(Frame { kind, label, block_type, nodes: Vec::new() });
+                    continue;
+                },
+                Opcode::Else => {
+                    let frame = stack.last_mut().expect("function frame is never popped before the last opcode; qed");
+                    if let FrameKind::If = frame.kind {
+                        let then_body = ::std::mem::take(&mut frame.nodes);
+                        frame.kind = FrameKind::Else(then_body);
+                        continue;
+                    }
+                    return Err(Error::UnexpectedElse(position));
+                },
+                Opcode::End => {
+                    let frame = stack.pop().expect("function frame is never popped before the last opcode; qed");
+                    match frame.kind {
+                        FrameKind::Function => {
+                            if position + 1 != elements.len() {
+                                return Err(Error::UnexpectedEnd(position));
+                            }
+                            tree.body = frame.nodes;
+                            return Ok(tree);
+                        },
+                        FrameKind::Block => Node::Block(Block {
+                            label: frame.label,
+                            block_type: frame.block_type,
+                            body: frame.nodes,
+                        }),
+                        FrameKind::Loop => Node::Loop(Block {
+                            label: frame.label,
+                            block_type: frame.block_type,
+                            body: frame.nodes,
+                        }),
+                        FrameKind::If => Node::If(If {
+                            label: frame.label,
+                            block_type: frame.block_type,
+                            then_body: frame.nodes,
+                            else_body: None,
+                        }),
+                        FrameKind::Else(then_body) => Node::If(If {
+                            label: frame.label,
+                            block_type: frame.block_type,
+                            then_body,
+                            else_body: Some(frame.nodes),
+                        }),
+                    }
+                },
+                Opcode::Br(depth) => Node::Br(resolve(&stack, depth)?),
+                Opcode::BrIf(depth) => Node::BrIf(resolve(&stack, depth)?),
+                Opcode::BrTable(ref table, default) => {
+                    let targets = table.iter()
+                        .map(|depth| resolve(&stack, *depth))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Node::BrTable(targets, resolve(&stack, default)?)
+                },
+                ref opcode => Node::Opcode(opcode.clone()),
+            };
+
+            stack.last_mut()
+                .expect("function frame is never popped before the last opcode; qed")
+                .nodes
+                .push(node);
+        }
+
+        Err(Error::MissingEnd)
+    }
+
+    /// Build tree from the code of the function body.
+    pub fn from_func_body(body: &FuncBody) -> Result<Self, Error> {
+        Tree::from_opcodes(body.code())
+    }
+
+    /// Nodes of the function body.
+    pub fn body(&self) -> &[Node] { &self.body }
+
+    /// Nodes of the function body (mutable).
+    pub fn body_mut(&mut self) -> &mut Vec<Node> { &mut self.body }
+
+    /// Allocate new label, unique for this tree.
+    pub fn fresh_label(&mut self) -> Label {
+        let label = Label(self.next_label);
+        self.next_label += 1;
+        label
+    }
+
+    /// Convert tree back to the opcode sequence, including the final `End`.
+    pub fn to_opcodes(&self) -> Result<Opcodes, Error> {
+        let mut labels = vec![FUNCTION_LABEL];
+        let mut result = Vec::new();
+        emit(&self.body, &mut labels, &mut result)?;
+        result.push(Opcode::End);
+        Ok(Opcodes::new(result))
+    }
+}
+
+fn max_label(nodes: &[Node]) -> Option<Label> {
+    nodes.iter().filter_map(|node| match *node {
+        Node::Opcode(_) => None,
+        Node::Block(ref block) | Node::Loop(ref block) => Some(block.label).max(max_label(&block.body)),
+        Node::If(ref if_) => Some(if_.label)
+            .max(max_label(&if_.then_body))
+            .max(if_.else_body.as_ref().and_then(|body| max_label(body))),
+        Node::Br(label) | Node::BrIf(label) => Some(label),
+        Node::BrTable(ref table, default) => table.iter().cloned().max().max(Some(default)),
+    }).max()
+}
+
+fn depth_of(labels: &[Label], label: Label) -> Result<u32, Error> {
+    labels.iter()
+        .rev()
+        .position(|l| *l == label)
+        .map(|depth| depth as u32)
+        .ok_or(Error::UnresolvedLabel(label))
+}
+
+fn emit(nodes: &[Node], labels: &mut Vec<Label>, result: &mut Vec<Opcode>) -> Result<(), Error> {
+    for node in nodes {
+        match *node {
+            Node::Opcode(ref opcode) => {
+                match *opcode {
+                    Opcode::Block(_) | Opcode::Loop(_) | Opcode::If(_) | Opcode::Else | Opcode::End |
+                    Opcode::Br(_) | Opcode::BrIf(_) | Opcode::BrTable(_, _) =>
+                        return Err(Error::StructuralOpcode(opcode.clone())),
+                    _ => result.push(opcode.clone()),
+                }
+            },
+            Node::Block(ref block) => {
+                result.push(Opcode::Block(block.block_type));
+                emit_scoped(block.label, &block.body, labels, result)?;
+                result.push(Opcode::End);
+            },
+            Node::Loop(ref block) => {
+                result.push(Opcode::Loop(block.block_type));
+                emit_scoped(block.label, &block.body, labels, result)?;
+                result.push(Opcode::End);
+            },
+            Node::If(ref if_) => {
+                result.push(Opcode::If(if_.block_type));
+                emit_scoped(if_.label, &if_.then_body, labels, result)?;
+                if let Some(ref else_body) = if_.else_body {
+                    result.push(Opcode::Else);
+                    emit_scoped(if_.label, else_body, labels, result)?;
+                }
+                result.push(Opcode::End);
+            },
+            Node::Br(label) => result.push(Opcode::Br(depth_of(labels, label)?)),
+            Node::BrIf(label) => result.push(Opcode::BrIf(depth_of(labels, label)?)),
+            Node::BrTable(ref table, default) => {
+                let table = table.iter()
+                    .map(|label| depth_of(labels, *label))
+                    .collect::<Result<Vec<_>, _>>()?;
+                result.push(Opcode::BrTable(table, depth_of(labels, default)?));
+            },
+        }
+    }
+    Ok(())
+}
+
+fn emit_scoped(label: Label, nodes: &[Node], labels: &mut Vec<Label>, result: &mut Vec<Opcode>) -> Result<(), Error> {
+    labels.push(label);
+    let emitted = emit(nodes, labels, result);
+    labels.pop();
+    emitted
+}
+
+#[cfg(test)]
+mod tests {
+
+    use elements::{Opcode, Opcodes, BlockType, ValueType, deserialize_file};
+    use analysis::Error;
+    use super::{Tree, Node, Label, FUNCTION_LABEL};
+
+    #[test]
+    fn nested() {
+        use elements::Opcode::*;
+
+        let opcodes = Opcodes::new(vec![
+            Block(BlockType::NoResult),
+                Loop(BlockType::NoResult),
+                    GetLocal(0),
+                    BrIf(1),
+                    GetLocal(0),
+                    If(BlockType::Value(ValueType::I32)),
+                        I32Const(1),
+                    Else,
+                        I32Const(2),
+                        Br(3),
+                    End,
+                    Drop,
+                    Br(0),
+                End,
+            End,
+            End,
+        ]);
+
+        let tree = Tree::from_opcodes(&opcodes).expect("tree to be built");
+        assert_eq!(tree.body().len(), 1);
+
+        let outer = match tree.body()[0] {
+            Node::Block(ref block) => block,
+            _ => panic!("Should be block"),
+        };
+        let inner = match outer.body[0] {
+            Node::Loop(ref block) => block,
+            _ => panic!("Should be loop"),
+        };
+        assert_eq!(inner.body[1], Node::BrIf(outer.label));
+        assert_eq!(inner.body[5], Node::Br(inner.label));
+        match inner.body[3] {
+            Node::If(ref if_) => {
+                assert_eq!(if_.then_body, vec![Node::Opcode(I32Const(1))]);
+                let else_body = if_.else_body.as_ref().expect("else to exist");
+                assert_eq!(else_body[1], Node::Br(FUNCTION_LABEL));
+            },
+            _ => panic!("Should be if"),
+        }
+
+        assert_eq!(tree.to_opcodes().expect("opcodes to be emitted"), opcodes);
+    }
+
+    #[test]
+    fn wrap_in_block() {
+        use elements::Opcode::*;
+
+        let opcodes = Opcodes::new(vec![
+            Block(BlockType::NoResult),
+                Br(0),
+            End,
+            End,
+        ]);
+        let mut tree = Tree::from_opcodes(&opcodes).expect("tree to be built");
+
+        // wrap the whole body into the new block, branch depth should be adjusted
+        let label = tree.fresh_label();
+        let body = ::std::mem::take(tree.body_mut());
+        tree.body_mut().push(Node::Block(super::Block { label, block_type: BlockType::NoResult, body }));
+
+        assert_eq!(
+            tree.to_opcodes().expect("opcodes to be emitted").elements(),
+            &[Block(BlockType::NoResult), Block(BlockType::NoResult), Br(0), End, End, End]
+        );
+    }
+
+    #[test]
+    fn fresh_label_after_supplied_labels() {
+        let inner = super::Block { label: Label(7), block_type: BlockType::NoResult, body: vec![Node::Br(Label(3))] };
+        let mut tree = Tree::new(vec![Node::Block(super::Block {
+            label: Label(3),
+            block_type: BlockType::NoResult,
+            body: vec![Node::Loop(inner)],
+        })]);
+
+        assert_eq!(tree.fresh_label(), Label(8));
+    }
+
+    #[test]
+    fn errors() {
+        use elements::Opcode::*;
+
+        let cases = vec![
+            (vec![Else, End], Error::UnexpectedElse(0)),
+            (vec![Nop, End, Nop, End], Error::UnexpectedEnd(1)),
+            (vec![Block(BlockType::NoResult), End], Error::MissingEnd),
+            (vec![Br(1), End], Error::InvalidBranchDepth { position: 0, depth: 1 }),
+        ];
+
+        for (opcodes, error) in cases {
+            assert_eq!(Tree::from_opcodes(&Opcodes::new(opcodes)), Err(error));
+        }
+
+        let tree = Tree::new(vec![Node::Br(Label(5))]);
+        assert_eq!(tree.to_opcodes(), Err(Error::UnresolvedLabel(Label(5))));
+
+        let tree = Tree::new(vec![Node::Opcode(Opcode::Br(0))]);
+        assert_eq!(tree.to_opcodes(), Err(Error::StructuralOpcode(Opcode::Br(0))));
+    }
+
+    #[test]
+    fn roundtrip_cases() {
+        for case in &["test5.wasm", "test6.wasm", "ifelse.wasm", "accumulate_u8.wasm", "hello.wasm"] {
+            let module = deserialize_file(format!("./res/cases/v1/{}", case)).expect("Should be deserialized");
+            for body in module.code_section().expect("code section to exist").bodies() {
+                let tree = Tree::from_func_body(body).expect("tree to be built");
+                assert_eq!(&tree.to_opcodes().expect("opcodes to be emitted"), body.code());
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use alloc::vec::Vec;
 use elements;
 use super::invoke::{Invoke, Identity};
 use super::misc::{ValueTypeBuilder, ValueTypesBuilder, OptionalValueTypeBuilder};
+use super::emit::{Emitter, EmitError};
 
 pub enum Signature {
     TypeReference(u32),
@@ -190,6 +192,7 @@ impl<F> SignaturesBuilder<F> where F: Invoke<SignatureBindings> {
 pub struct FuncBodyBuilder<F=Identity> {
     callback: F,
     body: elements::FuncBody,
+    signature: Option<elements::FunctionType>,
 }
 
 impl<F> FuncBodyBuilder<F> {
@@ -197,6 +200,7 @@ impl<F> FuncBodyBuilder<F> {
         FuncBodyBuilder {
             callback: callback,
             body: elements::FuncBody::new(Vec::new(), elements::Opcodes::empty()),
+            signature: None,
         }
     }
 }
@@ -217,6 +221,38 @@ impl<F> FuncBodyBuilder<F> where F: Invoke<elements::FuncBody> {
         self
     }
 
+    /// Signature checked by `emit`, known already if the function signature is inline
+    pub fn with_signature(mut self, signature: elements::FunctionType) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Emit the code with the typed emitter, replacing the opcodes and
+    /// appending the allocated locals to the ones already declared.
+    ///
+    /// The first mistake in the emitted code is passed to the callback and,
+    /// for the function of the module, reported by `ModuleBuilder::try_build`.
+    /// Signature given by the type reference is resolved by the module builder;
+    /// if it is still unknown, nothing is emitted and this is reported as the mistake.
+    pub fn emit<C: FnOnce(&mut Emitter)>(mut self, code: C) -> Self
+        where F: Invoke<EmitError, Result=F>
+    {
+        let mut emitter = match self.signature {
+            Some(ref signature) => Emitter::with_locals(signature, self.body.locals()),
+            None => {
+                self.callback = self.callback.invoke(EmitError::unknown_signature());
+                return self;
+            },
+        };
+        code(&mut emitter);
+        let (body, error) = emitter.into_body();
+        self.body = body;
+        if let Some(error) = error {
+            self.callback = self.callback.invoke(error);
+        }
+        self
+    }
+
     pub fn build(self) -> F::Result {
         self.callback.invoke(self.body)
     }
@@ -226,6 +262,8 @@ pub struct FunctionDefinition {
     pub is_main: bool,
     pub signature: Signature,
     pub code: elements::FuncBody,
+    /// First mistake in the code emitted for the body, if any
+    pub emit_error: Option<EmitError>,
 }
 
 impl Default for FunctionDefinition {
@@ -234,6 +272,7 @@ impl Default for FunctionDefinition {
             is_main: false,
             signature: Signature::TypeReference(0),
             code: elements::FuncBody::empty(),
+            emit_error: None,
         }
     }
 }
@@ -241,6 +280,7 @@ impl Default for FunctionDefinition {
 pub struct FunctionBuilder<F=Identity> {
     callback: F,
     func: FunctionDefinition,
+    types: Vec<elements::FunctionType>,
 }
 
 impl FunctionBuilder {
@@ -254,9 +294,16 @@ impl<F> FunctionBuilder<F> where F: Invoke<FunctionDefinition> {
         FunctionBuilder {
             callback: callback,
             func: Default::default(),
+            types: Vec::new(),
         }
     }
 
+    /// Types of the module, used to resolve the signature given by the type reference
+    pub(crate) fn with_types(mut self, types: Vec<elements::FunctionType>) -> Self {
+        self.types = types;
+        self
+    }
+
     pub fn main(mut self) -> Self {
         self.func.is_main = true;
         self
@@ -272,7 +319,15 @@ impl<F> FunctionBuilder<F> where F: Invoke<FunctionDefinition> {
     }
 
     pub fn body(self) -> FuncBodyBuilder<Self> {
-        FuncBodyBuilder::with_callback(self)
+        let signature = match self.func.signature {
+            Signature::Inline(ref signature) => Some(signature.clone()),
+            Signature::TypeReference(type_ref) => self.types.get(type_ref as usize).cloned(),
+        };
+        let builder = FuncBodyBuilder::with_callback(self);
+        match signature {
+            Some(signature) => builder.with_signature(signature),
+            None => builder,
+        }
     }
 
     pub fn with_body(mut self, body: elements::FuncBody) -> Self {
@@ -309,6 +364,17 @@ impl<F> Invoke<elements::FuncBody> for FunctionBuilder<F> where F: Invoke<Functi
     }
 }
 
+impl<F> Invoke<EmitError> for FunctionBuilder<F> where F: Invoke<FunctionDefinition> {
+    type Result = Self;
+
+    fn invoke(mut self, error: EmitError) -> Self::Result {
+        if self.func.emit_error.is_none() {
+            self.func.emit_error = Some(error);
+        }
+        self
+    }
+}
+
 /// New builder of signature list
 pub fn signatures() -> SignaturesBuilder {
     SignaturesBuilder::new()
@@ -327,6 +393,7 @@ pub fn function() -> FunctionBuilder {
 #[cfg(test)]
 mod tests {
 
+    use alloc::{vec::Vec, borrow::ToOwned};
     use super::{signatures, function};
     use elements;
 
@@ -364,4 +431,88 @@ mod tests {
         assert_eq!(func.code.locals().len(), 0);
         assert_eq!(func.code.code().elements().len(), 1);
     }
+
+    #[test]
+    fn emit_example() {
+        let func = function()
+            .signature()
+                .param().i32()
+                .return_type().i32()
+                .build()
+            .body()
+                .emit(|e| {
+                    let x = e.param(0);
+                    let doubled = e.local(elements::ValueType::I32);
+                    e.get_local(x).i32_const(2).i32_mul().tee_local(doubled);
+                })
+                .build()
+            .build();
+
+        assert_eq!(func.code.locals(), &[elements::Local::new(1, elements::ValueType::I32)][..]);
+        assert_eq!(func.code.code().elements(), &[
+            elements::Opcode::GetLocal(0),
+            elements::Opcode::I32Const(2),
+            elements::Opcode::I32Mul,
+            elements::Opcode::TeeLocal(1),
+            elements::Opcode::End,
+        ][..]);
+    }
+
+    #[test]
+    fn emit_mistake() {
+        let func = function()
+            .signature().return_type().i64().build()
+            .body()
+                .emit(|e| { e.i32_const(1); })
+                .build()
+            .build();
+
+        assert_eq!(func.emit_error.map(|err| err.position()), Some(1));
+    }
+
+    #[test]
+    fn emit_by_type_reference() {
+        use elements::{FunctionType, Opcode, ValueType};
+        use super::Signature;
+
+        let func = function()
+            .with_signature(Signature::TypeReference(0))
+            .body()
+                .emit(|e| { e.i32_const(1); })
+                .build()
+            .build();
+        assert_eq!(func.emit_error.map(|err| err.message().to_owned()),
+            Some("Signature of the function body is unknown".to_owned()));
+        assert_eq!(func.code.code().elements(), &[Opcode::End][..]);
+
+        let func = function()
+            .with_signature(Signature::TypeReference(0))
+            .body()
+                .with_signature(FunctionType::new(Vec::new(), Some(ValueType::I32)))
+                .emit(|e| { e.i32_const(1); })
+                .build()
+            .build();
+        assert!(func.emit_error.is_none());
+        assert_eq!(func.code.code().elements(), &[Opcode::I32Const(1), Opcode::End][..]);
+    }
+
+    #[test]
+    fn emit_after_declared_locals() {
+        use elements::{Local, Opcode, ValueType};
+
+        let func = function()
+            .signature().param().i32().return_type().i64().build()
+            .body()
+                .with_locals(vec![Local::new(1, ValueType::I32)])
+                .emit(|e| {
+                    let l = e.local(ValueType::I64);
+                    e.i64_const(5).tee_local(l);
+                })
+                .build()
+            .build();
+
+        assert!(func.emit_error.is_none());
+        assert_eq!(func.code.locals(), &[Local::new(1, ValueType::I32), Local::new(1, ValueType::I64)][..]);
+        assert_eq!(func.code.code().elements(), &[Opcode::I64Const(5), Opcode::TeeLocal(2), Opcode::End][..]);
+    }
 }
\ No newline at end of file
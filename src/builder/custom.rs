@@ -0,0 +1,142 @@
+use alloc::{vec::Vec, string::String};
+use elements;
+use super::invoke::{Invoke, Identity};
+
+/// Kind of the known section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Type section
+    Type,
+    /// Import section
+    Import,
+    /// Function section
+    Function,
+    /// Table section
+    Table,
+    /// Memory section
+    Memory,
+    /// Global section
+    Global,
+    /// Export section
+    Export,
+    /// Start section
+    Start,
+    /// Element section
+    Element,
+    /// Code section
+    Code,
+    /// Data section
+    Data,
+}
+
+impl SectionKind {
+    /// Kind of the known section, `None` for custom and unparsed sections
+    pub fn of(section: &elements::Section) -> Option<SectionKind> {
+        match *section {
+            elements::Section::Type(_) => Some(SectionKind::Type),
+            elements::Section::Import(_) => Some(SectionKind::Import),
+            elements::Section::Function(_) => Some(SectionKind::Function),
+            elements::Section::Table(_) => Some(SectionKind::Table),
+            elements::Section::Memory(_) => Some(SectionKind::Memory),
+            elements::Section::Global(_) => Some(SectionKind::Global),
+            elements::Section::Export(_) => Some(SectionKind::Export),
+            elements::Section::Start(_) => Some(SectionKind::Start),
+            elements::Section::Element(_) => Some(SectionKind::Element),
+            elements::Section::Code(_) => Some(SectionKind::Code),
+            elements::Section::Data(_) => Some(SectionKind::Data),
+            elements::Section::Custom(_) | elements::Section::Unparsed { .. } => None,
+        }
+    }
+}
+
+/// Place of the additional section in the built module
+///
+/// If the referenced section is not present in the module, the additional
+/// section is placed where it would have been.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Before the section of the kind
+    Before(SectionKind),
+    /// After the section of the kind
+    After(SectionKind),
+    /// After all sections
+    End,
+}
+
+impl Placement {
+    /// Sorting key of the section at this place among the known sections
+    pub(crate) fn key(&self) -> u32 {
+        match *self {
+            Placement::Before(kind) => kind as u32 * 3,
+            Placement::After(kind) => kind as u32 * 3 + 2,
+            Placement::End => SectionKind::Data as u32 * 3 + 3,
+        }
+    }
+}
+
+/// Custom section with its place in the module
+pub struct CustomSectionDefinition {
+    /// Place of the section
+    pub placement: Placement,
+    /// The section
+    pub section: elements::CustomSection,
+}
+
+/// Custom section builder
+pub struct CustomSectionBuilder<F=Identity> {
+    callback: F,
+    name: String,
+    payload: Vec<u8>,
+    placement: Placement,
+}
+
+impl CustomSectionBuilder {
+    /// New custom section builder
+    pub fn new() -> Self {
+        CustomSectionBuilder::with_callback(Identity)
+    }
+}
+
+impl<F> CustomSectionBuilder<F> where F: Invoke<CustomSectionDefinition> {
+    /// New custom section builder with bound callback
+    pub fn with_callback(callback: F) -> Self {
+        CustomSectionBuilder {
+            callback,
+            name: String::new(),
+            payload: Vec::new(),
+            placement: Placement::End,
+        }
+    }
+
+    /// Name of the section
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Payload of the section
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Place the section before the section of the kind
+    pub fn before(mut self, kind: SectionKind) -> Self {
+        self.placement = Placement::Before(kind);
+        self
+    }
+
+    /// Place the section after the section of the kind
+    pub fn after(mut self, kind: SectionKind) -> Self {
+        self.placement = Placement::After(kind);
+        self
+    }
+
+    /// Finish the section
+    pub fn build(self) -> F::Result {
+        self.callback.invoke(CustomSectionDefinition {
+            placement: self.placement,
+            section: elements::CustomSection::new(self.name, self.payload),
+        })
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use super::invoke::{Identity, Invoke};
 use elements;
 
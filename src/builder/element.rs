@@ -0,0 +1,72 @@
+use alloc::vec::Vec;
+use super::invoke::{Identity, Invoke};
+use elements;
+
+/// Element segment builder
+pub struct ElementSegmentBuilder<F=Identity> {
+    callback: F,
+    table_index: u32,
+    offset: elements::InitExpr,
+    members: Vec<u32>,
+}
+
+impl ElementSegmentBuilder {
+    /// New element segment builder
+    pub fn new() -> Self {
+        ElementSegmentBuilder::with_callback(Identity)
+    }
+}
+
+impl<F> ElementSegmentBuilder<F> {
+    /// New element segment builder with bound callback
+    pub fn with_callback(callback: F) -> Self {
+        ElementSegmentBuilder {
+            callback,
+            table_index: 0,
+            offset: elements::InitExpr::new(vec![elements::Opcode::I32Const(0), elements::Opcode::End]),
+            members: Vec::new(),
+        }
+    }
+
+    /// Table the segment initializes
+    pub fn table_index(mut self, index: u32) -> Self {
+        self.table_index = index;
+        self
+    }
+
+    /// Offset computed by the constant opcode, `i32.const` or `get_global` of the imported global
+    pub fn offset(mut self, opcode: elements::Opcode) -> Self {
+        self.offset = elements::InitExpr::new(vec![opcode, elements::Opcode::End]);
+        self
+    }
+
+    /// Offset given by the imported global
+    pub fn offset_global(self, index: u32) -> Self {
+        self.offset(elements::Opcode::GetGlobal(index))
+    }
+
+    /// Functions placed into the table
+    pub fn members(mut self, members: Vec<u32>) -> Self {
+        self.members = members;
+        self
+    }
+
+    /// Function placed into the table after the previous ones
+    pub fn member(mut self, function: u32) -> Self {
+        self.members.push(function);
+        self
+    }
+}
+
+impl<F> ElementSegmentBuilder<F> where F: Invoke<elements::ElementSegment> {
+    /// Finish the segment
+    pub fn build(self) -> F::Result {
+        self.callback.invoke(
+            elements::ElementSegment::new(
+                self.table_index,
+                self.offset,
+                self.members,
+            )
+        )
+    }
+}
@@ -0,0 +1,797 @@
+//! Typed instruction emitter.
+
+use alloc::{vec::Vec, string::String};
+use std::fmt;
+use elements::{self, Opcode, Opcodes, BlockType, ValueType, FunctionType, FuncBody};
+
+/// Handle of the parameter or local of the function being emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Local(u32);
+
+impl Local {
+    /// Index of the local, parameters first.
+    pub fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Handle of the block, loop or `if` to branch to.
+///
+/// Branching to the label is only valid inside the closure it was passed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// Mistake in the emitted code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitError {
+    position: usize,
+    message: String,
+}
+
+impl EmitError {
+    /// Position of the offending opcode in the function body.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Description of the mistake.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Code can not be emitted without the signature of the function.
+    pub(crate) fn unknown_signature() -> Self {
+        EmitError { position: 0, message: "Signature of the function body is unknown".into() }
+    }
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "At opcode {}: {}", self.position, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    label: usize,
+    result: Option<ValueType>,
+    height: usize,
+    unreachable: bool,
+}
+
+impl Frame {
+    /// Type of the values taken by the branch to this frame.
+    fn branch_type(&self) -> Option<ValueType> {
+        if self.kind == FrameKind::Loop { None } else { self.result }
+    }
+}
+
+/// Emitter of the function body checking the stack types of every opcode.
+///
+/// Mistakes do not stop the emission; the first one is reported by `finish`.
+#[derive(Debug)]
+pub struct Emitter {
+    locals: Vec<ValueType>,
+    params: usize,
+    code: Vec<Opcode>,
+    /// Operand stack, `None` for values of unknown type left by the unreachable code.
+    stack: Vec<Option<ValueType>>,
+    frames: Vec<Frame>,
+    labels: usize,
+    error: Option<EmitError>,
+}
+
+macro_rules! operators {
+    ($($name:ident: $opcode:ident ($($param:ident),*) -> $result:ident;)*) => {
+        $(
+            #[doc = concat!("Emit `", stringify!($opcode), "`.")]
+            pub fn $name(&mut self) -> &mut Self {
+                self.operator(Opcode::$opcode, &[$(ValueType::$param),*], Some(ValueType::$result))
+            }
+        )*
+    }
+}
+
+macro_rules! loads {
+    ($($name:ident: $opcode:ident ($size:expr) -> $result:ident;)*) => {
+        $(
+            #[doc = concat!("Emit `", stringify!($opcode), "` with the alignment exponent and the offset.")]
+            pub fn $name(&mut self, align: u32, offset: u32) -> &mut Self {
+                self.check_align(align, $size);
+                self.operator(Opcode::$opcode(align, offset), &[ValueType::I32], Some(ValueType::$result))
+            }
+        )*
+    }
+}
+
+macro_rules! stores {
+    ($($name:ident: $opcode:ident ($size:expr, $value:ident);)*) => {
+        $(
+            #[doc = concat!("Emit `", stringify!($opcode), "` with the alignment exponent and the offset.")]
+            pub fn $name(&mut self, align: u32, offset: u32) -> &mut Self {
+                self.check_align(align, $size);
+                self.operator(Opcode::$opcode(align, offset), &[ValueType::I32, ValueType::$value], None)
+            }
+        )*
+    }
+}
+
+impl Emitter {
+    /// New emitter of the body of the function with `signature`.
+    pub fn new(signature: &FunctionType) -> Self {
+        Emitter::with_locals(signature, &[])
+    }
+
+    /// New emitter of the body of the function with `signature` and the
+    /// already declared `locals`, which are kept in the finished body.
+    pub fn with_locals(signature: &FunctionType, locals: &[elements::Local]) -> Self {
+        let declared = locals.iter().flat_map(|local| (0..local.count()).map(move |_| local.value_type()));
+        Emitter {
+            locals: signature.params().iter().cloned().chain(declared).collect(),
+            params: signature.params().len(),
+            code: Vec::new(),
+            stack: Vec::new(),
+            frames: vec![Frame {
+                kind: FrameKind::Function,
+                label: 0,
+                result: signature.return_type(),
+                height: 0,
+                unreachable: false,
+            }],
+            labels: 1,
+            error: None,
+        }
+    }
+
+    /// Handle of the parameter `index`.
+    pub fn param(&mut self, index: u32) -> Local {
+        if index as usize >= self.params {
+            self.fail(format!("Function has no parameter {}", index));
+        }
+        Local(index)
+    }
+
+    /// Allocate the new local of `value_type`.
+    pub fn local(&mut self, value_type: ValueType) -> Local {
+        self.locals.push(value_type);
+        Local(self.locals.len() as u32 - 1)
+    }
+
+    /// Finish the body, checking the value left for the function result.
+    pub fn finish(self) -> Result<FuncBody, EmitError> {
+        match self.into_body() {
+            (body, None) => Ok(body),
+            (_, Some(error)) => Err(error),
+        }
+    }
+
+    /// Finish the body, returning it together with the first mistake, if any.
+    pub(crate) fn into_body(mut self) -> (FuncBody, Option<EmitError>) {
+        self.end_frame();
+        self.code.push(Opcode::End);
+
+        let mut locals: Vec<elements::Local> = Vec::new();
+        for &value_type in &self.locals[self.params..] {
+            match locals.last_mut() {
+                Some(last) if last.value_type() == value_type => {
+                    *last = elements::Local::new(last.count() + 1, value_type);
+                },
+                _ => locals.push(elements::Local::new(1, value_type)),
+            }
+        }
+        (FuncBody::new(locals, Opcodes::new(self.code)), self.error)
+    }
+
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(EmitError { position: self.code.len(), message });
+        }
+    }
+
+    fn push(&mut self, value_type: Option<ValueType>) {
+        self.stack.push(value_type);
+    }
+
+    fn pop(&mut self, expected: Option<ValueType>) -> Option<ValueType> {
+        let frame = self.frames.last().expect("function frame is never popped before finish");
+        if self.stack.len() == frame.height {
+            if !frame.unreachable {
+                self.fail(format!("Expected {} on the stack, but it is empty", describe(expected)));
+            }
+            return expected;
+        }
+        let actual = self.stack.pop().expect("stack is higher than the frame");
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if actual != expected => {
+                self.fail(format!("Expected {:?} on the stack, found {:?}", expected, actual));
+                Some(expected)
+            },
+            (None, expected) => expected,
+            (actual, _) => actual,
+        }
+    }
+
+    fn operator(&mut self, opcode: Opcode, params: &[ValueType], result: Option<ValueType>) -> &mut Self {
+        for &param in params.iter().rev() {
+            self.pop(Some(param));
+        }
+        self.code.push(opcode);
+        if result.is_some() {
+            self.push(result);
+        }
+        self
+    }
+
+    fn check_align(&mut self, align: u32, size: u32) {
+        if align >= 32 || 1u32 << align > size {
+            self.fail(format!("Alignment 2^{} is larger than the access size {}", align, size));
+        }
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("function frame is never popped before finish");
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn frame(&mut self, label: Label) -> Option<(u32, Option<ValueType>)> {
+        match self.frames.iter().rposition(|frame| frame.label == label.0) {
+            Some(index) => Some(((self.frames.len() - 1 - index) as u32, self.frames[index].branch_type())),
+            None => {
+                self.fail("Label is not in scope".into());
+                None
+            },
+        }
+    }
+
+    /// Check the value left for the result of the innermost frame and pop it.
+    fn end_frame(&mut self) -> Frame {
+        let result = self.frames.last().expect("frame to end").result;
+        if result.is_some() {
+            self.pop(result);
+        }
+        let frame = self.frames.pop().expect("frame to end");
+        if self.stack.len() > frame.height {
+            self.fail(format!("{} values are left on the stack at the end of the block", self.stack.len() - frame.height));
+            self.stack.truncate(frame.height);
+        }
+        frame
+    }
+
+    fn structured<C: FnOnce(&mut Emitter, Label)>(&mut self, kind: FrameKind, block_type: BlockType, code: C) {
+        let result = match block_type {
+            BlockType::Value(value_type) => Some(value_type),
+            BlockType::NoResult => None,
+        };
+        let label = Label(self.labels);
+        self.labels += 1;
+        self.frames.push(Frame { kind, label: label.0, result, height: self.stack.len(), unreachable: false });
+        code(self, label);
+    }
+
+    /// Emit `block` with the code emitted by `code` and the closing `end`.
+    pub fn block<C: FnOnce(&mut Emitter, Label)>(&mut self, block_type: BlockType, code: C) -> &mut Self {
+        self.code.push(Opcode::Block(block_type));
+        self.structured(FrameKind::Block, block_type, code);
+        self.end_frame();
+        self.code.push(Opcode::End);
+        self.push_block_result(block_type);
+        self
+    }
+
+    /// Emit `loop` with the code emitted by `code` and the closing `end`.
+    ///
+    /// Branches to the label of the loop continue with its next iteration.
+    pub fn loop_<C: FnOnce(&mut Emitter, Label)>(&mut self, block_type: BlockType, code: C) -> &mut Self {
+        self.code.push(Opcode::Loop(block_type));
+        self.structured(FrameKind::Loop, block_type, code);
+        self.end_frame();
+        self.code.push(Opcode::End);
+        self.push_block_result(block_type);
+        self
+    }
+
+    /// Emit `if` without `else` taking the condition from the stack.
+    pub fn if_<C: FnOnce(&mut Emitter, Label)>(&mut self, block_type: BlockType, then: C) -> &mut Self {
+        if block_type != BlockType::NoResult {
+            self.fail("If without else can not have the result".into());
+        }
+        self.pop(Some(ValueType::I32));
+        self.code.push(Opcode::If(block_type));
+        self.structured(FrameKind::If, block_type, then);
+        self.end_frame();
+        self.code.push(Opcode::End);
+        self
+    }
+
+    /// Emit `if` with `else` taking the condition from the stack.
+    pub fn if_else<T, E>(&mut self, block_type: BlockType, then: T, otherwise: E) -> &mut Self
+        where T: FnOnce(&mut Emitter, Label), E: FnOnce(&mut Emitter, Label)
+    {
+        self.pop(Some(ValueType::I32));
+        self.code.push(Opcode::If(block_type));
+        self.structured(FrameKind::If, block_type, then);
+        self.end_frame();
+        self.code.push(Opcode::Else);
+        self.structured(FrameKind::Else, block_type, otherwise);
+        self.end_frame();
+        self.code.push(Opcode::End);
+        self.push_block_result(block_type);
+        self
+    }
+
+    fn push_block_result(&mut self, block_type: BlockType) {
+        if let BlockType::Value(value_type) = block_type {
+            self.push(Some(value_type));
+        }
+    }
+
+    /// Emit `br` to the label.
+    pub fn br(&mut self, label: Label) -> &mut Self {
+        if let Some((depth, branch_type)) = self.frame(label) {
+            if branch_type.is_some() {
+                self.pop(branch_type);
+            }
+            self.code.push(Opcode::Br(depth));
+        }
+        self.set_unreachable();
+        self
+    }
+
+    /// Emit `br_if` to the label taking the condition from the stack.
+    pub fn br_if(&mut self, label: Label) -> &mut Self {
+        self.pop(Some(ValueType::I32));
+        if let Some((depth, branch_type)) = self.frame(label) {
+            if branch_type.is_some() {
+                let value = self.pop(branch_type);
+                self.push(value);
+            }
+            self.code.push(Opcode::BrIf(depth));
+        }
+        self
+    }
+
+    /// Emit `br_table` to the labels taking the index from the stack.
+    pub fn br_table(&mut self, labels: &[Label], default: Label) -> &mut Self {
+        self.pop(Some(ValueType::I32));
+        let mut depths = Vec::new();
+        let default = self.frame(default);
+        for &label in labels {
+            if let Some((depth, branch_type)) = self.frame(label) {
+                if let Some((_, default_type)) = default {
+                    if branch_type != default_type {
+                        self.fail("Labels of br_table have different types".into());
+                    }
+                }
+                depths.push(depth);
+            }
+        }
+        if let Some((default, branch_type)) = default {
+            if branch_type.is_some() {
+                self.pop(branch_type);
+            }
+            self.code.push(Opcode::BrTable(depths, default));
+        }
+        self.set_unreachable();
+        self
+    }
+
+    /// Emit `return` taking the function result from the stack.
+    pub fn return_(&mut self) -> &mut Self {
+        let result = self.frames[0].result;
+        if result.is_some() {
+            self.pop(result);
+        }
+        self.code.push(Opcode::Return);
+        self.set_unreachable();
+        self
+    }
+
+    /// Emit `unreachable`.
+    pub fn unreachable(&mut self) -> &mut Self {
+        self.code.push(Opcode::Unreachable);
+        self.set_unreachable();
+        self
+    }
+
+    /// Emit `nop`.
+    pub fn nop(&mut self) -> &mut Self {
+        self.code.push(Opcode::Nop);
+        self
+    }
+
+    /// Emit `drop`.
+    pub fn drop(&mut self) -> &mut Self {
+        self.pop(None);
+        self.code.push(Opcode::Drop);
+        self
+    }
+
+    /// Emit `select` choosing between two values of the same type.
+    pub fn select(&mut self) -> &mut Self {
+        self.pop(Some(ValueType::I32));
+        let second = self.pop(None);
+        let first = self.pop(second);
+        self.code.push(Opcode::Select);
+        self.push(first.or(second));
+        self
+    }
+
+    /// Emit `call` of the function `index` with `signature`.
+    pub fn call(&mut self, index: u32, signature: &FunctionType) -> &mut Self {
+        self.operator(Opcode::Call(index), signature.params(), signature.return_type())
+    }
+
+    /// Emit `call_indirect` of the type `type_ref` being `signature`, taking the table index from the stack.
+    pub fn call_indirect(&mut self, type_ref: u32, signature: &FunctionType) -> &mut Self {
+        self.pop(Some(ValueType::I32));
+        self.operator(Opcode::CallIndirect(type_ref, false), signature.params(), signature.return_type())
+    }
+
+    fn local_type(&mut self, local: Local) -> Option<ValueType> {
+        match self.locals.get(local.0 as usize) {
+            Some(&value_type) => Some(value_type),
+            None => {
+                self.fail(format!("Function has no local {}", local.0));
+                None
+            },
+        }
+    }
+
+    /// Emit `get_local`.
+    pub fn get_local(&mut self, local: Local) -> &mut Self {
+        let value_type = self.local_type(local);
+        self.code.push(Opcode::GetLocal(local.0));
+        self.push(value_type);
+        self
+    }
+
+    /// Emit `set_local`.
+    pub fn set_local(&mut self, local: Local) -> &mut Self {
+        let value_type = self.local_type(local);
+        self.pop(value_type);
+        self.code.push(Opcode::SetLocal(local.0));
+        self
+    }
+
+    /// Emit `tee_local`.
+    pub fn tee_local(&mut self, local: Local) -> &mut Self {
+        let value_type = self.local_type(local);
+        let value = self.pop(value_type);
+        self.code.push(Opcode::TeeLocal(local.0));
+        self.push(value);
+        self
+    }
+
+    /// Emit `get_global` of the global `index` holding `value_type`.
+    pub fn get_global(&mut self, index: u32, value_type: ValueType) -> &mut Self {
+        self.operator(Opcode::GetGlobal(index), &[], Some(value_type))
+    }
+
+    /// Emit `set_global` of the global `index` holding `value_type`.
+    pub fn set_global(&mut self, index: u32, value_type: ValueType) -> &mut Self {
+        self.operator(Opcode::SetGlobal(index), &[value_type], None)
+    }
+
+    /// Emit `i32.const`.
+    pub fn i32_const(&mut self, value: i32) -> &mut Self {
+        self.operator(Opcode::I32Const(value), &[], Some(ValueType::I32))
+    }
+
+    /// Emit `i64.const`.
+    pub fn i64_const(&mut self, value: i64) -> &mut Self {
+        self.operator(Opcode::I64Const(value), &[], Some(ValueType::I64))
+    }
+
+    /// Emit `f32.const`.
+    pub fn f32_const(&mut self, value: f32) -> &mut Self {
+        self.operator(Opcode::F32Const(value.to_bits()), &[], Some(ValueType::F32))
+    }
+
+    /// Emit `f64.const`.
+    pub fn f64_const(&mut self, value: f64) -> &mut Self {
+        self.operator(Opcode::F64Const(value.to_bits()), &[], Some(ValueType::F64))
+    }
+
+    /// Emit `current_memory`.
+    pub fn current_memory(&mut self) -> &mut Self {
+        self.operator(Opcode::CurrentMemory(false), &[], Some(ValueType::I32))
+    }
+
+    /// Emit `grow_memory`.
+    pub fn grow_memory(&mut self) -> &mut Self {
+        self.operator(Opcode::GrowMemory(false), &[ValueType::I32], Some(ValueType::I32))
+    }
+
+    loads! {
+        i32_load: I32Load(4) -> I32;
+        i64_load: I64Load(8) -> I64;
+        f32_load: F32Load(4) -> F32;
+        f64_load: F64Load(8) -> F64;
+        i32_load8_s: I32Load8S(1) -> I32;
+        i32_load8_u: I32Load8U(1) -> I32;
+        i32_load16_s: I32Load16S(2) -> I32;
+        i32_load16_u: I32Load16U(2) -> I32;
+        i64_load8_s: I64Load8S(1) -> I64;
+        i64_load8_u: I64Load8U(1) -> I64;
+        i64_load16_s: I64Load16S(2) -> I64;
+        i64_load16_u: I64Load16U(2) -> I64;
+        i64_load32_s: I64Load32S(4) -> I64;
+        i64_load32_u: I64Load32U(4) -> I64;
+    }
+
+    stores! {
+        i32_store: I32Store(4, I32);
+        i64_store: I64Store(8, I64);
+        f32_store: F32Store(4, F32);
+        f64_store: F64Store(8, F64);
+        i32_store8: I32Store8(1, I32);
+        i32_store16: I32Store16(2, I32);
+        i64_store8: I64Store8(1, I64);
+        i64_store16: I64Store16(2, I64);
+        i64_store32: I64Store32(4, I64);
+    }
+
+    operators! {
+        i32_eqz: I32Eqz(I32) -> I32;
+        i32_eq: I32Eq(I32, I32) -> I32;
+        i32_ne: I32Ne(I32, I32) -> I32;
+        i32_lt_s: I32LtS(I32, I32) -> I32;
+        i32_lt_u: I32LtU(I32, I32) -> I32;
+        i32_gt_s: I32GtS(I32, I32) -> I32;
+        i32_gt_u: I32GtU(I32, I32) -> I32;
+        i32_le_s: I32LeS(I32, I32) -> I32;
+        i32_le_u: I32LeU(I32, I32) -> I32;
+        i32_ge_s: I32GeS(I32, I32) -> I32;
+        i32_ge_u: I32GeU(I32, I32) -> I32;
+
+        i64_eqz: I64Eqz(I64) -> I32;
+        i64_eq: I64Eq(I64, I64) -> I32;
+        i64_ne: I64Ne(I64, I64) -> I32;
+        i64_lt_s: I64LtS(I64, I64) -> I32;
+        i64_lt_u: I64LtU(I64, I64) -> I32;
+        i64_gt_s: I64GtS(I64, I64) -> I32;
+        i64_gt_u: I64GtU(I64, I64) -> I32;
+        i64_le_s: I64LeS(I64, I64) -> I32;
+        i64_le_u: I64LeU(I64, I64) -> I32;
+        i64_ge_s: I64GeS(I64, I64) -> I32;
+        i64_ge_u: I64GeU(I64, I64) -> I32;
+
+        f32_eq: F32Eq(F32, F32) -> I32;
+        f32_ne: F32Ne(F32, F32) -> I32;
+        f32_lt: F32Lt(F32, F32) -> I32;
+        f32_gt: F32Gt(F32, F32) -> I32;
+        f32_le: F32Le(F32, F32) -> I32;
+        f32_ge: F32Ge(F32, F32) -> I32;
+
+        f64_eq: F64Eq(F64, F64) -> I32;
+        f64_ne: F64Ne(F64, F64) -> I32;
+        f64_lt: F64Lt(F64, F64) -> I32;
+        f64_gt: F64Gt(F64, F64) -> I32;
+        f64_le: F64Le(F64, F64) -> I32;
+        f64_ge: F64Ge(F64, F64) -> I32;
+
+        i32_clz: I32Clz(I32) -> I32;
+        i32_ctz: I32Ctz(I32) -> I32;
+        i32_popcnt: I32Popcnt(I32) -> I32;
+        i32_add: I32Add(I32, I32) -> I32;
+        i32_sub: I32Sub(I32, I32) -> I32;
+        i32_mul: I32Mul(I32, I32) -> I32;
+        i32_div_s: I32DivS(I32, I32) -> I32;
+        i32_div_u: I32DivU(I32, I32) -> I32;
+        i32_rem_s: I32RemS(I32, I32) -> I32;
+        i32_rem_u: I32RemU(I32, I32) -> I32;
+        i32_and: I32And(I32, I32) -> I32;
+        i32_or: I32Or(I32, I32) -> I32;
+        i32_xor: I32Xor(I32, I32) -> I32;
+        i32_shl: I32Shl(I32, I32) -> I32;
+        i32_shr_s: I32ShrS(I32, I32) -> I32;
+        i32_shr_u: I32ShrU(I32, I32) -> I32;
+        i32_rotl: I32Rotl(I32, I32) -> I32;
+        i32_rotr: I32Rotr(I32, I32) -> I32;
+
+        i64_clz: I64Clz(I64) -> I64;
+        i64_ctz: I64Ctz(I64) -> I64;
+        i64_popcnt: I64Popcnt(I64) -> I64;
+        i64_add: I64Add(I64, I64) -> I64;
+        i64_sub: I64Sub(I64, I64) -> I64;
+        i64_mul: I64Mul(I64, I64) -> I64;
+        i64_div_s: I64DivS(I64, I64) -> I64;
+        i64_div_u: I64DivU(I64, I64) -> I64;
+        i64_rem_s: I64RemS(I64, I64) -> I64;
+        i64_rem_u: I64RemU(I64, I64) -> I64;
+        i64_and: I64And(I64, I64) -> I64;
+        i64_or: I64Or(I64, I64) -> I64;
+        i64_xor: I64Xor(I64, I64) -> I64;
+        i64_shl: I64Shl(I64, I64) -> I64;
+        i64_shr_s: I64ShrS(I64, I64) -> I64;
+        i64_shr_u: I64ShrU(I64, I64) -> I64;
+        i64_rotl: I64Rotl(I64, I64) -> I64;
+        i64_rotr: I64Rotr(I64, I64) -> I64;
+
+        f32_abs: F32Abs(F32) -> F32;
+        f32_neg: F32Neg(F32) -> F32;
+        f32_ceil: F32Ceil(F32) -> F32;
+        f32_floor: F32Floor(F32) -> F32;
+        f32_trunc: F32Trunc(F32) -> F32;
+        f32_nearest: F32Nearest(F32) -> F32;
+        f32_sqrt: F32Sqrt(F32) -> F32;
+        f32_add: F32Add(F32, F32) -> F32;
+        f32_sub: F32Sub(F32, F32) -> F32;
+        f32_mul: F32Mul(F32, F32) -> F32;
+        f32_div: F32Div(F32, F32) -> F32;
+        f32_min: F32Min(F32, F32) -> F32;
+        f32_max: F32Max(F32, F32) -> F32;
+        f32_copysign: F32Copysign(F32, F32) -> F32;
+
+        f64_abs: F64Abs(F64) -> F64;
+        f64_neg: F64Neg(F64) -> F64;
+        f64_ceil: F64Ceil(F64) -> F64;
+        f64_floor: F64Floor(F64) -> F64;
+        f64_trunc: F64Trunc(F64) -> F64;
+        f64_nearest: F64Nearest(F64) -> F64;
+        f64_sqrt: F64Sqrt(F64) -> F64;
+        f64_add: F64Add(F64, F64) -> F64;
+        f64_sub: F64Sub(F64, F64) -> F64;
+        f64_mul: F64Mul(F64, F64) -> F64;
+        f64_div: F64Div(F64, F64) -> F64;
+        f64_min: F64Min(F64, F64) -> F64;
+        f64_max: F64Max(F64, F64) -> F64;
+        f64_copysign: F64Copysign(F64, F64) -> F64;
+
+        i32_wrap_i64: I32WarpI64(I64) -> I32;
+        i32_trunc_s_f32: I32TruncSF32(F32) -> I32;
+        i32_trunc_u_f32: I32TruncUF32(F32) -> I32;
+        i32_trunc_s_f64: I32TruncSF64(F64) -> I32;
+        i32_trunc_u_f64: I32TruncUF64(F64) -> I32;
+        i64_extend_s_i32: I64ExtendSI32(I32) -> I64;
+        i64_extend_u_i32: I64ExtendUI32(I32) -> I64;
+        i64_trunc_s_f32: I64TruncSF32(F32) -> I64;
+        i64_trunc_u_f32: I64TruncUF32(F32) -> I64;
+        i64_trunc_s_f64: I64TruncSF64(F64) -> I64;
+        i64_trunc_u_f64: I64TruncUF64(F64) -> I64;
+        f32_convert_s_i32: F32ConvertSI32(I32) -> F32;
+        f32_convert_u_i32: F32ConvertUI32(I32) -> F32;
+        f32_convert_s_i64: F32ConvertSI64(I64) -> F32;
+        f32_convert_u_i64: F32ConvertUI64(I64) -> F32;
+        f32_demote_f64: F32DemoteF64(F64) -> F32;
+        f64_convert_s_i32: F64ConvertSI32(I32) -> F64;
+        f64_convert_u_i32: F64ConvertUI32(I32) -> F64;
+        f64_convert_s_i64: F64ConvertSI64(I64) -> F64;
+        f64_convert_u_i64: F64ConvertUI64(I64) -> F64;
+        f64_promote_f32: F64PromoteF32(F32) -> F64;
+        i32_reinterpret_f32: I32ReinterpretF32(F32) -> I32;
+        i64_reinterpret_f64: I64ReinterpretF64(F64) -> I64;
+        f32_reinterpret_i32: F32ReinterpretI32(I32) -> F32;
+        f64_reinterpret_i64: F64ReinterpretI64(I64) -> F64;
+    }
+}
+
+fn describe(value_type: Option<ValueType>) -> String {
+    match value_type {
+        Some(value_type) => format!("{:?}", value_type),
+        None => "a value".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use elements::{Opcode, BlockType, ValueType, FunctionType, Local};
+    use super::Emitter;
+
+    #[test]
+    fn factorial() {
+        let signature = FunctionType::new(vec![ValueType::I64], Some(ValueType::I64));
+        let mut e = Emitter::new(&signature);
+        let n = e.param(0);
+        let result = e.local(ValueType::I64);
+        e.i64_const(1).set_local(result);
+        e.block(BlockType::NoResult, |e, done| {
+            e.loop_(BlockType::NoResult, |e, next| {
+                e.get_local(n).i64_eqz().br_if(done);
+                e.get_local(result).get_local(n).i64_mul().set_local(result);
+                e.get_local(n).i64_const(1).i64_sub().set_local(n);
+                e.br(next);
+            });
+        });
+        e.get_local(result);
+        let body = e.finish().expect("factorial to be well-typed");
+
+        assert_eq!(body.locals(), &[Local::new(1, ValueType::I64)][..]);
+        assert_eq!(body.code().elements(), &[
+            Opcode::I64Const(1),
+            Opcode::SetLocal(1),
+            Opcode::Block(BlockType::NoResult),
+            Opcode::Loop(BlockType::NoResult),
+            Opcode::GetLocal(0),
+            Opcode::I64Eqz,
+            Opcode::BrIf(1),
+            Opcode::GetLocal(1),
+            Opcode::GetLocal(0),
+            Opcode::I64Mul,
+            Opcode::SetLocal(1),
+            Opcode::GetLocal(0),
+            Opcode::I64Const(1),
+            Opcode::I64Sub,
+            Opcode::SetLocal(0),
+            Opcode::Br(0),
+            Opcode::End,
+            Opcode::End,
+            Opcode::GetLocal(1),
+            Opcode::End,
+        ][..]);
+    }
+
+    #[test]
+    fn if_else_result() {
+        let signature = FunctionType::new(vec![ValueType::I32], Some(ValueType::F32));
+        let mut e = Emitter::new(&signature);
+        let x = e.param(0);
+        e.get_local(x).if_else(
+            BlockType::Value(ValueType::F32),
+            |e, _| { e.f32_const(1.5); },
+            |e, _| { e.i32_const(2).f32_convert_s_i32(); },
+        );
+        e.return_();
+        e.finish().expect("if/else to be well-typed");
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let signature = FunctionType::new(vec![], Some(ValueType::I32));
+        let mut e = Emitter::new(&signature);
+        e.i32_const(1).i64_const(2).i32_add();
+        let error = e.finish().unwrap_err();
+        assert_eq!(error.position(), 2);
+        assert_eq!(error.message(), "Expected I32 on the stack, found I64");
+    }
+
+    #[test]
+    fn missing_result() {
+        let mut e = Emitter::new(&FunctionType::new(vec![], Some(ValueType::I32)));
+        e.nop();
+        assert!(e.finish().is_err());
+
+        let mut e = Emitter::new(&FunctionType::default());
+        e.i32_const(1);
+        assert!(e.finish().is_err());
+    }
+
+    #[test]
+    fn unreachable_code() {
+        let mut e = Emitter::new(&FunctionType::new(vec![], Some(ValueType::I64)));
+        e.block(BlockType::Value(ValueType::I32), |e, _| {
+            e.unreachable().i32_add();
+        });
+        e.drop().unreachable();
+        e.finish().expect("code after unreachable to be well-typed");
+    }
+
+    #[test]
+    fn escaped_label() {
+        let mut e = Emitter::new(&FunctionType::default());
+        let mut escaped = None;
+        e.block(BlockType::NoResult, |_, label| escaped = Some(label));
+        e.block(BlockType::NoResult, |_, _| {});
+        e.block(BlockType::NoResult, |e, _| { e.br(escaped.unwrap()); });
+        assert_eq!(e.finish().unwrap_err().message(), "Label is not in scope");
+    }
+
+    #[test]
+    fn alignment() {
+        let mut e = Emitter::new(&FunctionType::default());
+        e.i32_const(0).i32_load8_u(1, 0).drop();
+        assert!(e.finish().is_err());
+    }
+}
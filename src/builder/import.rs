@@ -1,3 +1,4 @@
+use alloc::{string::String, borrow::ToOwned};
 use super::invoke::{Invoke, Identity};
 use elements;
 
@@ -10,9 +10,14 @@ mod table;
 mod export;
 mod global;
 mod data;
+mod emit;
+mod custom;
+mod element;
 
-pub use self::module::{module, from_module, ModuleBuilder};
+pub use self::module::{module, from_module, ModuleBuilder, FunctionIndex, TypeIndex, BuildError};
 pub use self::code::{signatures, signature, function};
+pub use self::emit::{Emitter, EmitError, Local, Label};
+pub use self::custom::{SectionKind, Placement};
 pub use self::import::import;
 pub use self::export::export;
 pub use self::global::global;
\ No newline at end of file
@@ -1,22 +1,136 @@
+use alloc::{vec::Vec, string::String};
+use std::fmt;
 use super::invoke::{Invoke, Identity};
 use super::code::{self, SignaturesBuilder, FunctionBuilder};
 use super::memory::{self, MemoryBuilder};
 use super::table::{self, TableBuilder};
 use super::{import, export, global, data};
+use super::custom::{CustomSectionBuilder, CustomSectionDefinition, SectionKind, Placement};
+use super::element::ElementSegmentBuilder;
+use super::emit::EmitError;
 use elements;
 
 /// Module builder
 pub struct ModuleBuilder<F=Identity> {
     callback: F,
     module: ModuleScaffold,
+    start_export: Option<String>,
+    /// First mistake in the emitted code with the index of its function
+    emit_error: Option<(u32, EmitError)>,
 }
 
-/// Location of the internal module function
-pub struct CodeLocation {
-    /// Location (index in 'functions' section) of the signature
-    pub signature: u32,
-    /// Location (index in the 'code' section) of the body
-    pub body: u32,
+/// Index of the function in the function index space, imported functions first
+///
+/// Imports pushed after the function shift the index space, the index is
+/// valid for the imports present at the time it was returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FunctionIndex(u32);
+
+impl FunctionIndex {
+    /// Raw index, as used by `call` and exports
+    pub fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<FunctionIndex> for u32 {
+    fn from(index: FunctionIndex) -> u32 {
+        index.0
+    }
+}
+
+impl From<u32> for FunctionIndex {
+    fn from(index: u32) -> FunctionIndex {
+        FunctionIndex(index)
+    }
+}
+
+/// Index of the function type in the type section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TypeIndex(u32);
+
+impl TypeIndex {
+    /// Raw index, as used by functions, imports and `call_indirect`
+    pub fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<TypeIndex> for u32 {
+    fn from(index: TypeIndex) -> u32 {
+        index.0
+    }
+}
+
+impl From<u32> for TypeIndex {
+    fn from(index: u32) -> TypeIndex {
+        TypeIndex(index)
+    }
+}
+
+/// Error of the module built by `ModuleBuilder::try_build` or of its edit
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// Edited function is not defined in the module
+    NotDefined(u32),
+    /// Edited function is not imported
+    NotImported(u32),
+    /// Function can not be inserted at the index
+    InvalidPosition(u32),
+    /// Function or imported function refers to the type out of the type section
+    InvalidTypeReference {
+        /// Index of the function in the function index space
+        function: u32,
+        /// Referenced type
+        type_ref: u32,
+    },
+    /// Function and code sections have different number of entries
+    MissingBody {
+        /// Entries of the function section
+        functions: usize,
+        /// Entries of the code section
+        bodies: usize,
+    },
+    /// Export refers to the item out of its index space
+    InvalidExport {
+        /// Field of the export
+        field: String,
+        /// Referenced index
+        index: u32,
+    },
+    /// Start function out of the function index space
+    InvalidStart(u32),
+    /// Start export is not an exported function
+    UnknownStartExport(String),
+    /// Code emitted for the function body is not well-typed
+    Emit {
+        /// Index of the function in the function index space
+        function: u32,
+        /// Mistake in the emitted code
+        error: EmitError,
+    },
+    /// Module does not pass the validation
+    Validation(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::NotDefined(index) => write!(f, "Function {} is not defined in the module", index),
+            BuildError::NotImported(index) => write!(f, "Function {} is not imported", index),
+            BuildError::InvalidPosition(index) => write!(f, "Function can not be inserted at {}", index),
+            BuildError::InvalidTypeReference { function, type_ref } =>
+                write!(f, "Function {} refers to unknown type {}", function, type_ref),
+            BuildError::MissingBody { functions, bodies } =>
+                write!(f, "{} functions are declared, but {} bodies are defined", functions, bodies),
+            BuildError::InvalidExport { ref field, index } =>
+                write!(f, "Export {} refers to unknown index {}", field, index),
+            BuildError::InvalidStart(index) => write!(f, "Start refers to unknown function {}", index),
+            BuildError::UnknownStartExport(ref field) => write!(f, "Start export {} is not an exported function", field),
+            BuildError::Emit { function, ref error } => write!(f, "Function {} is not well-typed: {}", function, error),
+            BuildError::Validation(ref msg) => write!(f, "Validation error: {}", msg),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -32,7 +146,7 @@ struct ModuleScaffold {
     pub element: elements::ElementSection,
     pub code: elements::CodeSection,
     pub data: elements::DataSection,
-    pub other: Vec<elements::Section>,
+    pub other: Vec<(Placement, elements::Section)>,
 }
 
 impl From<elements::Module> for ModuleScaffold {
@@ -49,8 +163,12 @@ impl From<elements::Module> for ModuleScaffold {
         let mut code: Option<elements::CodeSection> = None;
         let mut data: Option<elements::DataSection> = None;
 
-        let mut sections = module.into_sections();
-        while let Some(section) = sections.pop() {
+        let mut other = Vec::new();
+        let mut placement = Placement::Before(SectionKind::Type);
+        for section in module.into_sections() {
+            if let Some(kind) = SectionKind::of(&section) {
+                placement = Placement::After(kind);
+            }
             match section {
                 elements::Section::Type(sect) => { types = Some(sect); }
                 elements::Section::Import(sect) => { import = Some(sect); }
@@ -63,7 +181,7 @@ impl From<elements::Module> for ModuleScaffold {
                 elements::Section::Element(sect) => { element = Some(sect); }
                 elements::Section::Code(sect) => { code = Some(sect); }
                 elements::Section::Data(sect) => { data = Some(sect); }
-                _ => {}
+                section => { other.push((placement, section)); }
             }
         }
 
@@ -79,7 +197,7 @@ impl From<elements::Module> for ModuleScaffold {
             element: element.unwrap_or_default(),
             code: code.unwrap_or_default(),
             data: data.unwrap_or_default(),
-            other: sections,
+            other,
         }
     }
 }
@@ -131,8 +249,13 @@ impl From<ModuleScaffold> for elements::Module {
         if data.entries().len() > 0 {
             sections.push(elements::Section::Data(data));
         }
-        sections.extend(module.other);
-        elements::Module::new(sections)
+        let known = |section: &elements::Section| SectionKind::of(section).map(|kind| kind as u32 * 3 + 1);
+        let mut sections: Vec<(u32, elements::Section)> = sections.into_iter()
+            .map(|section| (known(&section).expect("scaffold sections are known"), section))
+            .chain(module.other.into_iter().map(|(placement, section)| (placement.key(), section)))
+            .collect();
+        sections.sort_by_key(|&(key, _)| key);
+        elements::Module::new(sections.into_iter().map(|(_, section)| section).collect())
     }
 }
 
@@ -149,6 +272,8 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
         ModuleBuilder {
             callback: callback,
             module: Default::default(),
+            start_export: None,
+            emit_error: None,
         }
     }
 
@@ -158,17 +283,20 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
         self
     }
 
-    /// Fill module with sections from iterator
+    /// Fill module with sections from iterator, see `with_section`
     pub fn with_sections<I>(mut self, sections: I) -> Self 
         where I: IntoIterator<Item=elements::Section>
     {
-        self.module.other.extend(sections);
+        self.module.other.extend(sections.into_iter().map(|section| (placement_of(&section), section)));
         self
     }
 
     /// Add additional section
+    ///
+    /// Known section is placed where the sections of its kind belong, custom
+    /// and unparsed sections are placed after all sections.
     pub fn with_section(mut self, section: elements::Section) -> Self {
-        self.module.other.push(section);
+        self.module.other.push((placement_of(&section), section));
         self
     }
 
@@ -181,25 +309,22 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
     /// Push stand-alone function definition, creating sections, signature and code blocks
     /// in corresponding sections.
     /// `FunctionDefinition` can be build using `builder::function` builder
-    pub fn push_function(&mut self, func: code::FunctionDefinition) -> CodeLocation {
+    pub fn push_function(&mut self, func: code::FunctionDefinition) -> FunctionIndex {
         let signature = func.signature;
         let body = func.code;
 
         let type_ref = self.resolve_type_ref(signature);
 
         self.module.functions.entries_mut().push(elements::Func::new(type_ref));
-        let signature_index = self.module.functions.entries_mut().len() as u32 - 1;
         self.module.code.bodies_mut().push(body);
-        let body_index = self.module.code.bodies_mut().len() as u32 - 1;
+        let index = (self.module.import.functions() + self.module.functions.entries().len()) as u32 - 1;
+        self.record_emit_error(index, func.emit_error);
 
         if func.is_main {
-            self.module.start = Some(body_index);
+            self.module.start = Some(index);
         }
 
-        CodeLocation {
-            signature: signature_index,
-            body: body_index,
-        }
+        FunctionIndex(index)
     }
 
     /// Push linear memory region
@@ -229,32 +354,52 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
     fn resolve_type_ref(&mut self, signature: code::Signature) -> u32 {
         match signature {
             code::Signature::Inline(func_type) => {
-                // todo: maybe search for existing type
-                self.module.types.types_mut().push(elements::Type::Function(func_type));
-                self.module.types.types().len() as u32 - 1
+                let existing = self.module.types.types().iter().position(|t| match *t {
+                    elements::Type::Function(ref existing) => *existing == func_type,
+                });
+                match existing {
+                    Some(index) => index as u32,
+                    None => {
+                        self.module.types.types_mut().push(elements::Type::Function(func_type));
+                        self.module.types.types().len() as u32 - 1
+                    },
+                }
             }
             code::Signature::TypeReference(type_ref) => {
-                type_ref
+                // refer to the first of the identical types
+                let types = self.module.types.types();
+                types.get(type_ref as usize)
+                    .and_then(|referenced| types.iter().position(|t| t == referenced))
+                    .map_or(type_ref, |index| index as u32)
             }
         }
     }
 
+    /// Intern the signature of the imported function.
+    fn intern_import(&mut self, mut entry: elements::ImportEntry) -> elements::ImportEntry {
+        if let elements::External::Function(ref mut type_ref) = *entry.external_mut() {
+            *type_ref = self.resolve_type_ref(code::Signature::TypeReference(*type_ref));
+        }
+        entry
+    }
+
     /// Push one function signature, returning it's calling index.
-    /// Can create corresponding type in type section.
-    pub fn push_signature(&mut self, signature: code::Signature) -> u32 {
-        self.resolve_type_ref(signature)
+    /// Reuses the identical type of the type section, creating it only if there is none.
+    pub fn push_signature(&mut self, signature: code::Signature) -> TypeIndex {
+        TypeIndex(self.resolve_type_ref(signature))
     }
 
     /// Push signatures in the module, returning corresponding indices of pushed signatures
-    pub fn push_signatures(&mut self, signatures: code::SignatureBindings) -> Vec<u32> {
+    pub fn push_signatures(&mut self, signatures: code::SignatureBindings) -> Vec<TypeIndex> {
         signatures.into_iter().map(|binding|
-            self.resolve_type_ref(binding)
+            TypeIndex(self.resolve_type_ref(binding))
         ).collect()
     }
 
     /// Push import entry to module. Not that it does not update calling indices in
-    /// function bodies.
+    /// function bodies. Imported function refers to the first type identical to its own.
     pub fn push_import(&mut self, import: elements::ImportEntry) -> u32 {
+        let import = self.intern_import(import);
         self.module.import.entries_mut().push(import);
         // todo: actually update calling addresses in function bodies
         // todo: also batch push
@@ -262,15 +407,133 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
         self.module.import.entries_mut().len() as u32 - 1
     }
 
+    /// Push import of the function with the signature, reusing the identical type
+    /// of the type section. Like `push_import`, it does not update calling indices
+    /// in function bodies.
+    pub fn push_function_import(&mut self, module: &str, field: &str, signature: code::Signature) -> FunctionIndex {
+        let type_ref = self.resolve_type_ref(signature);
+        let index = self.module.import.functions() as u32;
+        self.module.import.entries_mut().push(
+            elements::ImportEntry::new(module.into(), field.into(), elements::External::Function(type_ref))
+        );
+        FunctionIndex(index)
+    }
+
     /// Push export entry to module. 
     pub fn push_export(&mut self, export: elements::ExportEntry) -> u32 {
         self.module.export.entries_mut().push(export);
         self.module.export.entries_mut().len() as u32 - 1
     }
 
+    /// Replace the body of the defined function
+    pub fn replace_function_body(&mut self, index: FunctionIndex, body: elements::FuncBody) -> Result<(), BuildError> {
+        let position = self.defined_position(index)?;
+        self.module.code.bodies_mut()[position] = body;
+        Ok(())
+    }
+
+    /// Change the signature of the imported function, returning the index of its new type.
+    /// Callers of the function are not updated.
+    pub fn set_import_signature(&mut self, index: FunctionIndex, signature: code::Signature) -> Result<TypeIndex, BuildError> {
+        if index.0 >= self.module.import.functions() as u32 {
+            return Err(BuildError::NotImported(index.0));
+        }
+        let type_ref = self.resolve_type_ref(signature);
+        let entry = self.module.import.entries_mut().iter_mut()
+            .filter(|entry| matches!(*entry.external(), elements::External::Function(_)))
+            .nth(index.0 as usize)
+            .expect("index is below the number of imported functions");
+        *entry.external_mut() = elements::External::Function(type_ref);
+        Ok(TypeIndex(type_ref))
+    }
+
+    /// Remove the export with the field name, returning it if there was one
+    pub fn remove_export(&mut self, field: &str) -> Option<elements::ExportEntry> {
+        let entries = self.module.export.entries_mut();
+        let position = entries.iter().position(|entry| entry.field() == field)?;
+        Some(entries.remove(position))
+    }
+
+    /// Insert the function definition at the index of the function index space.
+    /// References to the function at that index and the following ones are renumbered,
+    /// a name section which can not be renumbered is dropped.
+    pub fn insert_function(&mut self, at: FunctionIndex, func: code::FunctionDefinition) -> Result<FunctionIndex, BuildError> {
+        let imported = self.module.import.functions() as u32;
+        let total = imported + self.module.functions.entries().len() as u32;
+        if at.0 < imported || at.0 > total {
+            return Err(BuildError::InvalidPosition(at.0));
+        }
+
+        self.renumber_functions(|index| if index >= at.0 { index + 1 } else { index });
+        let type_ref = self.resolve_type_ref(func.signature);
+        let position = (at.0 - imported) as usize;
+        self.module.functions.entries_mut().insert(position, elements::Func::new(type_ref));
+        self.module.code.bodies_mut().insert(position, func.code);
+        self.record_emit_error(at.0, func.emit_error);
+        if func.is_main {
+            self.module.start = Some(at.0);
+        }
+        Ok(at)
+    }
+
+    fn record_emit_error(&mut self, function: u32, error: Option<EmitError>) {
+        if let (None, Some(error)) = (&self.emit_error, error) {
+            self.emit_error = Some((function, error));
+        }
+    }
+
+    fn defined_position(&self, index: FunctionIndex) -> Result<usize, BuildError> {
+        let imported = self.module.import.functions() as u32;
+        if index.0 < imported || index.0 - imported >= self.module.code.bodies().len() as u32 {
+            return Err(BuildError::NotDefined(index.0));
+        }
+        Ok((index.0 - imported) as usize)
+    }
+
+    /// Rewrite every reference to the function index space with `map`.
+    /// Name sections which can not be renumbered are dropped.
+    fn renumber_functions<M: Fn(u32) -> u32>(&mut self, map: M) {
+        renumber_calls(&mut self.module.code, &map);
+        renumber_exports(&mut self.module.export, &map);
+        if let Some(ref mut start) = self.module.start {
+            *start = map(*start);
+        }
+        if let Some((ref mut function, _)) = self.emit_error {
+            *function = map(*function);
+        }
+        renumber_members(&mut self.module.element, &map);
+
+        let mut stale = Vec::new();
+        for (position, &mut (_, ref mut section)) in self.module.other.iter_mut().enumerate() {
+            match *section {
+                elements::Section::Code(ref mut code) => renumber_calls(code, &map),
+                elements::Section::Export(ref mut export) => renumber_exports(export, &map),
+                elements::Section::Start(ref mut start) => *start = map(*start),
+                elements::Section::Element(ref mut element) => renumber_members(element, &map),
+                elements::Section::Custom(ref mut custom) => {
+                    let renumbered = match elements::NameSection::from_custom(custom) {
+                        Some(names) => names.and_then(|names| renumber_names(names, &map).into_custom()),
+                        None => continue,
+                    };
+                    match renumbered {
+                        Ok(renumbered) => *custom = renumbered,
+                        Err(_) => stale.push(position),
+                    }
+                },
+                _ => {},
+            }
+        }
+        for position in stale.into_iter().rev() {
+            self.module.other.remove(position);
+        }
+    }
+
     /// Add new function using dedicated builder
     pub fn function(self) -> FunctionBuilder<Self> {
-        FunctionBuilder::with_callback(self)
+        let types = self.module.types.types().iter()
+            .map(|ty| match *ty { elements::Type::Function(ref signature) => signature.clone() })
+            .collect();
+        FunctionBuilder::with_callback(self).with_types(types)
     }
 
     /// Add new linear memory using dedicated builder
@@ -294,9 +557,15 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
         self
     }
 
-    /// With inserted import entry
+    /// With inserted import entry, see `push_import`
     pub fn with_import(mut self, entry: elements::ImportEntry) -> Self {
-        self.module.import.entries_mut().push(entry);
+        self.push_import(entry);
+        self
+    }
+
+    /// With inserted import of the function with the signature, see `push_function_import`
+    pub fn with_function_import(mut self, module: &str, field: &str, signature: code::Signature) -> Self {
+        self.push_function_import(module, field, signature);
         self
     }
 
@@ -384,10 +653,184 @@ impl<F> ModuleBuilder<F> where F: Invoke<elements::Module> {
         data::DataSegmentBuilder::with_callback(self)
     }
 
+    /// Add custom section at the given place
+    pub fn with_custom_section(mut self, section: elements::CustomSection, placement: Placement) -> Self {
+        self.module.other.push((placement, elements::Section::Custom(section)));
+        self
+    }
+
+    /// Custom section builder
+    /// # Examples
+    /// ```
+    /// use sophon_wasm::builder::{module, SectionKind};
+    ///
+    /// let module = module()
+    ///    .custom_section()
+    ///        .name("producers")
+    ///        .payload(vec![1, 2, 3])
+    ///        .before(SectionKind::Code)
+    ///        .build()
+    ///    .build();
+    ///
+    /// assert_eq!(module.sections().len(), 1);
+    /// ```
+    pub fn custom_section(self) -> CustomSectionBuilder<Self> {
+        CustomSectionBuilder::with_callback(self)
+    }
+
+    /// Add element segment to the builder
+    pub fn with_element_segment(mut self, segment: elements::ElementSegment) -> Self {
+        self.module.element.entries_mut().push(segment);
+        self
+    }
+
+    /// Element segment builder
+    pub fn element(self) -> ElementSegmentBuilder<Self> {
+        ElementSegmentBuilder::with_callback(self)
+    }
+
+    /// Start with the function
+    pub fn with_start(mut self, index: FunctionIndex) -> Self {
+        self.module.start = Some(index.0);
+        self
+    }
+
+    /// Start with the function exported as `field`, resolved when the module is built
+    ///
+    /// If there is no such export, `try_build` fails and `build` leaves the start unchanged.
+    pub fn start_export(mut self, field: &str) -> Self {
+        self.start_export = Some(field.into());
+        self
+    }
+
+    fn resolve_start_export(&mut self) -> Result<(), BuildError> {
+        let field = match self.start_export.take() {
+            Some(field) => field,
+            None => return Ok(()),
+        };
+        let index = self.module.export.entries().iter()
+            .filter(|entry| entry.field() == field)
+            .filter_map(|entry| match *entry.internal() {
+                elements::Internal::Function(index) => Some(index),
+                _ => None,
+            })
+            .next();
+        match index {
+            Some(index) => {
+                self.module.start = Some(index);
+                Ok(())
+            },
+            None => Err(BuildError::UnknownStartExport(field)),
+        }
+    }
+
     /// Build module (final step)
-    pub fn build(self) -> F::Result {
+    pub fn build(mut self) -> F::Result {
+        // the missing start export is only reported by `try_build`
+        let _ = self.resolve_start_export();
         self.callback.invoke(self.module.into())
     }
+
+    /// Build module, checking the emitted code, the references between its
+    /// sections and, with the `std` feature, running the full validation (final step)
+    pub fn try_build(mut self) -> Result<F::Result, BuildError> {
+        if let Some((function, error)) = self.emit_error.take() {
+            return Err(BuildError::Emit { function, error });
+        }
+        self.resolve_start_export()?;
+        check_references(&self.module)?;
+        let module: elements::Module = self.module.into();
+        #[cfg(feature = "std")]
+        ::validation::validate_module(&module).map_err(|err| BuildError::Validation(err.to_string()))?;
+        Ok(self.callback.invoke(module))
+    }
+}
+
+fn renumber_calls<M: Fn(u32) -> u32>(code: &mut elements::CodeSection, map: &M) {
+    for body in code.bodies_mut() {
+        for opcode in body.code_mut().elements_mut() {
+            if let elements::Opcode::Call(ref mut index) = *opcode {
+                *index = map(*index);
+            }
+        }
+    }
+}
+
+fn renumber_exports<M: Fn(u32) -> u32>(export: &mut elements::ExportSection, map: &M) {
+    for entry in export.entries_mut() {
+        if let elements::Internal::Function(ref mut index) = *entry.internal_mut() {
+            *index = map(*index);
+        }
+    }
+}
+
+fn renumber_members<M: Fn(u32) -> u32>(element: &mut elements::ElementSection, map: &M) {
+    for segment in element.entries_mut() {
+        for member in segment.members_mut() {
+            *member = map(*member);
+        }
+    }
+}
+
+fn renumber_names<M: Fn(u32) -> u32>(mut names: elements::NameSection, map: &M) -> elements::NameSection {
+    if let Some(ref mut functions) = *names.functions_mut() {
+        let renumbered = functions.names().iter().map(|(&index, name)| (map(index), name.clone())).collect();
+        *functions.names_mut() = renumbered;
+    }
+    if let Some(ref mut locals) = *names.locals_mut() {
+        let renumbered = ::std::mem::take(locals).into_iter().map(|(index, names)| (map(index), names)).collect();
+        *locals = renumbered;
+    }
+    names
+}
+
+fn placement_of(section: &elements::Section) -> Placement {
+    SectionKind::of(section).map_or(Placement::End, Placement::After)
+}
+
+fn check_references(module: &ModuleScaffold) -> Result<(), BuildError> {
+    let types = module.types.types().len() as u32;
+    let type_refs = module.import.entries().iter()
+        .filter_map(|entry| match *entry.external() {
+            elements::External::Function(type_ref) => Some(type_ref),
+            _ => None,
+        })
+        .chain(module.functions.entries().iter().map(|func| func.type_ref()));
+    for (function, type_ref) in type_refs.enumerate() {
+        if type_ref >= types {
+            return Err(BuildError::InvalidTypeReference { function: function as u32, type_ref });
+        }
+    }
+
+    let functions = module.functions.entries().len();
+    let bodies = module.code.bodies().len();
+    if functions != bodies {
+        return Err(BuildError::MissingBody { functions, bodies });
+    }
+
+    let imported = |kind: fn(&elements::External) -> bool| module.import.entries().iter()
+        .filter(|entry| kind(entry.external()))
+        .count();
+    let total_functions = (imported(|e| matches!(*e, elements::External::Function(_))) + functions) as u32;
+    for entry in module.export.entries() {
+        let (index, count) = match *entry.internal() {
+            elements::Internal::Function(index) => (index, total_functions),
+            elements::Internal::Global(index) => (index,
+                (imported(|e| matches!(*e, elements::External::Global(_))) + module.global.entries().len()) as u32),
+            elements::Internal::Memory(index) => (index,
+                (imported(|e| matches!(*e, elements::External::Memory(_))) + module.memory.entries().len()) as u32),
+            elements::Internal::Table(index) => (index,
+                (imported(|e| matches!(*e, elements::External::Table(_))) + module.table.entries().len()) as u32),
+        };
+        if index >= count {
+            return Err(BuildError::InvalidExport { field: entry.field().into(), index });
+        }
+    }
+
+    match module.start {
+        Some(start) if start >= total_functions => Err(BuildError::InvalidStart(start)),
+        _ => Ok(()),
+    }
 }
 
 impl<F> Invoke<elements::FunctionSection> for ModuleBuilder<F> 
@@ -487,6 +930,26 @@ impl<F> Invoke<elements::DataSegment> for ModuleBuilder<F>
     }    
 }
 
+impl<F> Invoke<elements::ElementSegment> for ModuleBuilder<F>
+    where F: Invoke<elements::Module>
+{
+    type Result = Self;
+
+    fn invoke(self, segment: elements::ElementSegment) -> Self {
+        self.with_element_segment(segment)
+    }
+}
+
+impl<F> Invoke<CustomSectionDefinition> for ModuleBuilder<F>
+    where F: Invoke<elements::Module>
+{
+    type Result = Self;
+
+    fn invoke(self, definition: CustomSectionDefinition) -> Self {
+        self.with_custom_section(definition.section, definition.placement)
+    }
+}
+
 /// Start new module builder
 /// # Examples
 ///
@@ -509,6 +972,8 @@ pub fn module() -> ModuleBuilder {
 }
 
 /// Start builder to extend existing module
+///
+/// Custom sections of the module are kept after the known section preceding them.
 pub fn from_module(module: elements::Module) -> ModuleBuilder {
     ModuleBuilder::new().with_module(module)
 }
@@ -516,7 +981,14 @@ pub fn from_module(module: elements::Module) -> ModuleBuilder {
 #[cfg(test)]
 mod tests {
 
-    use super::module;
+    use alloc::{vec::Vec, string::{String, ToString}, borrow::ToOwned};
+    use elements::{
+        Module, Opcodes, Opcode, FunctionType, Type, Section, TypeSection, FunctionSection, Func, FuncBody,
+        Internal, External, ImportEntry, NameSection, NameMap, ValueType,
+    };
+    use super::super::code::{function, signature, Signature};
+    use super::super::custom::SectionKind;
+    use super::{module, from_module, BuildError, FunctionIndex};
 
     #[test]
     fn smoky() {
@@ -567,4 +1039,415 @@ mod tests {
 
         assert_eq!(module.data_section().expect("data section to exist").entries().len(), 1);
     }
+
+    #[test]
+    fn custom_sections_are_kept() {
+        let custom = ::elements::CustomSection::new("name".into(), vec![0u8, 1, 0]);
+        let module = module()
+            .with_section(::elements::Section::Custom(custom))
+            .build();
+
+        let module = super::from_module(module).build();
+        assert_eq!(module.sections().len(), 1);
+    }
+
+    #[test]
+    fn try_build_emitted_code() {
+        use elements::{Local, ValueType};
+
+        let result = module()
+            .function()
+                .signature().param().i32().return_type().i64().build()
+                .body()
+                    .with_locals(vec![Local::new(1, ValueType::I32)])
+                    .emit(|e| {
+                        let l = e.local(ValueType::I64);
+                        e.i64_const(5).tee_local(l);
+                    })
+                    .build()
+                .build()
+            .try_build();
+        assert!(result.is_ok());
+
+        let result = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .function()
+                .signature().return_type().i64().build()
+                .body()
+                    .emit(|e| { e.i32_const(1); })
+                    .build()
+                .build()
+            .try_build();
+        match result.err() {
+            Some(BuildError::Emit { function: 1, ref error }) => assert_eq!(error.position(), 1),
+            other => panic!("Unexpected result {:?}", other.map(|err| err.to_string())),
+        }
+    }
+
+    #[test]
+    fn try_build_emitted_code_by_type_reference() {
+        let mut builder = module();
+        let type_index = builder.push_signature(signature().param().i32().build_sig());
+        let result = builder
+            .function()
+                .with_signature(Signature::TypeReference(type_index.index()))
+                .body()
+                    .emit(|e| { let p = e.param(0); e.get_local(p).drop(); })
+                    .build()
+                .build()
+            .try_build();
+        assert!(result.is_ok());
+
+        let result = module()
+            .function()
+                .with_signature(Signature::TypeReference(0))
+                .body()
+                    .emit(|e| { e.i32_const(1).drop(); })
+                    .build()
+                .build()
+            .try_build();
+        match result.err() {
+            Some(BuildError::Emit { function: 0, ref error }) =>
+                assert_eq!(error.message(), "Signature of the function body is unknown"),
+            other => panic!("Unexpected result {:?}", other.map(|err| err.to_string())),
+        }
+    }
+
+    #[test]
+    fn try_build() {
+        let built = module()
+            .function()
+                .signature().param().i32().build()
+                .body().build()
+                .build()
+            .export().field("f").internal().func(0).build()
+            .try_build()
+            .ok()
+            .expect("module to be valid");
+        assert_eq!(built.export_section().expect("export section to exist").entries().len(), 1);
+
+        let result = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .export().field("g").internal().func(1).build()
+            .try_build();
+        assert_eq!(result.err(), Some(BuildError::InvalidExport { field: "g".into(), index: 1 }));
+
+        let mut builder = module();
+        builder.push_function(function().with_signature(Signature::TypeReference(3)).body().build().build());
+        assert_eq!(builder.try_build().err(), Some(BuildError::InvalidTypeReference { function: 0, type_ref: 3 }));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn try_build_validates() {
+        let result = module()
+            .function()
+                .signature().return_type().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::I64Const(1), Opcode::End])).build()
+                .build()
+            .try_build();
+        match result.err() {
+            Some(BuildError::Validation(_)) => {},
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_body() {
+        let declared = Module::new(vec![
+            Section::Type(TypeSection::with_types(vec![Type::Function(FunctionType::default())])),
+            Section::Function(FunctionSection::with_entries(vec![Func::new(0)])),
+        ]);
+        let result = from_module(declared).try_build();
+        assert_eq!(result.err(), Some(BuildError::MissingBody { functions: 1, bodies: 0 }));
+    }
+
+    #[test]
+    fn typed_indices() {
+        let mut builder = module()
+            .import().module("env").field("f").external().func(0).build();
+        let type_index = builder.push_signature(signature().build_sig());
+        let function_index = builder.push_function(function().signature().build().body().build().main().build());
+        assert_eq!(type_index.index(), 0);
+        assert_eq!(function_index.index(), 1);
+
+        let built = builder.try_build().ok().expect("module to be valid");
+        assert_eq!(built.start_section(), Some(1));
+    }
+
+    #[test]
+    fn import_of_second_type() {
+        let mut builder = module();
+        builder.push_signature(signature().build_sig());
+        builder.push_signature(signature().param().i32().build_sig());
+        let built = builder
+            .import().module("env").field("f").external().func(1).build()
+            .try_build()
+            .ok()
+            .expect("module to be valid");
+        assert_eq!(built.type_section().expect("type section to exist").types().len(), 2);
+        assert_eq!(built.import_section().expect("import section to exist").functions(), 1);
+    }
+
+    #[test]
+    fn signatures_are_interned() {
+        let existing = module()
+            .function()
+                .signature().param().i64().build()
+                .body().build()
+                .build()
+            .build();
+
+        let mut builder = from_module(existing)
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::GetLocal(0), Opcode::End])).build()
+                .build()
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::I32Const(1), Opcode::End])).build()
+                .build()
+            .function()
+                .signature().param().i64().build()
+                .body().build()
+                .build()
+            .with_function_import("env", "hash", signature().param().i32().return_type().i32().build_sig());
+        let binary = builder.push_signature(signature().param().i32().return_type().i32().build_sig());
+        let unary = builder.push_signature(signature().param().i64().build_sig());
+        let nullary = builder.push_signature(signature().build_sig());
+        assert_eq!((unary.index(), binary.index(), nullary.index()), (0, 1, 2));
+        let log = builder.push_function_import("env", "log", signature().param().i64().build_sig());
+        assert_eq!(log.index(), 1);
+
+        let built = builder.try_build().ok().expect("module to be valid");
+        assert_eq!(built.type_section().expect("type section to exist").types().len(), 3);
+        let type_refs: Vec<u32> = built.function_section().expect("function section to exist")
+            .entries().iter().map(|f| f.type_ref()).collect();
+        assert_eq!(type_refs, vec![0, 1, 1, 0]);
+        let import_refs: Vec<_> = built.import_section().expect("import section to exist")
+            .entries().iter().map(|entry| entry.external().clone()).collect();
+        assert_eq!(import_refs, vec![External::Function(1), External::Function(0)]);
+    }
+
+    #[test]
+    fn import_types_are_interned() {
+        let duplicated = Module::new(vec![
+            Section::Type(TypeSection::with_types(vec![
+                Type::Function(FunctionType::default()),
+                Type::Function(FunctionType::default()),
+            ])),
+        ]);
+        let mut builder = from_module(duplicated)
+            .with_import(ImportEntry::new("env".into(), "a".into(), External::Function(1)))
+            .import().module("env").field("b").external().func(1).build()
+            .function()
+                .with_signature(Signature::TypeReference(1))
+                .body().build()
+                .build();
+        builder.push_import(ImportEntry::new("env".into(), "c".into(), External::Function(1)));
+
+        let built = builder.try_build().ok().expect("module to be valid");
+        let import_refs: Vec<_> = built.import_section().expect("import section to exist")
+            .entries().iter().map(|entry| entry.external().clone()).collect();
+        assert_eq!(import_refs, vec![External::Function(0); 3]);
+        assert_eq!(built.function_section().expect("function section to exist").entries()[0].type_ref(), 0);
+    }
+
+    #[test]
+    fn known_sections_in_order() {
+        let built = module()
+            .with_section(Section::Start(0))
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_sections(vec![
+                Section::Custom(::elements::CustomSection::new("last".into(), Vec::new())),
+                Section::Memory(::elements::MemorySection::with_entries(vec![::elements::MemoryType::new(1, None)])),
+            ])
+            .build();
+
+        let kinds: Vec<_> = built.sections().iter().map(SectionKind::of).collect();
+        assert_eq!(kinds, vec![
+            Some(SectionKind::Type), Some(SectionKind::Function), Some(SectionKind::Memory),
+            Some(SectionKind::Start), Some(SectionKind::Code), None,
+        ]);
+    }
+
+    #[test]
+    fn editing() {
+        let mut names = NameSection::default();
+        *names.functions_mut() = Some(NameMap::new(vec![(1, "first".to_owned()), (2, "second".to_owned())].into_iter().collect()));
+
+        let mut builder = module()
+            .import().module("env").field("log").external().func(0).build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Call(2), Opcode::End])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Call(0), Opcode::End])).build()
+                .build()
+            .table().with_min(2).with_element(0, vec![1, 2]).build()
+            .export().field("first").internal().func(1).build()
+            .export().field("second").internal().func(2).build()
+            .with_section(Section::Custom(names.into_custom().unwrap()));
+
+        let inserted = builder.insert_function(
+            FunctionIndex::from(2),
+            function().signature().param().i32().build().body().build().build(),
+        ).expect("insertion to succeed");
+        assert_eq!(inserted.index(), 2);
+
+        builder.replace_function_body(
+            FunctionIndex::from(1),
+            FuncBody::new(vec![], Opcodes::new(vec![Opcode::Call(3), Opcode::Call(3), Opcode::End])),
+        ).expect("function 1 to be defined");
+
+        let log_type = builder.set_import_signature(
+            FunctionIndex::from(0),
+            signature().build_sig(),
+        ).expect("function 0 to be imported");
+        assert_eq!(log_type.index(), 0);
+
+        let removed = builder.remove_export("first").expect("export to exist");
+        assert_eq!(*removed.internal(), Internal::Function(1));
+        assert!(builder.remove_export("first").is_none());
+
+        assert_eq!(builder.replace_function_body(FunctionIndex::from(0), FuncBody::empty()), Err(BuildError::NotDefined(0)));
+        assert_eq!(builder.set_import_signature(FunctionIndex::from(1), signature().build_sig()), Err(BuildError::NotImported(1)));
+        assert_eq!(
+            builder.insert_function(FunctionIndex::from(0), function().signature().build().body().build().build()).err(),
+            Some(BuildError::InvalidPosition(0)),
+        );
+
+        let built = builder.try_build().ok().expect("edited module to be valid");
+        let bodies = built.code_section().expect("code section to exist").bodies();
+        assert_eq!(bodies[0].code().elements(), &[Opcode::Call(3), Opcode::Call(3), Opcode::End][..]);
+        assert_eq!(bodies[2].code().elements(), &[Opcode::Call(0), Opcode::End][..]);
+        assert_eq!(built.elements_section().unwrap().entries()[0].members(), &[1, 3]);
+        let exports = built.export_section().expect("export section to exist").entries();
+        assert_eq!(exports.len(), 1);
+        assert_eq!(*exports[0].internal(), Internal::Function(3));
+
+        let names = built.sections().iter()
+            .filter_map(|s| match *s { Section::Custom(ref c) => NameSection::from_custom(c), _ => None })
+            .next().unwrap().unwrap();
+        assert_eq!(names.functions().unwrap().get(1), Some("first"));
+        assert_eq!(names.functions().unwrap().get(3), Some("second"));
+    }
+
+    #[test]
+    fn insert_renumbers_added_sections() {
+        let mut builder = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_section(Section::Start(0))
+            .with_section(Section::Element(::elements::ElementSection::with_entries(vec![
+                ::elements::ElementSegment::new(0, ::elements::InitExpr::new(vec![Opcode::I32Const(0), Opcode::End]), vec![0]),
+            ])))
+            // malformed name section can not be renumbered
+            .with_section(Section::Custom(::elements::CustomSection::new("name".into(), vec![1, 5, 0])));
+
+        builder.insert_function(FunctionIndex::from(0), function().signature().build().body().build().build())
+            .expect("insertion to succeed");
+
+        let built = builder.build();
+        assert_eq!(built.start_section(), Some(1));
+        assert_eq!(built.elements_section().unwrap().entries()[0].members(), &[1]);
+        assert!(built.sections().iter().all(|section| !matches!(*section, Section::Custom(_))));
+    }
+
+    fn custom_names(module: &Module) -> Vec<String> {
+        module.sections().iter().map(|section| match *section {
+            Section::Custom(ref custom) => custom.name().to_owned(),
+            ref known => format!("{:?}", SectionKind::of(known).unwrap()),
+        }).collect()
+    }
+
+    #[test]
+    fn custom_section_placement() {
+        let built = module()
+            .custom_section().name("last").build()
+            .custom_section().name("before_code").before(SectionKind::Code).build()
+            .custom_section().name("after_type").after(SectionKind::Type).build()
+            .custom_section().name("before_memory").before(SectionKind::Memory).build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .build();
+        assert_eq!(custom_names(&built), vec![
+            "Type", "after_type", "Function", "before_memory", "before_code", "Code", "last",
+        ]);
+
+        let rebuilt = from_module(built)
+            .memory().with_min(1).build()
+            .build();
+        // custom sections of the existing module stay after the preceding known section
+        assert_eq!(custom_names(&rebuilt), vec![
+            "Type", "after_type", "Function", "before_memory", "before_code", "Memory", "Code", "last",
+        ]);
+    }
+
+    #[test]
+    fn element_segments() {
+        let built = module()
+            .import().module("env").field("table_base").external().global(ValueType::I32, false).build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .table().with_min(4).build()
+            .element().offset_global(0).member(0).member(0).build()
+            .element().offset(Opcode::I32Const(3)).members(vec![0]).build()
+            .try_build()
+            .ok()
+            .expect("module to be valid");
+
+        let segments = built.elements_section().expect("element section to exist").entries();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].offset().code(), &[Opcode::GetGlobal(0), Opcode::End][..]);
+        assert_eq!(segments[0].members(), &[0, 0]);
+        assert_eq!(segments[1].offset().code(), &[Opcode::I32Const(3), Opcode::End][..]);
+    }
+
+    #[test]
+    fn start_by_export() {
+        let builder = || module()
+            .function()
+                .signature().param().i32().build()
+                .body().build()
+                .build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build();
+
+        let built = builder()
+            .start_export("init")
+            .export().field("init").internal().func(1).build()
+            .try_build()
+            .ok()
+            .expect("module to be valid");
+        assert_eq!(built.start_section(), Some(1));
+
+        let result = builder().start_export("missing").try_build();
+        assert_eq!(result.err(), Some(BuildError::UnknownStartExport("missing".into())));
+    }
+
+    #[test]
+    fn start_by_missing_export() {
+        let built = module().start_export("missing").build();
+        assert_eq!(built.start_section(), None);
+    }
  }
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use elements;
 use super::invoke::{Invoke, Identity};
 
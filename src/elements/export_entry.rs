@@ -1,8 +1,10 @@
-use std::io;
+use alloc::string::String;
+use io;
 use super::{Deserialize, Serialize, Error, VarUint7, VarUint32};
 
 /// Internal reference of the exported entry.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Internal {
     /// Function reference.
     Function(u32),
@@ -48,7 +50,8 @@ impl Serialize for Internal {
 }
 
 /// Export entry.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExportEntry {
     field_str: String,
     internal: Internal,
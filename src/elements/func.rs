@@ -1,4 +1,5 @@
-use std::io;
+use alloc::vec::Vec;
+use io;
 use super::{
     Deserialize, Error, ValueType, VarUint32, CountedList, Opcodes,
     Serialize, CountedWriter, CountedListWriter,
@@ -6,6 +7,7 @@ use super::{
 
 /// Function signature (type reference)
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Func(u32);
 
 impl Func {
@@ -24,7 +26,8 @@ impl Func {
 }
 
 /// Local definition inside the function body.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Local {
     count: u32,
     value_type: ValueType,
@@ -65,6 +68,7 @@ impl Serialize for Local {
 
 /// Function body definition.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FuncBody {
     locals: Vec<Local>,
     opcodes: Opcodes,
@@ -1,8 +1,9 @@
-use std::io;
+use io;
 use super::{Deserialize, Serialize, Error, GlobalType, InitExpr};
 
 /// Global entry in the module.
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GlobalEntry {
     global_type: GlobalType,
     init_expr: InitExpr,
@@ -1,11 +1,13 @@
-use std::io;
+use alloc::string::String;
+use io;
 use super::{
     Deserialize, Serialize, Error, VarUint7, VarInt7, VarUint32, VarUint1,
     ValueType, TableElementType
 };
 
 /// Global definition struct
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GlobalType {
     content_type: ValueType,
     is_mutable: bool,
@@ -51,7 +53,8 @@ impl Serialize for GlobalType {
 }
 
 /// Table entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TableType {
     elem_type: TableElementType,
     limits: ResizableLimits,
@@ -96,7 +99,8 @@ impl Serialize for TableType {
 }
 
 /// Memory limits
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ResizableLimits {
     initial: u32,
     maximum: Option<u32>,
@@ -150,7 +154,8 @@ impl Serialize for ResizableLimits {
 }
 
 /// Memory entry.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MemoryType(ResizableLimits);
 
 impl MemoryType {
@@ -181,7 +186,8 @@ impl Serialize for MemoryType {
 }
 
 /// External to local binding.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum External {
     /// Binds to function with index.
     Function(u32),
@@ -238,7 +244,8 @@ impl Serialize for External {
 }
 
 /// Import entry.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ImportEntry {
     module_str: String,
     field_str: String,
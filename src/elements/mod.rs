@@ -1,6 +1,7 @@
 //! Elements of the WebAssembly binary format.
 
-use std::io;
+use alloc::{vec::Vec, string::String};
+use io;
 
 mod module;
 mod section;
@@ -12,6 +13,8 @@ mod global_entry;
 mod ops;
 mod func;
 mod segment;
+mod name_section;
+mod visitor;
 
 pub use self::module::{Module, peek_size};
 pub use self::section::{
@@ -27,9 +30,14 @@ pub use self::primitives::{
     Uint64, VarUint64, CountedList, CountedWriter, CountedListWriter,
 };
 pub use self::types::{Type, ValueType, BlockType, FunctionType, TableElementType};
-pub use self::ops::{Opcode, Opcodes, InitExpr};
+pub use self::ops::{Opcode, Opcodes, InitExpr, OpcodeCategory};
 pub use self::func::{Func, FuncBody, Local};
 pub use self::segment::{ElementSegment, DataSegment};
+pub use self::name_section::{NameSection, NameMap, NAME_SECTION};
+pub use self::visitor::{
+    Visitor, VisitorMut, Location, Editor, walk_module, walk_func_body, walk_opcode,
+    walk_module_mut, walk_func_body_mut, walk_opcode_mut,
+};
 
 /// Deserialization from serial i/o
 pub trait Deserialize : Sized {
@@ -114,6 +122,7 @@ impl From<Unparsed> for Vec<u8> {
 }
 
 /// Deserialize module from file.
+#[cfg(feature = "std")]
 pub fn deserialize_file<P: AsRef<::std::path::Path>>(p: P) -> Result<Module, Error> {
     use std::io::Read;
 
@@ -137,6 +146,7 @@ pub fn serialize<T: Serialize>(val: T) -> Result<Vec<u8>, T::Error> {
 }
 
 /// Serialize module to the file
+#[cfg(feature = "std")]
 pub fn serialize_to_file<P: AsRef<::std::path::Path>>(p: P, module: Module) -> Result<(), Error>
 {
     let mut io = ::std::fs::File::create(p)?;
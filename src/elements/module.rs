@@ -1,4 +1,5 @@
-use std::io;
+use alloc::vec::Vec;
+use io;
 use byteorder::{LittleEndian, ByteOrder};
 
 use super::{Deserialize, Serialize, Error, Uint32};
@@ -11,6 +12,7 @@ const WASM_MAGIC_NUMBER: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
 
 /// WebAssembly module
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Module {
     magic: u32,
     version: u32,
@@ -197,10 +199,10 @@ struct PeekSection<'a> {
 }
 
 impl<'a> io::Read for PeekSection<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let available = ::std::cmp::min(buf.len(), self.region.len() - self.cursor);
         if available < buf.len() {
-            return Err(::std::io::Error::from(::std::io::ErrorKind::UnexpectedEof));
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
         }
 
         let range = self.cursor..self.cursor + buf.len();
@@ -247,7 +249,7 @@ pub fn peek_size(source: &[u8]) -> usize {
     cursor
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod integration_tests {
 
     use super::super::{deserialize_file, serialize, deserialize_buffer, Section};
@@ -0,0 +1,234 @@
+use alloc::{vec::Vec, string::String, borrow::ToOwned};
+use io;
+use alloc::collections::BTreeMap;
+use super::{
+    Deserialize, Serialize, Error, VarUint7, VarUint32, CountedWriter, CustomSection,
+};
+
+/// Name of the custom section with debug names.
+pub const NAME_SECTION: &str = "name";
+
+const MODULE_SUBSECTION: u8 = 0;
+const FUNCTION_SUBSECTION: u8 = 1;
+const LOCAL_SUBSECTION: u8 = 2;
+
+/// Map from index to name, ordered by index.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NameMap(BTreeMap<u32, String>);
+
+impl NameMap {
+    /// New name map from index-name pairs.
+    pub fn new(names: BTreeMap<u32, String>) -> Self {
+        NameMap(names)
+    }
+
+    /// Index-name pairs.
+    pub fn names(&self) -> &BTreeMap<u32, String> { &self.0 }
+
+    /// Index-name pairs (mutable).
+    pub fn names_mut(&mut self) -> &mut BTreeMap<u32, String> { &mut self.0 }
+
+    /// Name for the index, if any.
+    pub fn get(&self, index: u32) -> Option<&str> {
+        self.0.get(&index).map(|name| name.as_str())
+    }
+}
+
+impl Deserialize for NameMap {
+    type Error = Error;
+
+    fn deserialize<R: io::Read>(reader: &mut R) -> Result<Self, Self::Error> {
+        let count: u32 = VarUint32::deserialize(reader)?.into();
+        let mut names = BTreeMap::new();
+        for _ in 0..count {
+            let index: u32 = VarUint32::deserialize(reader)?.into();
+            let name = String::deserialize(reader)?;
+            names.insert(index, name);
+        }
+        Ok(NameMap(names))
+    }
+}
+
+impl Serialize for NameMap {
+    type Error = Error;
+
+    fn serialize<W: io::Write>(self, writer: &mut W) -> Result<(), Self::Error> {
+        VarUint32::from(self.0.len()).serialize(writer)?;
+        for (index, name) in self.0 {
+            VarUint32::from(index).serialize(writer)?;
+            name.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Debug names of the module (contents of the custom section `name`).
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NameSection {
+    module: Option<String>,
+    functions: Option<NameMap>,
+    locals: Option<BTreeMap<u32, NameMap>>,
+    unknown: Vec<(u8, Vec<u8>)>,
+}
+
+impl NameSection {
+    /// Parse names from the custom section, `None` if it is not a name section.
+    pub fn from_custom(section: &CustomSection) -> Option<Result<Self, Error>> {
+        if section.name() != NAME_SECTION {
+            return None;
+        }
+        let mut reader = io::Cursor::new(section.payload());
+        Some(NameSection::deserialize(&mut reader))
+    }
+
+    /// Serialize names into the custom section.
+    pub fn into_custom(self) -> Result<CustomSection, Error> {
+        let mut payload = Vec::new();
+        self.serialize(&mut payload)?;
+        Ok(CustomSection::new(NAME_SECTION.to_owned(), payload))
+    }
+
+    /// Module name, if any.
+    pub fn module(&self) -> Option<&str> { self.module.as_deref() }
+
+    /// Module name (mutable).
+    pub fn module_mut(&mut self) -> &mut Option<String> { &mut self.module }
+
+    /// Function names by function index, if any.
+    pub fn functions(&self) -> Option<&NameMap> { self.functions.as_ref() }
+
+    /// Function names by function index (mutable).
+    pub fn functions_mut(&mut self) -> &mut Option<NameMap> { &mut self.functions }
+
+    /// Local names by function index, if any.
+    pub fn locals(&self) -> Option<&BTreeMap<u32, NameMap>> { self.locals.as_ref() }
+
+    /// Local names by function index (mutable).
+    pub fn locals_mut(&mut self) -> &mut Option<BTreeMap<u32, NameMap>> { &mut self.locals }
+}
+
+impl Deserialize for NameSection {
+    type Error = Error;
+
+    fn deserialize<R: io::Read>(reader: &mut R) -> Result<Self, Self::Error> {
+        let mut section = NameSection::default();
+        loop {
+            let mut id = [0u8; 1];
+            if reader.read(&mut id)? == 0 {
+                break;
+            }
+            let id = id[0];
+            let length: usize = VarUint32::deserialize(reader)?.into();
+            let mut payload = vec![0u8; length];
+            reader.read_exact(&mut payload[..])?;
+            let mut payload_reader = io::Cursor::new(&payload[..]);
+
+            match id {
+                MODULE_SUBSECTION => {
+                    section.module = Some(String::deserialize(&mut payload_reader)?);
+                },
+                FUNCTION_SUBSECTION => {
+                    section.functions = Some(NameMap::deserialize(&mut payload_reader)?);
+                },
+                LOCAL_SUBSECTION => {
+                    let count: u32 = VarUint32::deserialize(&mut payload_reader)?.into();
+                    let mut locals = BTreeMap::new();
+                    for _ in 0..count {
+                        let index: u32 = VarUint32::deserialize(&mut payload_reader)?.into();
+                        locals.insert(index, NameMap::deserialize(&mut payload_reader)?);
+                    }
+                    section.locals = Some(locals);
+                },
+                _ => {
+                    section.unknown.push((id, payload));
+                },
+            }
+        }
+        Ok(section)
+    }
+}
+
+impl Serialize for NameSection {
+    type Error = Error;
+
+    fn serialize<W: io::Write>(self, writer: &mut W) -> Result<(), Self::Error> {
+        if let Some(module) = self.module {
+            VarUint7::from(MODULE_SUBSECTION).serialize(writer)?;
+            let mut counted_writer = CountedWriter::new(writer);
+            module.serialize(&mut counted_writer)?;
+            counted_writer.done()?;
+        }
+        if let Some(functions) = self.functions {
+            VarUint7::from(FUNCTION_SUBSECTION).serialize(writer)?;
+            let mut counted_writer = CountedWriter::new(writer);
+            functions.serialize(&mut counted_writer)?;
+            counted_writer.done()?;
+        }
+        if let Some(locals) = self.locals {
+            VarUint7::from(LOCAL_SUBSECTION).serialize(writer)?;
+            let mut counted_writer = CountedWriter::new(writer);
+            VarUint32::from(locals.len()).serialize(&mut counted_writer)?;
+            for (index, names) in locals {
+                VarUint32::from(index).serialize(&mut counted_writer)?;
+                names.serialize(&mut counted_writer)?;
+            }
+            counted_writer.done()?;
+        }
+        for (id, payload) in self.unknown {
+            VarUint7::from(id).serialize(writer)?;
+            VarUint32::from(payload.len()).serialize(writer)?;
+            writer.write_all(&payload[..])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use alloc::{vec::Vec, borrow::ToOwned, collections::BTreeMap};
+    use super::super::{CustomSection, serialize, deserialize_buffer};
+    use super::{NameSection, NameMap};
+
+    #[test]
+    fn roundtrip() {
+        let mut functions = BTreeMap::new();
+        functions.insert(0, "main".to_owned());
+        functions.insert(3, "helper".to_owned());
+
+        let mut locals = BTreeMap::new();
+        let mut main_locals = BTreeMap::new();
+        main_locals.insert(1, "counter".to_owned());
+        locals.insert(0, NameMap::new(main_locals));
+
+        let mut names = NameSection::default();
+        *names.module_mut() = Some("contract".to_owned());
+        *names.functions_mut() = Some(NameMap::new(functions));
+        *names.locals_mut() = Some(locals);
+
+        let buf = serialize(names.clone()).expect("names to be serialized");
+        let deserialized: NameSection = deserialize_buffer(buf).expect("names to be deserialized");
+        assert_eq!(deserialized, names);
+        assert_eq!(deserialized.functions().and_then(|f| f.get(3)), Some("helper"));
+    }
+
+    #[test]
+    fn custom() {
+        let section = CustomSection::new("name".to_owned(), vec![
+            0x01,             // function names
+            0x06,             // 6 bytes
+            0x01,             // 1 name
+            0x02,             // index 2
+            0x03, b'f', b'o', b'o',
+        ]);
+        let names = NameSection::from_custom(&section)
+            .expect("section to be recognized")
+            .expect("names to be parsed");
+        assert_eq!(names.functions().and_then(|f| f.get(2)), Some("foo"));
+        assert_eq!(names.clone().into_custom().expect("names to be serialized").payload(), section.payload());
+
+        assert!(NameSection::from_custom(&CustomSection::new("other".to_owned(), Vec::new())).is_none());
+    }
+}
@@ -1,4 +1,6 @@
-use std::{io, fmt};
+use alloc::vec::Vec;
+use std::fmt;
+use io;
 use super::{
     Serialize, Deserialize, Error, VarUint7,
     VarUint1, VarUint32, CountedList, BlockType,
@@ -8,6 +10,7 @@ use super::{
 
 /// Collection of opcodes (usually inside a block section).
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Opcodes(Vec<Opcode>);
 
 impl Opcodes {
@@ -54,7 +57,8 @@ impl Deserialize for Opcodes {
 }
 
 /// Initialization expression.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct InitExpr(Vec<Opcode>);
 
 impl InitExpr {
@@ -102,6 +106,7 @@ impl Deserialize for InitExpr {
 
 /// Opcode
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(missing_docs)]
 pub enum Opcode {
     Unreachable,
@@ -311,6 +316,56 @@ impl Opcode {
             _ => false,
         }
     }
+
+    /// Category of the opcode.
+    pub fn category(&self) -> OpcodeCategory {
+        use self::Opcode::*;
+
+        match *self {
+            Unreachable | Nop | Block(_) | Loop(_) | If(_) | Else | End |
+            Br(_) | BrIf(_) | BrTable(_, _) | Return => OpcodeCategory::Control,
+
+            Call(_) | CallIndirect(_, _) => OpcodeCategory::Call,
+
+            Drop | Select => OpcodeCategory::Parametric,
+
+            GetLocal(_) | SetLocal(_) | TeeLocal(_) |
+            GetGlobal(_) | SetGlobal(_) => OpcodeCategory::Variable,
+
+            I32Load(_, _) | I64Load(_, _) | F32Load(_, _) | F64Load(_, _) |
+            I32Load8S(_, _) | I32Load8U(_, _) | I32Load16S(_, _) | I32Load16U(_, _) |
+            I64Load8S(_, _) | I64Load8U(_, _) | I64Load16S(_, _) | I64Load16U(_, _) |
+            I64Load32S(_, _) | I64Load32U(_, _) |
+            I32Store(_, _) | I64Store(_, _) | F32Store(_, _) | F64Store(_, _) |
+            I32Store8(_, _) | I32Store16(_, _) |
+            I64Store8(_, _) | I64Store16(_, _) | I64Store32(_, _) |
+            CurrentMemory(_) | GrowMemory(_) => OpcodeCategory::Memory,
+
+            I32Const(_) | I64Const(_) | F32Const(_) | F64Const(_) => OpcodeCategory::Constant,
+
+            _ => OpcodeCategory::Numeric,
+        }
+    }
+}
+
+/// Broad category of the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OpcodeCategory {
+    /// Structured control flow, branches, `nop` and `unreachable`.
+    Control,
+    /// Direct and indirect calls.
+    Call,
+    /// `drop` and `select`.
+    Parametric,
+    /// Local and global variable access.
+    Variable,
+    /// Linear memory loads, stores and size operators.
+    Memory,
+    /// Constants.
+    Constant,
+    /// Numeric comparisons, arithmetic and conversions.
+    Numeric,
 }
 
 impl Deserialize for Opcode {
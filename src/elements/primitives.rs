@@ -1,4 +1,5 @@
-use std::io;
+use alloc::{vec::Vec, string::String};
+use io;
 use byteorder::{LittleEndian, ByteOrder};
 use super::{Error, Deserialize, Serialize};
 
@@ -560,6 +561,7 @@ impl<I: Serialize<Error=::elements::Error>, T: IntoIterator<Item=I>> Serialize f
 #[cfg(test)]
 mod tests {
 
+    use alloc::vec::Vec;
     use super::super::{deserialize_buffer, Serialize};
     use super::{CountedList, VarInt7, VarUint32, VarInt32, VarInt64, VarUint64};
 
@@ -1,4 +1,5 @@
-use std::io;
+use alloc::{vec::Vec, string::String};
+use io;
 use super::{
     Serialize,
     Deserialize,
@@ -25,6 +26,7 @@ use super::types::Type;
 
 /// Section in the WebAssembly module.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Section {
     /// Section is unparsed.
     Unparsed {
@@ -182,12 +184,17 @@ impl Serialize for Section {
 
 /// Custom section
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CustomSection {
     name: String,
     payload: Vec<u8>,
 }
 
 impl CustomSection {
+    /// New custom section with given `name` and `payload`
+    pub fn new(name: String, payload: Vec<u8>) -> Self {
+        CustomSection { name: name, payload: payload }
+    }
 
     /// Name of the custom section
     pub fn name(&self) -> &str {
@@ -222,7 +229,7 @@ impl Deserialize for CustomSection {
         let mut payload = vec![0u8; payload_left as usize];
         reader.read_exact(&mut payload[..])?;
 
-        Ok(CustomSection { name: name, payload: payload })
+        Ok(CustomSection { name, payload })
     }
 }
 
@@ -230,7 +237,7 @@ impl Serialize for CustomSection {
     type Error = Error;
 
     fn serialize<W: io::Write>(self, writer: &mut W) -> Result<(), Self::Error> {
-        use std::io::Write;
+        use io::Write;
 
         let mut counted_writer = CountedWriter::new(writer);
         self.name.serialize(&mut counted_writer)?;
@@ -242,6 +249,7 @@ impl Serialize for CustomSection {
 
 /// Section with type declarations
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TypeSection(Vec<Type>);
 
 impl TypeSection {
@@ -290,6 +298,7 @@ impl Serialize for TypeSection {
 
 /// Section of the imports definition.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ImportSection(Vec<ImportEntry>);
 
 impl ImportSection {
@@ -352,6 +361,7 @@ impl Serialize for ImportSection {
 
 /// Section with function signatures definition.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FunctionSection(Vec<Func>);
 
 impl FunctionSection {
@@ -404,6 +414,7 @@ impl Serialize for FunctionSection {
 
 /// Section with table definition (currently only one is allowed).
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TableSection(Vec<TableType>);
 
 impl TableSection {
@@ -452,6 +463,7 @@ impl Serialize for TableSection {
 
 /// Section with table definition (currently only one entry is allowed).
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MemorySection(Vec<MemoryType>);
 
 impl MemorySection {
@@ -500,6 +512,7 @@ impl Serialize for MemorySection {
 
 /// Globals definition section.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GlobalSection(Vec<GlobalEntry>);
 
 impl GlobalSection {
@@ -548,6 +561,7 @@ impl Serialize for GlobalSection {
 
 /// List of exports definition.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExportSection(Vec<ExportEntry>);
 
 impl ExportSection {
@@ -596,6 +610,7 @@ impl Serialize for ExportSection {
 
 /// Section with function bodies of the module.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CodeSection(Vec<FuncBody>);
 
 impl CodeSection {
@@ -644,6 +659,7 @@ impl Serialize for CodeSection {
 
 /// Element entries section.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ElementSection(Vec<ElementSegment>);
 
 impl ElementSection {
@@ -692,6 +708,7 @@ impl Serialize for ElementSection {
 
 /// Data entries definitions.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DataSection(Vec<DataSegment>);
 
 impl DataSection {
@@ -738,7 +755,7 @@ impl Serialize for DataSection {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
 
     use super::super::{
@@ -1,8 +1,10 @@
-use std::io;
+use alloc::vec::Vec;
+use io;
 use super::{Deserialize, Serialize, Error, VarUint32, CountedList, InitExpr, CountedListWriter};
 
 /// Entry in the element section.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ElementSegment {
     index: u32,
     offset: InitExpr,
@@ -68,7 +70,8 @@ impl Serialize for ElementSegment {
 }
 
 /// Data segment definition.
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DataSegment {
     index: u32,
     offset: InitExpr,
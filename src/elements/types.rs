@@ -1,4 +1,6 @@
-use std::{io, fmt};
+use alloc::vec::Vec;
+use std::fmt;
+use io;
 use super::{
     Deserialize, Serialize, Error, VarUint7, VarInt7, VarUint1, CountedList,
     CountedListWriter
@@ -6,6 +8,7 @@ use super::{
 
 /// Type definition in types section. Currently can be only of the function type.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Type {
     /// Function type.
     Function(FunctionType),
@@ -31,6 +34,7 @@ impl Serialize for Type {
 
 /// Value type.
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ValueType {
     /// 32-bit signed integer
     I32,
@@ -86,6 +90,7 @@ impl fmt::Display for ValueType {
 
 /// Block type which is basically `ValueType` + NoResult (to define blocks that have no return type)
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BlockType {
     /// Value-type specified block type
     Value(ValueType),
@@ -128,6 +133,7 @@ impl Serialize for BlockType {
 
 /// Function signature type.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FunctionType {
     form: u8,
     params: Vec<ValueType>,
@@ -214,6 +220,7 @@ impl Serialize for FunctionType {
 
 /// Table element type.
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TableElementType {
     /// A reference to a function with any signature.
     AnyFunc,
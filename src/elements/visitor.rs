@@ -0,0 +1,410 @@
+//! Traversal of function bodies with per-category callbacks.
+//!
+//! `Visitor` walks a module (or a single function body) read-only, while
+//! `VisitorMut` additionally allows every visited opcode to be replaced,
+//! removed or surrounded by inserted opcodes through `Editor`.
+
+use alloc::vec::Vec;
+use std::mem;
+use super::{Module, Section, FuncBody, Opcode, Opcodes, OpcodeCategory};
+
+/// Location of the visited opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    /// Index of the function in the function index space (imported functions included).
+    pub func: u32,
+    /// Position of the opcode in the original (unedited) opcode sequence.
+    pub position: usize,
+    /// Block nesting depth. Opcodes of the function body itself are at depth `0`;
+    /// `Else` and `End` share the depth of the opcode which opened their block.
+    pub depth: u32,
+}
+
+/// Read-only visitor over modules and function bodies.
+///
+/// Every method has a default implementation, so implementors only override
+/// the callbacks they are interested in. Overriding `visit_opcode` replaces
+/// the dispatch to the per-category callbacks.
+pub trait Visitor {
+    /// Visit every function body of the module.
+    fn visit_module(&mut self, module: &Module) {
+        walk_module(self, module)
+    }
+
+    /// Visit all opcodes of the function body with index `func`.
+    fn visit_func_body(&mut self, func: u32, body: &FuncBody) {
+        walk_func_body(self, func, body)
+    }
+
+    /// Visit single opcode, dispatching to the callback of its category.
+    fn visit_opcode(&mut self, location: Location, opcode: &Opcode) {
+        walk_opcode(self, location, opcode)
+    }
+
+    /// Control flow opcode.
+    fn visit_control(&mut self, _location: Location, _opcode: &Opcode) { }
+
+    /// `call` or `call_indirect` opcode.
+    fn visit_call(&mut self, _location: Location, _opcode: &Opcode) { }
+
+    /// `drop` or `select` opcode.
+    fn visit_parametric(&mut self, _location: Location, _opcode: &Opcode) { }
+
+    /// Local or global variable access opcode.
+    fn visit_variable(&mut self, _location: Location, _opcode: &Opcode) { }
+
+    /// Linear memory opcode.
+    fn visit_memory(&mut self, _location: Location, _opcode: &Opcode) { }
+
+    /// Constant opcode.
+    fn visit_const(&mut self, _location: Location, _opcode: &Opcode) { }
+
+    /// Numeric opcode.
+    fn visit_numeric(&mut self, _location: Location, _opcode: &Opcode) { }
+}
+
+/// Visit every function body of the module with `visitor`.
+pub fn walk_module<V: Visitor + ?Sized>(visitor: &mut V, module: &Module) {
+    let imported_functions = imported_functions(module);
+    if let Some(code_section) = module.code_section() {
+        for (index, body) in code_section.bodies().iter().enumerate() {
+            visitor.visit_func_body(imported_functions + index as u32, body);
+        }
+    }
+}
+
+/// Visit every opcode of the function body with `visitor`.
+pub fn walk_func_body<V: Visitor + ?Sized>(visitor: &mut V, func: u32, body: &FuncBody) {
+    let mut depth = DepthTracker::default();
+    for (position, opcode) in body.code().elements().iter().enumerate() {
+        let location = Location { func, position, depth: depth.enter(opcode) };
+        visitor.visit_opcode(location, opcode);
+    }
+}
+
+/// Dispatch `opcode` to the callback of its category.
+pub fn walk_opcode<V: Visitor + ?Sized>(visitor: &mut V, location: Location, opcode: &Opcode) {
+    match opcode.category() {
+        OpcodeCategory::Control => visitor.visit_control(location, opcode),
+        OpcodeCategory::Call => visitor.visit_call(location, opcode),
+        OpcodeCategory::Parametric => visitor.visit_parametric(location, opcode),
+        OpcodeCategory::Variable => visitor.visit_variable(location, opcode),
+        OpcodeCategory::Memory => visitor.visit_memory(location, opcode),
+        OpcodeCategory::Constant => visitor.visit_const(location, opcode),
+        OpcodeCategory::Numeric => visitor.visit_numeric(location, opcode),
+    }
+}
+
+/// Pending edits of the currently visited opcode.
+///
+/// Edits are applied once the callback returns: opcodes inserted before
+/// and after are placed around the visited opcode (unless it is removed) and
+/// are not visited themselves. Positions reported by `Location` always refer
+/// to the original sequence, so edits never shift the opcodes still to be visited.
+#[derive(Debug)]
+pub struct Editor {
+    location: Location,
+    before: Vec<Opcode>,
+    after: Vec<Opcode>,
+    remove: bool,
+}
+
+impl Editor {
+    fn new(location: Location) -> Self {
+        Editor {
+            location,
+            before: Vec::new(),
+            after: Vec::new(),
+            remove: false,
+        }
+    }
+
+    /// Location of the visited opcode.
+    pub fn location(&self) -> Location { self.location }
+
+    /// Insert `opcode` before the visited opcode.
+    /// Subsequent calls insert after the previously inserted ones.
+    pub fn insert_before(&mut self, opcode: Opcode) {
+        self.before.push(opcode);
+    }
+
+    /// Insert `opcode` after the visited opcode.
+    /// Subsequent calls insert after the previously inserted ones.
+    pub fn insert_after(&mut self, opcode: Opcode) {
+        self.after.push(opcode);
+    }
+
+    /// Remove the visited opcode. Inserted opcodes are kept.
+    pub fn remove(&mut self) {
+        self.remove = true;
+    }
+
+    /// Is the visited opcode marked for removal.
+    pub fn is_removed(&self) -> bool { self.remove }
+
+    fn apply(self, opcode: Opcode, target: &mut Vec<Opcode>) {
+        target.extend(self.before);
+        if !self.remove {
+            target.push(opcode);
+        }
+        target.extend(self.after);
+    }
+}
+
+/// Mutable visitor over modules and function bodies.
+///
+/// Same as `Visitor`, but callbacks can change the visited opcode in place
+/// and use `Editor` to remove it or insert new opcodes around it. Keeping
+/// blocks balanced when inserting or removing `Block`, `Loop`, `If`, `Else`
+/// and `End` is up to the implementor.
+pub trait VisitorMut {
+    /// Visit every function body of the module.
+    fn visit_module(&mut self, module: &mut Module) {
+        walk_module_mut(self, module)
+    }
+
+    /// Visit all opcodes of the function body with index `func`.
+    fn visit_func_body(&mut self, func: u32, body: &mut FuncBody) {
+        walk_func_body_mut(self, func, body)
+    }
+
+    /// Visit single opcode, dispatching to the callback of its category.
+    fn visit_opcode(&mut self, editor: &mut Editor, opcode: &mut Opcode) {
+        walk_opcode_mut(self, editor, opcode)
+    }
+
+    /// Control flow opcode.
+    fn visit_control(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+
+    /// `call` or `call_indirect` opcode.
+    fn visit_call(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+
+    /// `drop` or `select` opcode.
+    fn visit_parametric(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+
+    /// Local or global variable access opcode.
+    fn visit_variable(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+
+    /// Linear memory opcode.
+    fn visit_memory(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+
+    /// Constant opcode.
+    fn visit_const(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+
+    /// Numeric opcode.
+    fn visit_numeric(&mut self, _editor: &mut Editor, _opcode: &mut Opcode) { }
+}
+
+/// Visit every function body of the module with mutable `visitor`.
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(visitor: &mut V, module: &mut Module) {
+    let imported_functions = imported_functions(module);
+    for section in module.sections_mut() {
+        if let Section::Code(ref mut code_section) = *section {
+            for (index, body) in code_section.bodies_mut().iter_mut().enumerate() {
+                visitor.visit_func_body(imported_functions + index as u32, body);
+            }
+        }
+    }
+}
+
+/// Visit every opcode of the function body with mutable `visitor`, applying requested edits.
+pub fn walk_func_body_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func: u32, body: &mut FuncBody) {
+    let opcodes = mem::take(body.code_mut().elements_mut());
+    let mut result = Vec::with_capacity(opcodes.len());
+    let mut depth = DepthTracker::default();
+
+    for (position, mut opcode) in opcodes.into_iter().enumerate() {
+        let location = Location { func, position, depth: depth.enter(&opcode) };
+        let mut editor = Editor::new(location);
+        visitor.visit_opcode(&mut editor, &mut opcode);
+        editor.apply(opcode, &mut result);
+    }
+
+    *body.code_mut() = Opcodes::new(result);
+}
+
+/// Dispatch `opcode` to the mutable callback of its category.
+pub fn walk_opcode_mut<V: VisitorMut + ?Sized>(visitor: &mut V, editor: &mut Editor, opcode: &mut Opcode) {
+    match opcode.category() {
+        OpcodeCategory::Control => visitor.visit_control(editor, opcode),
+        OpcodeCategory::Call => visitor.visit_call(editor, opcode),
+        OpcodeCategory::Parametric => visitor.visit_parametric(editor, opcode),
+        OpcodeCategory::Variable => visitor.visit_variable(editor, opcode),
+        OpcodeCategory::Memory => visitor.visit_memory(editor, opcode),
+        OpcodeCategory::Constant => visitor.visit_const(editor, opcode),
+        OpcodeCategory::Numeric => visitor.visit_numeric(editor, opcode),
+    }
+}
+
+fn imported_functions(module: &Module) -> u32 {
+    module.import_section().map(|s| s.functions() as u32).unwrap_or(0)
+}
+
+/// Tracks block nesting depth of the opcode sequence.
+#[derive(Default)]
+struct DepthTracker {
+    depth: u32,
+}
+
+impl DepthTracker {
+    /// Depth of `opcode`, updating the depth of the following opcodes.
+    fn enter(&mut self, opcode: &Opcode) -> u32 {
+        match *opcode {
+            Opcode::Block(_) | Opcode::Loop(_) | Opcode::If(_) => {
+                self.depth += 1;
+                self.depth - 1
+            },
+            Opcode::Else => self.depth.saturating_sub(1),
+            Opcode::End => {
+                self.depth = self.depth.saturating_sub(1);
+                self.depth
+            },
+            _ => self.depth,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+
+    use super::super::{Opcode, Opcodes, FuncBody, BlockType, deserialize_file};
+    use super::{Visitor, VisitorMut, Location, Editor, walk_func_body_mut};
+
+    fn body(opcodes: Vec<Opcode>) -> FuncBody {
+        FuncBody::new(Vec::new(), Opcodes::new(opcodes))
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        control: usize,
+        variable: usize,
+        numeric: usize,
+        depths: Vec<u32>,
+    }
+
+    impl Visitor for Counter {
+        fn visit_opcode(&mut self, location: Location, opcode: &Opcode) {
+            self.depths.push(location.depth);
+            super::walk_opcode(self, location, opcode)
+        }
+
+        fn visit_control(&mut self, _location: Location, _opcode: &Opcode) { self.control += 1; }
+        fn visit_variable(&mut self, _location: Location, _opcode: &Opcode) { self.variable += 1; }
+        fn visit_numeric(&mut self, _location: Location, _opcode: &Opcode) { self.numeric += 1; }
+    }
+
+    #[test]
+    fn categories_and_depth() {
+        use super::super::Opcode::*;
+
+        let body = body(vec![
+            Block(BlockType::NoResult),
+                GetLocal(0),
+                If(BlockType::NoResult),
+                    GetLocal(0),
+                    GetLocal(1),
+                    I32Add,
+                    SetLocal(0),
+                Else,
+                    Nop,
+                End,
+            End,
+            End,
+        ]);
+
+        let mut counter = Counter::default();
+        counter.visit_func_body(0, &body);
+
+        assert_eq!(counter.control, 7);
+        assert_eq!(counter.variable, 4);
+        assert_eq!(counter.numeric, 1);
+        assert_eq!(counter.depths, vec![0, 1, 1, 2, 2, 2, 2, 1, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn module_function_indices() {
+        struct Indices(Vec<u32>);
+
+        impl Visitor for Indices {
+            fn visit_func_body(&mut self, func: u32, _body: &FuncBody) {
+                self.0.push(func);
+            }
+        }
+
+        let module = deserialize_file("./res/cases/v1/test5.wasm").expect("Should be deserialized");
+        let imports = module.import_section().expect("import section to exist").functions() as u32;
+        let bodies = module.code_section().expect("code section to exist").bodies().len() as u32;
+
+        let mut indices = Indices(Vec::new());
+        indices.visit_module(&module);
+
+        assert_eq!(indices.0, (imports..imports + bodies).collect::<Vec<_>>());
+    }
+
+    struct InjectNop;
+
+    impl VisitorMut for InjectNop {
+        fn visit_control(&mut self, editor: &mut Editor, opcode: &mut Opcode) {
+            match *opcode {
+                Opcode::Block(_) | Opcode::If(_) => editor.insert_after(Opcode::Nop),
+                _ => {},
+            }
+        }
+    }
+
+    #[test]
+    fn insert() {
+        use super::super::Opcode::*;
+
+        let mut body = body(vec![
+            Block(BlockType::NoResult),
+                GetLocal(0),
+                If(BlockType::NoResult),
+                End,
+            End,
+            End,
+        ]);
+
+        InjectNop.visit_func_body(0, &mut body);
+
+        assert_eq!(body.code().elements(), &[
+            Block(BlockType::NoResult),
+                Nop,
+                GetLocal(0),
+                If(BlockType::NoResult),
+                    Nop,
+                End,
+            End,
+            End,
+        ]);
+    }
+
+    #[test]
+    fn remove_and_replace() {
+        use super::super::Opcode::*;
+
+        struct Rewrite;
+
+        impl VisitorMut for Rewrite {
+            fn visit_control(&mut self, editor: &mut Editor, opcode: &mut Opcode) {
+                if *opcode == Nop {
+                    editor.remove();
+                }
+            }
+
+            fn visit_const(&mut self, editor: &mut Editor, opcode: &mut Opcode) {
+                assert_eq!(editor.location().position, 2);
+                if let I32Const(ref mut value) = *opcode {
+                    *value += 1;
+                }
+                editor.insert_before(I32Const(0));
+                editor.insert_after(I32Add);
+            }
+        }
+
+        let mut body = body(vec![Nop, Nop, I32Const(41), Drop, End]);
+        walk_func_body_mut(&mut Rewrite, 0, &mut body);
+
+        assert_eq!(body.code().elements(), &[I32Const(0), I32Const(42), I32Add, Drop, End]);
+    }
+}
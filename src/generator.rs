@@ -0,0 +1,884 @@
+//! Random generation of valid modules.
+//!
+//! `Generator` builds a module from a seed or from arbitrary bytes (as given
+//! by a fuzzer). Every decision consumes the input, and an exhausted input
+//! reads as zeros, which always selects the smallest choice, so any input
+//! produces a module. Generated modules pass validation: function bodies are
+//! built from typed expression trees, direct calls only go to functions with
+//! lower indices, and loops have no back edges.
+
+use alloc::vec::Vec;
+use elements::{
+    Module, Section, Opcode, Opcodes, BlockType, ValueType, Type, FunctionType, ImportEntry, External,
+    GlobalType, GlobalEntry, InitExpr, MemoryType, TableType, ExportEntry, Internal, Func, FuncBody, Local,
+    ElementSegment, DataSegment, TypeSection, ImportSection, FunctionSection, TableSection, MemorySection,
+    GlobalSection, ExportSection, ElementSection, CodeSection, DataSection,
+};
+
+/// Limits and features of the generated modules.
+#[derive(Debug, Clone)]
+pub struct Config {
+    max_types: u32,
+    max_imports: u32,
+    max_functions: u32,
+    max_globals: u32,
+    max_data_segments: u32,
+    max_instructions: u32,
+    max_depth: u32,
+    floats: bool,
+    memory: bool,
+    table: bool,
+    start: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_types: 8,
+            max_imports: 4,
+            max_functions: 8,
+            max_globals: 4,
+            max_data_segments: 4,
+            max_instructions: 64,
+            max_depth: 4,
+            floats: true,
+            memory: true,
+            table: true,
+            start: true,
+        }
+    }
+}
+
+impl Config {
+    /// Default limits with all features enabled.
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// At most `max_types` function types.
+    pub fn with_max_types(mut self, max_types: u32) -> Self {
+        self.max_types = max_types;
+        self
+    }
+
+    /// At most `max_imports` imported functions and globals.
+    pub fn with_max_imports(mut self, max_imports: u32) -> Self {
+        self.max_imports = max_imports;
+        self
+    }
+
+    /// At most `max_functions` defined functions.
+    pub fn with_max_functions(mut self, max_functions: u32) -> Self {
+        self.max_functions = max_functions;
+        self
+    }
+
+    /// At most `max_globals` defined globals.
+    pub fn with_max_globals(mut self, max_globals: u32) -> Self {
+        self.max_globals = max_globals;
+        self
+    }
+
+    /// At most `max_data_segments` data segments.
+    pub fn with_max_data_segments(mut self, max_data_segments: u32) -> Self {
+        self.max_data_segments = max_data_segments;
+        self
+    }
+
+    /// About `max_instructions` opcodes in every function body.
+    pub fn with_max_instructions(mut self, max_instructions: u32) -> Self {
+        self.max_instructions = max_instructions;
+        self
+    }
+
+    /// At most `max_depth` nested expressions and blocks.
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Whether to use floating point types and opcodes.
+    pub fn with_floats(mut self, floats: bool) -> Self {
+        self.floats = floats;
+        self
+    }
+
+    /// Whether to define the memory.
+    pub fn with_memory(mut self, memory: bool) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Whether to define the table.
+    pub fn with_table(mut self, table: bool) -> Self {
+        self.table = table;
+        self
+    }
+
+    /// Whether to define the start function.
+    pub fn with_start(mut self, start: bool) -> Self {
+        self.start = start;
+        self
+    }
+}
+
+/// Generator of random valid modules.
+#[derive(Debug, Clone, Default)]
+pub struct Generator {
+    config: Config,
+}
+
+impl Generator {
+    /// New generator with the given configuration.
+    pub fn new(config: Config) -> Self {
+        Generator { config }
+    }
+
+    /// Module generated from the arbitrary bytes.
+    pub fn from_bytes(&self, data: &[u8]) -> Module {
+        ModuleGenerator::new(&self.config, Source::Bytes(data)).generate()
+    }
+
+    /// Module generated from the seed.
+    pub fn from_seed(&self, seed: u64) -> Module {
+        // xorshift state must not be zero
+        ModuleGenerator::new(&self.config, Source::Seed(seed | 1)).generate()
+    }
+}
+
+enum Source<'a> {
+    Bytes(&'a [u8]),
+    Seed(u64),
+}
+
+impl<'a> Source<'a> {
+    fn byte(&mut self) -> u8 {
+        match *self {
+            Source::Bytes(ref mut data) => match data.split_first() {
+                Some((&byte, rest)) => {
+                    *data = rest;
+                    byte
+                },
+                None => 0,
+            },
+            Source::Seed(ref mut state) => {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                (*state >> 24) as u8
+            },
+        }
+    }
+
+    fn u32(&mut self) -> u32 {
+        (0..4).fold(0, |value, _| (value << 8) | self.byte() as u32)
+    }
+
+    fn u64(&mut self) -> u64 {
+        ((self.u32() as u64) << 32) | self.u32() as u64
+    }
+
+    /// Number in `0..n`, zero for the empty range.
+    fn below(&mut self, n: u32) -> u32 {
+        match n {
+            0 | 1 => 0,
+            n if n <= 256 => self.byte() as u32 % n,
+            n => self.u32() % n,
+        }
+    }
+
+    fn chance(&mut self) -> bool {
+        self.byte() & 1 == 1
+    }
+
+    fn choose<T: Clone>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u32) as usize].clone()
+    }
+}
+
+#[derive(Clone)]
+struct Global {
+    value_type: ValueType,
+    mutable: bool,
+}
+
+struct ModuleGenerator<'a> {
+    config: &'a Config,
+    source: Source<'a>,
+    value_types: Vec<ValueType>,
+    types: Vec<FunctionType>,
+    /// Type of every function, imported first.
+    functions: Vec<u32>,
+    imported_functions: u32,
+    globals: Vec<Global>,
+    memory: bool,
+    table: bool,
+}
+
+impl<'a> ModuleGenerator<'a> {
+    fn new(config: &'a Config, source: Source<'a>) -> Self {
+        let mut value_types = vec![ValueType::I32, ValueType::I64];
+        if config.floats {
+            value_types.extend_from_slice(&[ValueType::F32, ValueType::F64]);
+        }
+        ModuleGenerator {
+            config,
+            source,
+            value_types,
+            types: Vec::new(),
+            functions: Vec::new(),
+            imported_functions: 0,
+            globals: Vec::new(),
+            memory: false,
+            table: false,
+        }
+    }
+
+    fn value_type(&mut self) -> ValueType {
+        let value_types = self.value_types.clone();
+        self.source.choose(&value_types)
+    }
+
+    fn constant(&mut self, value_type: ValueType) -> Opcode {
+        match value_type {
+            ValueType::I32 => Opcode::I32Const(self.source.u32() as i32),
+            ValueType::I64 => Opcode::I64Const(self.source.u64() as i64),
+            ValueType::F32 => Opcode::F32Const(self.source.u32()),
+            ValueType::F64 => Opcode::F64Const(self.source.u64()),
+        }
+    }
+
+    fn generate(mut self) -> Module {
+        let mut sections = Vec::new();
+
+        let type_count = 1 + self.source.below(self.config.max_types);
+        for _ in 0..type_count {
+            let params = (0..self.source.below(4)).map(|_| self.value_type()).collect();
+            let result = if self.source.chance() { Some(self.value_type()) } else { None };
+            self.types.push(FunctionType::new(params, result));
+        }
+
+        let defined_functions = self.source.below(self.config.max_functions + 1);
+        let import_count = self.source.below(self.config.max_imports + 1);
+        let mut imports = Vec::new();
+        let mut imported_globals = Vec::new();
+        for index in 0..import_count {
+            if self.source.chance() {
+                let value_type = self.value_type();
+                imported_globals.push(Global { value_type, mutable: false });
+                imports.push(ImportEntry::new(
+                    "env".into(),
+                    format!("global{}", index),
+                    External::Global(GlobalType::new(value_type, false)),
+                ));
+            } else {
+                imports.push(ImportEntry::new("env".into(), format!("func{}", index), External::Function(0)));
+            }
+        }
+        self.imported_functions = imports.iter()
+            .filter(|i| matches!(*i.external(), External::Function(_)))
+            .count() as u32;
+        let total_functions = self.imported_functions + defined_functions;
+        for import in &mut imports {
+            if let External::Function(ref mut type_ref) = *import.external_mut() {
+                *type_ref = self.source.below(type_count);
+                self.functions.push(*type_ref);
+            }
+        }
+        for _ in 0..defined_functions {
+            let type_ref = self.source.below(type_count);
+            self.functions.push(type_ref);
+        }
+        self.globals = imported_globals;
+
+        let mut table_size = 0;
+        if self.config.table && self.source.chance() {
+            table_size = self.source.below(8);
+            self.table = true;
+        }
+        let mut memory_pages = 0;
+        if self.config.memory && self.source.chance() {
+            memory_pages = self.source.below(3);
+            self.memory = true;
+        }
+
+        let mut globals = Vec::new();
+        for _ in 0..self.source.below(self.config.max_globals + 1) {
+            let value_type = self.value_type();
+            let mutable = self.source.chance();
+            let init = self.constant(value_type);
+            globals.push(GlobalEntry::new(GlobalType::new(value_type, mutable), InitExpr::new(vec![init, Opcode::End])));
+            self.globals.push(Global { value_type, mutable });
+        }
+
+        let mut bodies = Vec::new();
+        for index in self.imported_functions..total_functions {
+            let body = self.function_body(index);
+            bodies.push(body);
+        }
+
+        let mut exports = Vec::new();
+        for index in 0..total_functions {
+            if self.source.chance() {
+                exports.push(ExportEntry::new(format!("func{}", index), Internal::Function(index)));
+            }
+        }
+        for index in 0..self.globals.len() as u32 {
+            if !self.globals[index as usize].mutable && self.source.chance() {
+                exports.push(ExportEntry::new(format!("global{}", index), Internal::Global(index)));
+            }
+        }
+        if self.memory && self.source.chance() {
+            exports.push(ExportEntry::new("memory".into(), Internal::Memory(0)));
+        }
+        if self.table && self.source.chance() {
+            exports.push(ExportEntry::new("table".into(), Internal::Table(0)));
+        }
+
+        let start_candidates: Vec<u32> = (self.imported_functions..total_functions)
+            .filter(|&f| {
+                let signature = &self.types[self.functions[f as usize] as usize];
+                signature.params().is_empty() && signature.return_type().is_none()
+            })
+            .collect();
+        let start = if self.config.start && !start_candidates.is_empty() && self.source.chance() {
+            Some(self.source.choose(&start_candidates))
+        } else {
+            None
+        };
+
+        let mut elements = Vec::new();
+        if table_size > 0 && total_functions > 0 {
+            let members = (0..table_size).map(|_| self.source.below(total_functions)).collect();
+            elements.push(ElementSegment::new(0, InitExpr::new(vec![Opcode::I32Const(0), Opcode::End]), members));
+        }
+
+        let mut data = Vec::new();
+        if memory_pages > 0 {
+            for _ in 0..self.source.below(self.config.max_data_segments + 1) {
+                let len = self.source.below(16);
+                let offset = self.source.below(memory_pages * 65536 - len);
+                let value = (0..len).map(|_| self.source.byte()).collect();
+                data.push(DataSegment::new(0, InitExpr::new(vec![Opcode::I32Const(offset as i32), Opcode::End]), value));
+            }
+        }
+
+        sections.push(Section::Type(TypeSection::with_types(self.types.iter().cloned().map(Type::Function).collect())));
+        if !imports.is_empty() {
+            sections.push(Section::Import(ImportSection::with_entries(imports)));
+        }
+        if defined_functions > 0 {
+            let entries = self.functions[self.imported_functions as usize..].iter().map(|&t| Func::new(t)).collect();
+            sections.push(Section::Function(FunctionSection::with_entries(entries)));
+        }
+        if self.table {
+            let maximum = if self.source.chance() { Some(table_size + self.source.below(4)) } else { None };
+            sections.push(Section::Table(TableSection::with_entries(vec![TableType::new(table_size, maximum)])));
+        }
+        if self.memory {
+            let maximum = if self.source.chance() { Some(memory_pages + self.source.below(3)) } else { None };
+            sections.push(Section::Memory(MemorySection::with_entries(vec![MemoryType::new(memory_pages, maximum)])));
+        }
+        if !globals.is_empty() {
+            sections.push(Section::Global(GlobalSection::with_entries(globals)));
+        }
+        if !exports.is_empty() {
+            sections.push(Section::Export(ExportSection::with_entries(exports)));
+        }
+        if let Some(start) = start {
+            sections.push(Section::Start(start));
+        }
+        if !elements.is_empty() {
+            sections.push(Section::Element(ElementSection::with_entries(elements)));
+        }
+        if !bodies.is_empty() {
+            sections.push(Section::Code(CodeSection::with_bodies(bodies)));
+        }
+        if !data.is_empty() {
+            sections.push(Section::Data(DataSection::with_entries(data)));
+        }
+
+        Module::new(sections)
+    }
+
+    fn function_body(&mut self, index: u32) -> FuncBody {
+        let signature = self.types[self.functions[index as usize] as usize].clone();
+        let mut locals = signature.params().to_vec();
+        let mut declared = Vec::new();
+        for _ in 0..self.source.below(4) {
+            let count = 1 + self.source.below(3);
+            let value_type = self.value_type();
+            locals.extend((0..count).map(|_| value_type));
+            declared.push(Local::new(count, value_type));
+        }
+
+        let mut body = BodyGenerator {
+            module: self,
+            function: index,
+            locals,
+            code: Vec::new(),
+            fuel: 0,
+        };
+        body.fuel = body.module.config.max_instructions;
+        while body.fuel > 0 && body.module.source.chance() {
+            body.statement(0);
+        }
+        if let Some(result) = signature.return_type() {
+            body.expression(result, 0);
+        }
+        body.code.push(Opcode::End);
+
+        FuncBody::new(declared, Opcodes::new(body.code))
+    }
+}
+
+/// Memory opcode taking the alignment and the offset, with its natural size.
+type MemoryAccess = (fn(u32, u32) -> Opcode, u32);
+
+struct BodyGenerator<'a, 'b: 'a> {
+    module: &'a mut ModuleGenerator<'b>,
+    function: u32,
+    locals: Vec<ValueType>,
+    code: Vec<Opcode>,
+    fuel: u32,
+}
+
+impl<'a, 'b> BodyGenerator<'a, 'b> {
+    fn emit(&mut self, opcode: Opcode) {
+        self.fuel = self.fuel.saturating_sub(1);
+        self.code.push(opcode);
+    }
+
+    fn exhausted(&self, depth: u32) -> bool {
+        self.fuel == 0 || depth >= self.module.config.max_depth
+    }
+
+    fn locals_of(&self, value_type: ValueType) -> Vec<u32> {
+        (0..self.locals.len() as u32).filter(|&l| self.locals[l as usize] == value_type).collect()
+    }
+
+    fn globals_of(&self, value_type: ValueType, mutable: bool) -> Vec<u32> {
+        (0..self.module.globals.len() as u32)
+            .filter(|&g| {
+                let global = &self.module.globals[g as usize];
+                global.value_type == value_type && (!mutable || global.mutable)
+            })
+            .collect()
+    }
+
+    /// Functions callable directly, that is with lower indices, returning `result`.
+    fn callees(&self, result: Option<ValueType>) -> Vec<u32> {
+        (0..self.function)
+            .filter(|&f| self.module.types[self.module.functions[f as usize] as usize].return_type() == result)
+            .collect()
+    }
+
+    fn call(&mut self, func: u32, depth: u32) {
+        let signature = self.module.types[self.module.functions[func as usize] as usize].clone();
+        for &param in signature.params() {
+            self.expression(param, depth + 1);
+        }
+        self.emit(Opcode::Call(func));
+    }
+
+    fn call_indirect(&mut self, type_ref: u32, depth: u32) {
+        let signature = self.module.types[type_ref as usize].clone();
+        for &param in signature.params() {
+            self.expression(param, depth + 1);
+        }
+        self.expression(ValueType::I32, depth + 1);
+        self.emit(Opcode::CallIndirect(type_ref, false));
+    }
+
+    fn memory_immediates(&mut self, size: u32) -> (u32, u32) {
+        // alignment is the power of two, at most the natural one
+        let natural = size.trailing_zeros();
+        (self.module.source.below(natural + 1), self.module.source.below(16))
+    }
+
+    /// Opcodes leaving the single value of `value_type` on the stack.
+    fn expression(&mut self, value_type: ValueType, depth: u32) {
+        if self.exhausted(depth) {
+            return self.leaf(value_type);
+        }
+
+        match self.module.source.below(14) {
+            0 => self.leaf(value_type),
+            1 => {
+                let operator = self.unary(value_type);
+                self.expression(value_type, depth + 1);
+                self.emit(operator);
+            },
+            2 => {
+                let operator = self.binary(value_type);
+                self.expression(value_type, depth + 1);
+                self.expression(value_type, depth + 1);
+                self.emit(operator);
+            },
+            3 if value_type == ValueType::I32 => {
+                let operand = self.module.value_type();
+                let operator = self.comparison(operand);
+                self.expression(operand, depth + 1);
+                self.expression(operand, depth + 1);
+                self.emit(operator);
+            },
+            4 => {
+                let conversions = self.conversions(value_type);
+                let (operand, operator) = self.module.source.choose(&conversions);
+                self.expression(operand, depth + 1);
+                self.emit(operator);
+            },
+            5 => {
+                let callees = self.callees(Some(value_type));
+                if callees.is_empty() {
+                    return self.leaf(value_type);
+                }
+                let func = self.module.source.choose(&callees);
+                self.call(func, depth);
+            },
+            6 => {
+                self.expression(ValueType::I32, depth + 1);
+                self.emit(Opcode::If(BlockType::Value(value_type)));
+                self.block_body(Some(value_type), depth + 1);
+                self.emit(Opcode::Else);
+                self.block_body(Some(value_type), depth + 1);
+                self.emit(Opcode::End);
+            },
+            7 => {
+                self.emit(Opcode::Block(BlockType::Value(value_type)));
+                self.block_body(Some(value_type), depth + 1);
+                self.emit(Opcode::End);
+            },
+            8 => {
+                self.expression(value_type, depth + 1);
+                self.expression(value_type, depth + 1);
+                self.expression(ValueType::I32, depth + 1);
+                self.emit(Opcode::Select);
+            },
+            9 if self.module.memory => {
+                let (opcode, size) = self.load(value_type);
+                let (align, offset) = self.memory_immediates(size);
+                self.expression(ValueType::I32, depth + 1);
+                self.emit(opcode(align, offset));
+            },
+            10 => {
+                let locals = self.locals_of(value_type);
+                if locals.is_empty() {
+                    return self.leaf(value_type);
+                }
+                let local = self.module.source.choose(&locals);
+                self.expression(value_type, depth + 1);
+                self.emit(Opcode::TeeLocal(local));
+            },
+            11 if self.module.table => {
+                let types: Vec<u32> = (0..self.module.types.len() as u32)
+                    .filter(|&t| self.module.types[t as usize].return_type() == Some(value_type))
+                    .collect();
+                if types.is_empty() {
+                    return self.leaf(value_type);
+                }
+                let type_ref = self.module.source.choose(&types);
+                self.call_indirect(type_ref, depth);
+            },
+            12 if self.module.memory && value_type == ValueType::I32 => {
+                if self.module.source.chance() {
+                    self.emit(Opcode::CurrentMemory(false));
+                } else {
+                    self.expression(ValueType::I32, depth + 1);
+                    self.emit(Opcode::GrowMemory(false));
+                }
+            },
+            _ => self.leaf(value_type),
+        }
+    }
+
+    /// Constant, local or global of `value_type`.
+    fn leaf(&mut self, value_type: ValueType) {
+        let variables: Vec<Opcode> = match self.module.source.below(3) {
+            1 => self.locals_of(value_type).into_iter().map(Opcode::GetLocal).collect(),
+            2 => self.globals_of(value_type, false).into_iter().map(Opcode::GetGlobal).collect(),
+            _ => Vec::new(),
+        };
+        let opcode = if variables.is_empty() {
+            self.module.constant(value_type)
+        } else {
+            self.module.source.choose(&variables)
+        };
+        self.emit(opcode);
+    }
+
+    /// Opcodes leaving the stack as it is.
+    fn statement(&mut self, depth: u32) {
+        if self.exhausted(depth) {
+            return self.emit(Opcode::Nop);
+        }
+
+        match self.module.source.below(9) {
+            0 => self.emit(Opcode::Nop),
+            1 => {
+                let value_type = self.module.value_type();
+                self.expression(value_type, depth + 1);
+                self.emit(Opcode::Drop);
+            },
+            2 => {
+                let value_type = self.module.value_type();
+                let locals = self.locals_of(value_type);
+                if locals.is_empty() {
+                    return self.emit(Opcode::Nop);
+                }
+                let local = self.module.source.choose(&locals);
+                self.expression(value_type, depth + 1);
+                self.emit(Opcode::SetLocal(local));
+            },
+            3 => {
+                let value_type = self.module.value_type();
+                let globals = self.globals_of(value_type, true);
+                if globals.is_empty() {
+                    return self.emit(Opcode::Nop);
+                }
+                let global = self.module.source.choose(&globals);
+                self.expression(value_type, depth + 1);
+                self.emit(Opcode::SetGlobal(global));
+            },
+            4 if self.module.memory => {
+                let value_type = self.module.value_type();
+                let (opcode, size) = self.store(value_type);
+                let (align, offset) = self.memory_immediates(size);
+                self.expression(ValueType::I32, depth + 1);
+                self.expression(value_type, depth + 1);
+                self.emit(opcode(align, offset));
+            },
+            5 => {
+                let callees = self.callees(None);
+                if callees.is_empty() {
+                    return self.emit(Opcode::Nop);
+                }
+                let func = self.module.source.choose(&callees);
+                self.call(func, depth);
+            },
+            6 => {
+                self.expression(ValueType::I32, depth + 1);
+                self.emit(Opcode::If(BlockType::NoResult));
+                self.block_body(None, depth + 1);
+                if self.module.source.chance() {
+                    self.emit(Opcode::Else);
+                    self.block_body(None, depth + 1);
+                }
+                self.emit(Opcode::End);
+            },
+            7 => {
+                self.emit(Opcode::Block(BlockType::NoResult));
+                self.block_body(None, depth + 1);
+                self.expression(ValueType::I32, depth + 1);
+                self.emit(Opcode::BrIf(0));
+                self.block_body(None, depth + 1);
+                self.emit(Opcode::End);
+            },
+            8 => {
+                // without branches to the loop label, so it runs once
+                self.emit(Opcode::Loop(BlockType::NoResult));
+                self.block_body(None, depth + 1);
+                self.emit(Opcode::End);
+            },
+            _ => self.emit(Opcode::Nop),
+        }
+    }
+
+    /// Statements followed by the expression of `result`, if any.
+    fn block_body(&mut self, result: Option<ValueType>, depth: u32) {
+        while !self.exhausted(depth) && self.module.source.chance() {
+            self.statement(depth);
+        }
+        if let Some(value_type) = result {
+            self.expression(value_type, depth);
+        }
+    }
+
+    fn unary(&mut self, value_type: ValueType) -> Opcode {
+        let operators: &[Opcode] = match value_type {
+            ValueType::I32 => &[Opcode::I32Clz, Opcode::I32Ctz, Opcode::I32Popcnt, Opcode::I32Eqz],
+            ValueType::I64 => &[Opcode::I64Clz, Opcode::I64Ctz, Opcode::I64Popcnt],
+            ValueType::F32 => &[
+                Opcode::F32Abs, Opcode::F32Neg, Opcode::F32Ceil, Opcode::F32Floor,
+                Opcode::F32Trunc, Opcode::F32Nearest, Opcode::F32Sqrt,
+            ],
+            ValueType::F64 => &[
+                Opcode::F64Abs, Opcode::F64Neg, Opcode::F64Ceil, Opcode::F64Floor,
+                Opcode::F64Trunc, Opcode::F64Nearest, Opcode::F64Sqrt,
+            ],
+        };
+        self.module.source.choose(operators)
+    }
+
+    fn binary(&mut self, value_type: ValueType) -> Opcode {
+        let operators: &[Opcode] = match value_type {
+            ValueType::I32 => &[
+                Opcode::I32Add, Opcode::I32Sub, Opcode::I32Mul, Opcode::I32DivS, Opcode::I32DivU,
+                Opcode::I32RemS, Opcode::I32RemU, Opcode::I32And, Opcode::I32Or, Opcode::I32Xor,
+                Opcode::I32Shl, Opcode::I32ShrS, Opcode::I32ShrU, Opcode::I32Rotl, Opcode::I32Rotr,
+            ],
+            ValueType::I64 => &[
+                Opcode::I64Add, Opcode::I64Sub, Opcode::I64Mul, Opcode::I64DivS, Opcode::I64DivU,
+                Opcode::I64RemS, Opcode::I64RemU, Opcode::I64And, Opcode::I64Or, Opcode::I64Xor,
+                Opcode::I64Shl, Opcode::I64ShrS, Opcode::I64ShrU, Opcode::I64Rotl, Opcode::I64Rotr,
+            ],
+            ValueType::F32 => &[
+                Opcode::F32Add, Opcode::F32Sub, Opcode::F32Mul, Opcode::F32Div,
+                Opcode::F32Min, Opcode::F32Max, Opcode::F32Copysign,
+            ],
+            ValueType::F64 => &[
+                Opcode::F64Add, Opcode::F64Sub, Opcode::F64Mul, Opcode::F64Div,
+                Opcode::F64Min, Opcode::F64Max, Opcode::F64Copysign,
+            ],
+        };
+        self.module.source.choose(operators)
+    }
+
+    /// Comparison of two `operand` values producing `i32`.
+    fn comparison(&mut self, operand: ValueType) -> Opcode {
+        let operators: &[Opcode] = match operand {
+            ValueType::I32 => &[
+                Opcode::I32Eq, Opcode::I32Ne, Opcode::I32LtS, Opcode::I32LtU, Opcode::I32GtS,
+                Opcode::I32GtU, Opcode::I32LeS, Opcode::I32LeU, Opcode::I32GeS, Opcode::I32GeU,
+            ],
+            ValueType::I64 => &[
+                Opcode::I64Eq, Opcode::I64Ne, Opcode::I64LtS, Opcode::I64LtU, Opcode::I64GtS,
+                Opcode::I64GtU, Opcode::I64LeS, Opcode::I64LeU, Opcode::I64GeS, Opcode::I64GeU,
+            ],
+            ValueType::F32 => &[Opcode::F32Eq, Opcode::F32Ne, Opcode::F32Lt, Opcode::F32Gt, Opcode::F32Le, Opcode::F32Ge],
+            ValueType::F64 => &[Opcode::F64Eq, Opcode::F64Ne, Opcode::F64Lt, Opcode::F64Gt, Opcode::F64Le, Opcode::F64Ge],
+        };
+        self.module.source.choose(operators)
+    }
+
+    /// Operand types and opcodes converting them to `value_type`, traps excluded.
+    fn conversions(&self, value_type: ValueType) -> Vec<(ValueType, Opcode)> {
+        let conversions: &[(ValueType, Opcode)] = match value_type {
+            ValueType::I32 => &[
+                (ValueType::I64, Opcode::I32WarpI64),
+                (ValueType::I64, Opcode::I64Eqz),
+                (ValueType::F32, Opcode::I32ReinterpretF32),
+            ],
+            ValueType::I64 => &[
+                (ValueType::I32, Opcode::I64ExtendSI32),
+                (ValueType::I32, Opcode::I64ExtendUI32),
+                (ValueType::F64, Opcode::I64ReinterpretF64),
+            ],
+            ValueType::F32 => &[
+                (ValueType::I32, Opcode::F32ConvertSI32),
+                (ValueType::I32, Opcode::F32ConvertUI32),
+                (ValueType::I64, Opcode::F32ConvertSI64),
+                (ValueType::I64, Opcode::F32ConvertUI64),
+                (ValueType::F64, Opcode::F32DemoteF64),
+                (ValueType::I32, Opcode::F32ReinterpretI32),
+            ],
+            ValueType::F64 => &[
+                (ValueType::I32, Opcode::F64ConvertSI32),
+                (ValueType::I32, Opcode::F64ConvertUI32),
+                (ValueType::I64, Opcode::F64ConvertSI64),
+                (ValueType::I64, Opcode::F64ConvertUI64),
+                (ValueType::F32, Opcode::F64PromoteF32),
+                (ValueType::I64, Opcode::F64ReinterpretI64),
+            ],
+        };
+        conversions.iter()
+            .filter(|&&(operand, _)| self.module.value_types.contains(&operand))
+            .cloned()
+            .collect()
+    }
+
+    fn load(&mut self, value_type: ValueType) -> MemoryAccess {
+        let loads: &[MemoryAccess] = match value_type {
+            ValueType::I32 => &[
+                (Opcode::I32Load, 4), (Opcode::I32Load8S, 1), (Opcode::I32Load8U, 1),
+                (Opcode::I32Load16S, 2), (Opcode::I32Load16U, 2),
+            ],
+            ValueType::I64 => &[
+                (Opcode::I64Load, 8), (Opcode::I64Load8S, 1), (Opcode::I64Load8U, 1),
+                (Opcode::I64Load16S, 2), (Opcode::I64Load16U, 2), (Opcode::I64Load32S, 4), (Opcode::I64Load32U, 4),
+            ],
+            ValueType::F32 => &[(Opcode::F32Load, 4)],
+            ValueType::F64 => &[(Opcode::F64Load, 8)],
+        };
+        self.module.source.choose(loads)
+    }
+
+    fn store(&mut self, value_type: ValueType) -> MemoryAccess {
+        let stores: &[MemoryAccess] = match value_type {
+            ValueType::I32 => &[(Opcode::I32Store, 4), (Opcode::I32Store8, 1), (Opcode::I32Store16, 2)],
+            ValueType::I64 => &[
+                (Opcode::I64Store, 8), (Opcode::I64Store8, 1), (Opcode::I64Store16, 2), (Opcode::I64Store32, 4),
+            ],
+            ValueType::F32 => &[(Opcode::F32Store, 4)],
+            ValueType::F64 => &[(Opcode::F64Store, 8)],
+        };
+        self.module.source.choose(stores)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+
+    use elements::{self, Module, Section, ValueType, Type};
+    use validation::validate_module;
+    use super::{Generator, Config};
+
+    fn check(module: Module) {
+        validate_module(&module).expect("generated module to be valid");
+        let bytes = elements::serialize(module.clone()).expect("serialization to succeed");
+        let decoded: Module = elements::deserialize_buffer(bytes.clone()).expect("deserialization to succeed");
+        assert_eq!(elements::serialize(decoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn seeds() {
+        let generator = Generator::default();
+        for seed in 0..200 {
+            check(generator.from_seed(seed));
+        }
+        assert_eq!(
+            elements::serialize(generator.from_seed(7)).unwrap(),
+            elements::serialize(generator.from_seed(7)).unwrap(),
+        );
+    }
+
+    #[test]
+    fn bytes() {
+        let generator = Generator::default();
+        check(generator.from_bytes(&[]));
+        check(generator.from_bytes(&[0xff; 64]));
+        let mut data = Vec::new();
+        for i in 0..4096u32 {
+            data.push((i.wrapping_mul(2654435761) >> 13) as u8);
+        }
+        for start in 0..64 {
+            check(generator.from_bytes(&data[start * 8..]));
+        }
+    }
+
+    #[test]
+    fn config() {
+        let generator = Generator::new(Config::new()
+            .with_floats(false)
+            .with_memory(false)
+            .with_table(false)
+            .with_max_imports(0)
+            .with_max_instructions(200)
+            .with_max_depth(8));
+        for seed in 0..100 {
+            let module = generator.from_seed(seed);
+            assert!(module.import_section().is_none());
+            assert!(module.memory_section().is_none());
+            assert!(module.table_section().is_none());
+            for section in module.sections() {
+                if let Section::Type(ref types) = *section {
+                    for &Type::Function(ref signature) in types.types() {
+                        assert!(signature.params().iter().chain(signature.return_type().iter())
+                            .all(|t| *t == ValueType::I32 || *t == ValueType::I64));
+                    }
+                }
+            }
+            check(module);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Serial i/o used by the serialization of the elements.
+//!
+//! With the `std` feature these are the `std::io` items. Without it, this is
+//! a minimal replacement over byte slices and `Vec<u8>`, so the elements and
+//! the builders can be used in `no_std` environments.
+
+#[cfg(feature = "std")]
+pub use std::io::{Read, Write, Cursor, Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::imp::{Read, Write, Cursor, Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+mod imp {
+    use std::{cmp, fmt, result};
+    use alloc::vec::Vec;
+
+    /// Kind of the i/o error.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorKind {
+        /// Input ended before all requested bytes were read.
+        UnexpectedEof,
+        /// Output cannot accept more bytes.
+        WriteZero,
+        /// Any other error.
+        Other,
+    }
+
+    /// I/O error.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: &'static str,
+    }
+
+    impl Error {
+        /// New error of the given kind with the message.
+        pub fn new(kind: ErrorKind, message: &'static str) -> Self {
+            Error { kind, message }
+        }
+
+        /// Kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            let message = match kind {
+                ErrorKind::UnexpectedEof => "unexpected end of input",
+                ErrorKind::WriteZero => "failed to write whole buffer",
+                ErrorKind::Other => "other error",
+            };
+            Error { kind, message }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    /// Result of the i/o operation.
+    pub type Result<T> = result::Result<T, Error>;
+
+    /// Source of bytes.
+    pub trait Read {
+        /// Read at most `buf.len()` bytes, returning the number of bytes read.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Read exactly `buf.len()` bytes.
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf)? {
+                    0 => return Err(ErrorKind::UnexpectedEof.into()),
+                    n => buf = &mut buf[n..],
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Sink of bytes.
+    pub trait Write {
+        /// Write at most `buf.len()` bytes, returning the number of bytes written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Flush the buffered bytes.
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        /// Write all bytes of `buf`.
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf)? {
+                    0 => return Err(ErrorKind::WriteZero.into()),
+                    n => buf = &buf[n..],
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = cmp::min(buf.len(), self.len());
+            let (head, tail) = self.split_at(len);
+            buf[..len].copy_from_slice(head);
+            *self = tail;
+            Ok(len)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Reader over the in-memory buffer.
+    #[derive(Debug, Clone, Default)]
+    pub struct Cursor<T> {
+        inner: T,
+        position: usize,
+    }
+
+    impl<T> Cursor<T> {
+        /// New cursor at the start of the buffer.
+        pub fn new(inner: T) -> Self {
+            Cursor { inner, position: 0 }
+        }
+
+        /// Current position in the buffer.
+        pub fn position(&self) -> u64 {
+            self.position as u64
+        }
+
+        /// Move to the position in the buffer.
+        pub fn set_position(&mut self, position: u64) {
+            self.position = position as usize;
+        }
+
+        /// Reference to the buffer.
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        /// Destroy the cursor returning the buffer.
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let data = self.inner.as_ref();
+            let start = cmp::min(self.position, data.len());
+            let len = (&data[start..]).read(buf)?;
+            self.position = start + len;
+            Ok(len)
+        }
+    }
+}
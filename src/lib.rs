@@ -1,27 +1,48 @@
 //! WebAssembly format library
 
 #![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate log;
 extern crate byteorder;
+#[cfg(feature = "std")]
 extern crate parking_lot;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
+pub mod io;
 pub mod elements;
 pub mod builder;
+pub mod generator;
+#[cfg(feature = "std")]
 pub mod interpreter;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod transform;
+#[cfg(feature = "std")]
 mod validation;
+#[cfg(feature = "std")]
 mod common;
 
 pub use elements::{
     Error as SerializationError,
     deserialize_buffer,
-    deserialize_file,
     serialize,
-    serialize_to_file,
     peek_size,
 };
 
+#[cfg(feature = "std")]
+pub use elements::{deserialize_file, serialize_to_file};
+
+#[cfg(feature = "std")]
 pub use interpreter::{
     ProgramInstance,
     ModuleInstance,
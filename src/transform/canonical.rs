@@ -0,0 +1,229 @@
+//! Canonical form of the module.
+//!
+//! Modules which differ only in the encoding details have the same
+//! canonical form: custom sections are stripped, empty sections are removed,
+//! equal function types are merged and ordered by their first use, unused
+//! types are dropped and exports are sorted by name. The serializer always
+//! writes the minimal LEB128 encoding, so the serialized canonical form does
+//! not depend on the padding of the original binary either.
+
+use elements::{self, Module, Section, Opcode, Type, External};
+use super::Error;
+use super::remap::{Remap, IndexMap};
+
+/// Bring the module to the canonical form.
+pub fn canonicalize(module: &mut Module) -> Result<(), Error> {
+    module.sections_mut().retain(|section| match *section {
+        Section::Custom(_) => false,
+        Section::Type(ref s) => !s.types().is_empty(),
+        Section::Import(ref s) => !s.entries().is_empty(),
+        Section::Function(ref s) => !s.entries().is_empty(),
+        Section::Table(ref s) => !s.entries().is_empty(),
+        Section::Memory(ref s) => !s.entries().is_empty(),
+        Section::Global(ref s) => !s.entries().is_empty(),
+        Section::Export(ref s) => !s.entries().is_empty(),
+        Section::Element(ref s) => !s.entries().is_empty(),
+        Section::Code(ref s) => !s.bodies().is_empty(),
+        Section::Data(ref s) => !s.entries().is_empty(),
+        Section::Unparsed { .. } | Section::Start(_) => true,
+    });
+
+    let types: Vec<Type> = module.type_section().map(|s| s.types().to_vec()).unwrap_or_default();
+    let mut uses = Vec::new();
+    for entry in module.import_section().map(|s| s.entries()).unwrap_or(&[]) {
+        if let External::Function(type_ref) = *entry.external() {
+            uses.push(type_ref);
+        }
+    }
+    uses.extend(module.function_section().map(|s| s.entries()).unwrap_or(&[]).iter().map(|f| f.type_ref()));
+    for body in module.code_section().map(|s| s.bodies()).unwrap_or(&[]) {
+        uses.extend(body.code().elements().iter().filter_map(|opcode| match *opcode {
+            Opcode::CallIndirect(type_ref, _) => Some(type_ref),
+            _ => None,
+        }));
+    }
+
+    let mut canonical_types: Vec<Type> = Vec::new();
+    let mut map = vec![None; types.len()];
+    for type_ref in uses {
+        let index = type_ref as usize;
+        if index >= types.len() || map[index].is_some() {
+            continue;
+        }
+        let position = match canonical_types.iter().position(|t| *t == types[index]) {
+            Some(position) => position,
+            None => {
+                canonical_types.push(types[index].clone());
+                canonical_types.len() - 1
+            },
+        };
+        map[index] = Some(position as u32);
+    }
+    Remap::new().with_types(IndexMap::new(map)).apply(module)?;
+
+    let has_types = !canonical_types.is_empty();
+    for section in module.sections_mut() {
+        match *section {
+            Section::Type(ref mut type_section) => {
+                *type_section.types_mut() = ::std::mem::take(&mut canonical_types);
+            },
+            Section::Export(ref mut export_section) => {
+                export_section.entries_mut().sort_by(|a, b| a.field().cmp(b.field()));
+            },
+            _ => {},
+        }
+    }
+    if !has_types {
+        module.sections_mut().retain(|section| !matches!(*section, Section::Type(_)));
+    }
+
+    Ok(())
+}
+
+/// SHA-256 of the serialized canonical form of the module.
+pub fn content_hash(module: &Module) -> Result<[u8; 32], Error> {
+    let mut module = module.clone();
+    canonicalize(&mut module)?;
+    Ok(sha256(&elements::serialize(module)?))
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (value, add) in state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(*add);
+        }
+    }
+
+    let mut result = [0u8; 32];
+    for (i, value) in state.iter().enumerate() {
+        result[i * 4..i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{
+        Module, Opcodes, Opcode, ExportEntry, Internal, Section, CustomSection, ValueType, FunctionType, Type,
+        TypeSection, FunctionSection, Func, ExportSection, CodeSection, FuncBody,
+    };
+    use validation::validate_module;
+    use super::{canonicalize, content_hash, sha256};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        );
+    }
+
+    #[test]
+    fn equivalent_modules() {
+        let first = module()
+            .function()
+                .signature().param().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::End])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::I32Const(1), Opcode::Call(0), Opcode::End])).build()
+                .build()
+            .with_export(ExportEntry::new("b".into(), Internal::Function(1)))
+            .with_export(ExportEntry::new("a".into(), Internal::Function(0)))
+            .build();
+
+        // the same functions with extra and reordered types, unsorted exports and a custom section
+        let unary = || Type::Function(FunctionType::new(vec![ValueType::I32], None));
+        let mut second = Module::new(vec![
+            Section::Type(TypeSection::with_types(vec![
+                Type::Function(FunctionType::new(vec![], Some(ValueType::F64))),
+                Type::Function(FunctionType::default()),
+                unary(),
+                unary(),
+            ])),
+            Section::Function(FunctionSection::with_entries(vec![Func::new(3), Func::new(1)])),
+            Section::Export(ExportSection::with_entries(vec![
+                ExportEntry::new("a".into(), Internal::Function(0)),
+                ExportEntry::new("b".into(), Internal::Function(1)),
+            ])),
+            Section::Code(CodeSection::with_bodies(vec![
+                FuncBody::new(vec![], Opcodes::new(vec![Opcode::End])),
+                FuncBody::new(vec![], Opcodes::new(vec![Opcode::I32Const(1), Opcode::Call(0), Opcode::End])),
+            ])),
+            Section::Custom(CustomSection::new("producers".into(), vec![1, 2, 3])),
+        ]);
+        validate_module(&second).expect("second module to be valid");
+
+        assert_eq!(content_hash(&first).unwrap(), content_hash(&second).unwrap());
+
+        canonicalize(&mut second).expect("canonicalization to succeed");
+        validate_module(&second).expect("canonical module to be valid");
+        assert_eq!(second.type_section().unwrap().types(), &[
+            Type::Function(FunctionType::new(vec![ValueType::I32], None)),
+            Type::Function(FunctionType::default()),
+        ][..]);
+        assert!(second.sections().iter().all(|s| !matches!(*s, Section::Custom(_))));
+
+        let mut different = first.clone();
+        different.sections_mut().retain(|s| !matches!(*s, Section::Export(_)));
+        assert_ne!(content_hash(&first).unwrap(), content_hash(&different).unwrap());
+    }
+}
@@ -0,0 +1,362 @@
+//! Data segment packing.
+//!
+//! Consecutive segments with constant offsets are folded into an image of
+//! the memory they initialize, later segments overwriting earlier ones, and
+//! the image is emitted back as the segments sorted by offset. Adjacent and
+//! overlapping segments end up merged.
+//!
+//! When the memory is defined by the module, it starts zero-initialized, so
+//! zero bytes written by the segments before any segment with a global offset
+//! are not emitted: segments are trimmed and split around zero runs of at
+//! least `min_zero_run` bytes, and the gaps shorter than that are filled with
+//! zeros to merge the segments around them. If the trimmed zeros reached
+//! past the initial memory, an empty segment at their end is kept so the
+//! instantiation still traps.
+
+use std::collections::BTreeMap;
+use elements::{self, Module, Section, Opcode, DataSegment, InitExpr, External};
+use super::Error;
+
+/// Size of the data section before and after packing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Report {
+    /// Number of segments before packing.
+    pub original_segments: usize,
+    /// Number of segments after packing.
+    pub packed_segments: usize,
+    /// Serialized size of the segments before packing, in bytes.
+    pub original_size: usize,
+    /// Serialized size of the segments after packing, in bytes.
+    pub packed_size: usize,
+}
+
+impl Report {
+    /// Bytes saved by packing.
+    pub fn saved(&self) -> usize {
+        self.original_size.saturating_sub(self.packed_size)
+    }
+}
+
+/// Data segment packer.
+#[derive(Debug, Clone)]
+pub struct DataPacker {
+    min_zero_run: usize,
+}
+
+impl Default for DataPacker {
+    fn default() -> Self {
+        // about the size of the segment header, so splitting never grows the section
+        DataPacker { min_zero_run: 8 }
+    }
+}
+
+impl DataPacker {
+    /// New packer splitting segments around zero runs of at least 8 bytes.
+    pub fn new() -> Self {
+        DataPacker::default()
+    }
+
+    /// Split segments around zero runs of at least `min_zero_run` bytes.
+    pub fn with_min_zero_run(mut self, min_zero_run: usize) -> Self {
+        self.min_zero_run = ::std::cmp::max(min_zero_run, 1);
+        self
+    }
+
+    /// Pack the data segments of the module.
+    pub fn pack(&self, module: &mut Module) -> Result<Report, Error> {
+        let imported_memory = module.import_section()
+            .map(|s| s.entries().iter().any(|entry| matches!(*entry.external(), External::Memory(_))))
+            .unwrap_or(false);
+        // initial sizes of the defined memories, in bytes
+        let memory_sizes: Vec<u64> = module.memory_section()
+            .map(|s| s.entries().iter().map(|memory| memory.limits().initial() as u64 * PAGE_SIZE).collect())
+            .unwrap_or_default();
+
+        let data_section = match module.sections_mut().iter_mut().filter_map(|s| match *s {
+            Section::Data(ref mut data_section) => Some(data_section),
+            _ => None,
+        }).next() {
+            Some(data_section) => data_section,
+            None => return Ok(Report::default()),
+        };
+
+        let segments = data_section.entries_mut();
+        let mut report = Report {
+            original_segments: segments.len(),
+            original_size: serialized_size(segments)?,
+            ..Report::default()
+        };
+
+        let mut packed = Vec::with_capacity(segments.len());
+        // memories which had a segment with the global offset written already
+        let mut dirty = Vec::new();
+        let mut images: BTreeMap<u32, Image> = BTreeMap::new();
+        for segment in segments.drain(..) {
+            let index = segment.index();
+            match constant_offset(segment.offset()) {
+                Some(offset) => {
+                    images.entry(index)
+                        .or_insert_with(|| match memory_sizes.get(index as usize) {
+                            Some(&size) if !imported_memory && !dirty.contains(&index) => Image::zeroed(size),
+                            _ => Image::new(),
+                        })
+                        .write(offset, segment.value());
+                },
+                None => {
+                    if let Some(image) = images.remove(&index) {
+                        image.emit(index, self.min_zero_run, &mut packed);
+                    }
+                    dirty.push(index);
+                    packed.push(segment);
+                },
+            }
+        }
+        for (index, image) in images {
+            image.emit(index, self.min_zero_run, &mut packed);
+        }
+
+        report.packed_segments = packed.len();
+        report.packed_size = serialized_size(&packed)?;
+        *segments = packed;
+        Ok(report)
+    }
+}
+
+const PAGE_SIZE: u64 = 65536;
+
+fn constant_offset(offset: &InitExpr) -> Option<u32> {
+    match *offset.code() {
+        [Opcode::I32Const(value), Opcode::End] => Some(value as u32),
+        _ => None,
+    }
+}
+
+fn serialized_size(segments: &[DataSegment]) -> Result<usize, Error> {
+    let mut size = 0;
+    for segment in segments {
+        size += elements::serialize(segment.clone())?.len();
+    }
+    Ok(size)
+}
+
+/// Bytes written to the memory by consecutive constant offset segments.
+struct Image {
+    /// Disjoint non-adjacent runs of bytes by their offset.
+    runs: BTreeMap<u64, Vec<u8>>,
+    /// Initial size of the memory in bytes, if it is known to be zero where the image is not written.
+    zeroed: Option<u64>,
+}
+
+impl Image {
+    fn new() -> Self {
+        Image { runs: BTreeMap::new(), zeroed: None }
+    }
+
+    fn zeroed(memory_size: u64) -> Self {
+        Image { runs: BTreeMap::new(), zeroed: Some(memory_size) }
+    }
+
+    fn write(&mut self, offset: u32, value: &[u8]) {
+        let mut start = offset as u64;
+        let end = start + value.len() as u64;
+        let mut bytes = value.to_vec();
+
+        // absorb the runs overlapping or adjacent to the written bytes
+        let touching: Vec<u64> = self.runs.range(..=end)
+            .filter(|&(&run_start, run)| run_start + run.len() as u64 >= start)
+            .map(|(&run_start, _)| run_start)
+            .collect();
+        for run_start in touching {
+            let run = self.runs.remove(&run_start).expect("key was just listed");
+            let run_end = run_start + run.len() as u64;
+            if run_start < start {
+                let mut merged = run[..(start - run_start) as usize].to_vec();
+                merged.extend_from_slice(&bytes);
+                bytes = merged;
+                start = run_start;
+            }
+            if run_end > end {
+                bytes.extend_from_slice(&run[(end - run_start) as usize..]);
+            }
+        }
+        self.runs.insert(start, bytes);
+    }
+
+    fn emit(self, index: u32, min_zero_run: usize, segments: &mut Vec<DataSegment>) {
+        let end = self.runs.iter().next_back().map(|(&start, bytes)| start + bytes.len() as u64).unwrap_or(0);
+        // the end offset of the trimmed zeros is not representable in the zero-length segment
+        let zeroed = self.zeroed.filter(|_| end <= u32::MAX as u64);
+        let mut pieces: Vec<(u64, Vec<u8>)> = Vec::new();
+        for (start, bytes) in self.runs {
+            if zeroed.is_none() {
+                pieces.push((start, bytes));
+                continue;
+            }
+            let mut position = 0;
+            while position < bytes.len() {
+                if bytes[position] == 0 {
+                    position += 1;
+                    continue;
+                }
+                let piece_start = position;
+                let mut piece_end = position;
+                while position < bytes.len() {
+                    if bytes[position] != 0 {
+                        position += 1;
+                        piece_end = position;
+                        continue;
+                    }
+                    let zeros = bytes[position..].iter().take_while(|&&b| b == 0).count();
+                    if zeros >= min_zero_run || position + zeros == bytes.len() {
+                        break;
+                    }
+                    position += zeros;
+                }
+                let offset = start + piece_start as u64;
+                // untouched memory is zero, so short gaps between the runs are filled
+                let merge = match pieces.last() {
+                    Some(&(last_start, ref last)) => offset - (last_start + last.len() as u64) < min_zero_run as u64,
+                    None => false,
+                };
+                let piece = &bytes[piece_start..piece_end];
+                if merge {
+                    let last = pieces.last_mut().expect("merge is only set when there is the last piece");
+                    let filled = (offset - last.0) as usize;
+                    last.1.resize(filled, 0);
+                    last.1.extend_from_slice(piece);
+                } else {
+                    pieces.push((offset, piece.to_vec()));
+                }
+            }
+        }
+        // writing past the initial memory traps the instantiation, keep it trapping
+        if let Some(memory_size) = zeroed {
+            let packed_end = pieces.last().map(|&(start, ref bytes)| start + bytes.len() as u64);
+            if end > memory_size && packed_end != Some(end) {
+                pieces.push((end, Vec::new()));
+            }
+        }
+        for (offset, bytes) in pieces {
+            segments.push(DataSegment::new(
+                index,
+                InitExpr::new(vec![Opcode::I32Const(offset as u32 as i32), Opcode::End]),
+                bytes,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{
+        DataSegment, InitExpr, ImportEntry, External, MemoryType, Opcode, GlobalType, ValueType,
+    };
+    use super::{DataPacker, Report};
+
+    fn segment(offset: i32, value: &[u8]) -> DataSegment {
+        DataSegment::new(0, InitExpr::new(vec![Opcode::I32Const(offset), Opcode::End]), value.to_vec())
+    }
+
+    fn segments(module: &::elements::Module) -> Vec<(i32, Vec<u8>)> {
+        module.data_section().unwrap().entries().iter().map(|s| {
+            let offset = match s.offset().code()[0] {
+                Opcode::I32Const(offset) => offset,
+                _ => -1,
+            };
+            (offset, s.value().to_vec())
+        }).collect()
+    }
+
+    #[test]
+    fn merge_and_trim() {
+        let mut module = module()
+            .memory().with_min(1).build()
+            .with_data_segment(segment(16, &[0, 0, 1, 2, 3]))
+            .with_data_segment(segment(20, &[4, 5, 0, 0]))
+            .with_data_segment(segment(10, &[9, 0, 0]))
+            // overwrites the byte written by the first segment
+            .with_data_segment(segment(18, &[7]))
+            .build();
+
+        let report = DataPacker::new().pack(&mut module).expect("packing to succeed");
+        assert_eq!(segments(&module), vec![(10, vec![9, 0, 0, 0, 0, 0, 0, 0, 7, 2, 4, 5])]);
+        assert_eq!(report.original_segments, 4);
+        assert_eq!(report.packed_segments, 1);
+        assert!(report.saved() > 0);
+    }
+
+    #[test]
+    fn split_zero_runs() {
+        let mut data = vec![1, 2];
+        data.extend(vec![0; 100]);
+        data.push(3);
+        let mut module = module()
+            .memory().with_min(1).build()
+            .with_data_segment(segment(0, &data))
+            .build();
+
+        let report = DataPacker::new().with_min_zero_run(4).pack(&mut module).expect("packing to succeed");
+        assert_eq!(segments(&module), vec![(0, vec![1, 2]), (102, vec![3])]);
+        assert_eq!(report.original_size - report.packed_size, report.saved());
+        assert!(report.saved() > 90);
+    }
+
+    #[test]
+    fn imported_memory_keeps_zeros() {
+        let mut module = module()
+            .with_import(ImportEntry::new("env".into(), "memory".into(), External::Memory(MemoryType::new(1, None))))
+            .with_data_segment(segment(4, &[0, 1, 0]))
+            .with_data_segment(segment(7, &[0, 0]))
+            .build();
+
+        let report = DataPacker::new().pack(&mut module).expect("packing to succeed");
+        assert_eq!(segments(&module), vec![(4, vec![0, 1, 0, 0, 0])]);
+        assert_eq!(report, Report {
+            original_segments: 2,
+            packed_segments: 1,
+            original_size: 15,
+            packed_size: 10,
+        });
+    }
+
+    #[test]
+    fn global_offset_is_a_barrier() {
+        let mut module = module()
+            .memory().with_min(1).build()
+            .with_import(ImportEntry::new("env".into(), "base".into(), External::Global(GlobalType::new(ValueType::I32, false))))
+            .with_data_segment(segment(0, &[1, 0]))
+            .with_data_segment(DataSegment::new(0, InitExpr::new(vec![Opcode::GetGlobal(0), Opcode::End]), vec![5]))
+            .with_data_segment(segment(1, &[0]))
+            .build();
+
+        DataPacker::new().pack(&mut module).expect("packing to succeed");
+        let data = module.data_section().unwrap().entries();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data[0].value(), &[1]);
+        assert_eq!(data[1].offset().code(), &[Opcode::GetGlobal(0), Opcode::End]);
+        // the zero may overwrite the byte written at the global offset
+        assert_eq!(data[2].value(), &[0]);
+    }
+
+    #[test]
+    fn out_of_bounds_zeros_still_trap() {
+        let mut out_of_bounds = module()
+            .memory().with_min(1).build()
+            .with_data_segment(segment(16, &[1]))
+            .with_data_segment(segment(65530, &[0; 8]))
+            .build();
+
+        DataPacker::new().pack(&mut out_of_bounds).expect("packing to succeed");
+        assert_eq!(segments(&out_of_bounds), vec![(16, vec![1]), (65538, vec![])]);
+
+        let mut in_bounds = module()
+            .memory().with_min(1).build()
+            .with_data_segment(segment(65520, &[0; 8]))
+            .build();
+
+        DataPacker::new().pack(&mut in_bounds).expect("packing to succeed");
+        assert_eq!(segments(&in_bounds), vec![]);
+    }
+}
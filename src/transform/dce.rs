@@ -0,0 +1,330 @@
+//! Dead code elimination.
+//!
+//! Removes functions unreachable from the exports, the start function and
+//! the table, then types, globals and imports no longer referenced by
+//! anything that is left. All index references are renumbered.
+
+use std::collections::BTreeSet;
+use elements::{Module, Section, Opcode, External, Internal, FuncBody, InitExpr, Visitor, Location};
+use analysis::call_graph::CallGraph;
+use super::Error;
+use super::remap::{Remap, IndexMap};
+
+/// Number of items removed by the dead code elimination.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Report {
+    /// Removed functions, imported ones included.
+    pub functions: usize,
+    /// Removed globals, imported ones included.
+    pub globals: usize,
+    /// Removed function types.
+    pub types: usize,
+    /// Removed import entries.
+    pub imports: usize,
+}
+
+/// Globals and types referenced from the live function bodies.
+struct Uses<'a> {
+    live_functions: &'a BTreeSet<u32>,
+    globals: BTreeSet<u32>,
+    types: BTreeSet<u32>,
+}
+
+impl<'a> Visitor for Uses<'a> {
+    fn visit_func_body(&mut self, func: u32, body: &FuncBody) {
+        if self.live_functions.contains(&func) {
+            ::elements::walk_func_body(self, func, body);
+        }
+    }
+
+    fn visit_call(&mut self, _location: Location, opcode: &Opcode) {
+        if let Opcode::CallIndirect(type_ref, _) = *opcode {
+            self.types.insert(type_ref);
+        }
+    }
+
+    fn visit_variable(&mut self, _location: Location, opcode: &Opcode) {
+        match *opcode {
+            Opcode::GetGlobal(index) | Opcode::SetGlobal(index) => { self.globals.insert(index); },
+            _ => {},
+        }
+    }
+}
+
+/// Remove unreachable functions and unused types, globals and imports from the module.
+///
+/// Functions placed into the table by element segments are always kept, since
+/// removing them would change the layout of the table. On error the module is
+/// left unchanged.
+pub fn eliminate_dead_code(module: &mut Module) -> Result<Report, Error> {
+    let graph = CallGraph::new(module);
+    let mut roots = graph.roots().clone();
+    if let Some(elements_section) = module.elements_section() {
+        for segment in elements_section.entries() {
+            roots.extend(segment.members().iter().cloned());
+        }
+    }
+    let live_functions = graph.reachable_from(roots);
+
+    let mut uses = Uses { live_functions: &live_functions, globals: BTreeSet::new(), types: BTreeSet::new() };
+    uses.visit_module(module);
+    let Uses { globals: mut live_globals, types: mut live_types, .. } = uses;
+
+    let imported_globals = module.import_section().map(|s| s.globals() as u32).unwrap_or(0);
+    let global_inits: Vec<&InitExpr> = module.global_section()
+        .map(|s| s.entries().iter().map(|entry| entry.init_expr()).collect())
+        .unwrap_or_default();
+    let total_globals = imported_globals as usize + global_inits.len();
+
+    if let Some(export_section) = module.export_section() {
+        for entry in export_section.entries() {
+            if let Internal::Global(index) = *entry.internal() {
+                live_globals.insert(index);
+            }
+        }
+    }
+    if let Some(elements_section) = module.elements_section() {
+        for segment in elements_section.entries() {
+            init_expr_globals(segment.offset(), &mut live_globals);
+        }
+    }
+    if let Some(data_section) = module.data_section() {
+        for segment in data_section.entries() {
+            init_expr_globals(segment.offset(), &mut live_globals);
+        }
+    }
+    let mut stack: Vec<u32> = live_globals.iter().cloned().collect();
+    while let Some(index) = stack.pop() {
+        if index < imported_globals {
+            continue;
+        }
+        if let Some(init_expr) = global_inits.get((index - imported_globals) as usize) {
+            let mut referenced = BTreeSet::new();
+            init_expr_globals(init_expr, &mut referenced);
+            for global in referenced {
+                if live_globals.insert(global) {
+                    stack.push(global);
+                }
+            }
+        }
+    }
+
+    let mut function = 0;
+    if let Some(import_section) = module.import_section() {
+        for entry in import_section.entries() {
+            if let External::Function(type_ref) = *entry.external() {
+                if live_functions.contains(&function) {
+                    live_types.insert(type_ref);
+                }
+                function += 1;
+            }
+        }
+    }
+    if let Some(function_section) = module.function_section() {
+        for func in function_section.entries() {
+            if live_functions.contains(&function) {
+                live_types.insert(func.type_ref());
+            }
+            function += 1;
+        }
+    }
+    let total_types = module.type_section().map(|s| s.types().len()).unwrap_or(0);
+
+    let functions = IndexMap::retain(graph.len() as u32, |index| live_functions.contains(&index));
+    let globals = IndexMap::retain(total_globals as u32, |index| live_globals.contains(&index));
+    let types = IndexMap::retain(total_types as u32, |index| live_types.contains(&index));
+
+    let mut report = Report {
+        functions: graph.len() - live_functions.len(),
+        globals: total_globals - live_globals.iter().filter(|g| (**g as usize) < total_globals).count(),
+        types: total_types - live_types.iter().filter(|t| (**t as usize) < total_types).count(),
+        imports: 0,
+    };
+
+    // the module is only replaced once renumbering succeeds
+    let mut pruned = module.clone();
+    let imported_functions = graph.imported_functions() as usize;
+    for section in pruned.sections_mut() {
+        match *section {
+            Section::Type(ref mut type_section) => {
+                retain_indexed(type_section.types_mut(), 0, &types);
+            },
+            Section::Import(ref mut import_section) => {
+                let (mut function, mut global) = (0, 0);
+                let before = import_section.entries().len();
+                import_section.entries_mut().retain(|entry| match *entry.external() {
+                    External::Function(_) => { function += 1; !functions.is_removed(function - 1) },
+                    External::Global(_) => { global += 1; !globals.is_removed(global - 1) },
+                    _ => true,
+                });
+                report.imports = before - import_section.entries().len();
+            },
+            Section::Function(ref mut function_section) => {
+                retain_indexed(function_section.entries_mut(), imported_functions, &functions);
+            },
+            Section::Code(ref mut code_section) => {
+                retain_indexed(code_section.bodies_mut(), imported_functions, &functions);
+            },
+            Section::Global(ref mut global_section) => {
+                retain_indexed(global_section.entries_mut(), imported_globals as usize, &globals);
+            },
+            _ => {},
+        }
+    }
+
+    Remap::new()
+        .with_functions(functions)
+        .with_globals(globals)
+        .with_types(types)
+        .apply(&mut pruned)?;
+    *module = pruned;
+
+    Ok(report)
+}
+
+fn init_expr_globals(init_expr: &InitExpr, globals: &mut BTreeSet<u32>) {
+    for opcode in init_expr.code() {
+        if let Opcode::GetGlobal(index) = *opcode {
+            globals.insert(index);
+        }
+    }
+}
+
+/// Keep items whose index (`offset` plus position) is not removed by `map`.
+fn retain_indexed<T>(items: &mut Vec<T>, offset: usize, map: &IndexMap) {
+    let mut index = offset as u32;
+    items.retain(|_| {
+        index += 1;
+        !map.is_removed(index - 1)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::BTreeMap;
+    use builder::module;
+    use elements::{
+        Opcode, Opcodes, ExportEntry, Internal, ImportEntry, External, GlobalType,
+        ValueType, Section, NameSection, NameMap,
+    };
+    use super::{eliminate_dead_code, Report};
+
+    #[test]
+    fn removes_dead_functions_and_renumbers() {
+        use elements::Opcode::*;
+
+        let mut names = NameSection::default();
+        let mut functions = BTreeMap::new();
+        functions.insert(0, "unused_import".to_owned());
+        functions.insert(1, "used_import".to_owned());
+        functions.insert(2, "dead".to_owned());
+        functions.insert(3, "main".to_owned());
+        *names.functions_mut() = Some(NameMap::new(functions));
+
+        let mut module = module()
+            .with_import(ImportEntry::new("env".into(), "unused".into(), External::Function(0)))
+            .with_import(ImportEntry::new("env".into(), "used".into(), External::Function(1)))
+            .with_import(ImportEntry::new("env".into(), "g".into(), External::Global(GlobalType::new(ValueType::I32, false))))
+            // 2: dead, uses type 2 and the imported global
+            .function()
+                .signature().param().f32().build()
+                .body().with_opcodes(Opcodes::new(vec![GetGlobal(0), Drop, Call(0), End])).build()
+                .build()
+            // 3: main
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![I32Const(1), Call(1), End])).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(3)))
+            .with_section(Section::Custom(names.into_custom().unwrap()))
+            .build();
+
+        let report = eliminate_dead_code(&mut module).expect("dce to succeed");
+        assert_eq!(report, Report { functions: 2, globals: 1, types: 1, imports: 2 });
+
+        let imports = module.import_section().unwrap().entries();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].field(), "used");
+        assert_eq!(module.function_section().unwrap().entries().len(), 1);
+        assert_eq!(
+            module.code_section().unwrap().bodies()[0].code().elements(),
+            &[I32Const(1), Call(0), End][..]
+        );
+        match *module.export_section().unwrap().entries()[0].internal() {
+            Internal::Function(index) => assert_eq!(index, 1),
+            _ => panic!("Function export expected"),
+        }
+
+        let names = module.sections().iter()
+            .filter_map(|s| match *s { Section::Custom(ref c) => NameSection::from_custom(c), _ => None })
+            .next().unwrap().unwrap();
+        let functions = names.functions().unwrap();
+        assert_eq!(functions.names().len(), 2);
+        assert_eq!(functions.get(0), Some("used_import"));
+        assert_eq!(functions.get(1), Some("main"));
+    }
+
+    #[test]
+    fn keeps_table_and_global_references() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .global().value_type().i32().init_expr(Opcode::I32Const(0)).build()
+            .global().value_type().i32().init_expr(Opcode::I32Const(1)).build()
+            // 0: dead, the only function with its signature
+            .function()
+                .signature().param().i64().build()
+                .body().build()
+                .build()
+            // 1: table member, reads the global 1
+            .function()
+                .signature().with_return_type(Some(ValueType::I32)).build()
+                .body().with_opcodes(Opcodes::new(vec![GetGlobal(1), End])).build()
+                .build()
+            // 2: start
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .table()
+                .with_min(1)
+                .with_element(0, vec![1])
+                .build()
+            .with_section(Section::Start(2))
+            .build();
+
+        let report = eliminate_dead_code(&mut module).expect("dce to succeed");
+        assert_eq!(report, Report { functions: 1, globals: 1, types: 1, imports: 0 });
+        assert_eq!(module.start_section(), Some(1));
+        assert_eq!(module.elements_section().unwrap().entries()[0].members(), &[0]);
+        assert_eq!(
+            module.code_section().unwrap().bodies()[0].code().elements(),
+            &[GetGlobal(0), End][..]
+        );
+        assert_eq!(module.global_section().unwrap().entries().len(), 1);
+        assert_eq!(module.type_section().unwrap().types().len(), 2);
+    }
+
+    #[test]
+    fn unchanged_on_error() {
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(1)))
+            // malformed name section fails renumbering
+            .with_section(Section::Custom(::elements::CustomSection::new("name".into(), vec![1, 5, 0])))
+            .build();
+        let original = ::elements::serialize(module.clone()).unwrap();
+
+        assert!(eliminate_dead_code(&mut module).is_err());
+        assert_eq!(module.function_section().unwrap().entries().len(), 2);
+        assert_eq!(::elements::serialize(module).unwrap(), original);
+    }
+}
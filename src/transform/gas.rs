@@ -0,0 +1,446 @@
+//! Gas metering.
+//!
+//! Every function body is split into metered blocks: straight-line runs of
+//! opcodes ending with a control opcode. The total cost of the block is
+//! charged before its first opcode, so the charge is paid before any of the
+//! opcodes of the block are executed. `grow_memory` is replaced with the call
+//! to the generated function which additionally charges for every page.
+
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+use elements::{
+    Module, Section, Opcode, OpcodeCategory, BlockType, FunctionType, ValueType,
+    External, ImportEntry, ImportSection, GlobalEntry, GlobalType, GlobalSection,
+    InitExpr, FuncBody, Local, Opcodes,
+};
+use super::{Error, ensure_section, ensure_type, add_function};
+use super::remap::{Remap, IndexMap};
+
+/// Cost of the opcodes.
+///
+/// The cost of the opcode is the one set for the opcode itself, or else the
+/// one set for its category, or else the default cost.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    default: u32,
+    categories: HashMap<OpcodeCategory, u32>,
+    opcodes: HashMap<Discriminant<Opcode>, u32>,
+    grow_memory_page: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules::new(1)
+    }
+}
+
+impl Rules {
+    /// New rules charging `default` for every opcode and nothing for the memory pages.
+    pub fn new(default: u32) -> Self {
+        Rules {
+            default,
+            categories: HashMap::new(),
+            opcodes: HashMap::new(),
+            grow_memory_page: 0,
+        }
+    }
+
+    /// Set the cost of every opcode of the category.
+    pub fn with_category(mut self, category: OpcodeCategory, cost: u32) -> Self {
+        self.categories.insert(category, cost);
+        self
+    }
+
+    /// Set the cost of the opcode (immediate arguments of `opcode` are ignored).
+    pub fn with_opcode(mut self, opcode: &Opcode, cost: u32) -> Self {
+        self.opcodes.insert(mem::discriminant(opcode), cost);
+        self
+    }
+
+    /// Set the additional cost of every page requested by `grow_memory`.
+    pub fn with_grow_memory_page(mut self, cost: u32) -> Self {
+        self.grow_memory_page = cost;
+        self
+    }
+
+    /// Cost of the opcode.
+    pub fn cost(&self, opcode: &Opcode) -> u32 {
+        self.opcodes.get(&mem::discriminant(opcode)).cloned()
+            .or_else(|| self.categories.get(&opcode.category()).cloned())
+            .unwrap_or(self.default)
+    }
+
+    /// Additional cost of every page requested by `grow_memory`.
+    pub fn grow_memory_page(&self) -> u32 { self.grow_memory_page }
+}
+
+/// Where the spent gas is accounted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Counter {
+    /// Imported host function `(i32) -> ()` called with the cost of every block.
+    ///
+    /// The cost is unsigned: the host has to read the argument as `u32`.
+    Import {
+        /// Module of the import.
+        module: String,
+        /// Field of the import.
+        field: String,
+    },
+    /// Internal mutable `i64` global; execution traps once it exceeds `limit`.
+    Global {
+        /// Gas limit of the single instance.
+        limit: u64,
+    },
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter::Import { module: "env".into(), field: "gas".into() }
+    }
+}
+
+/// Opcodes charging `cost` to the counter.
+enum Charge {
+    Call(u32),
+    Global { index: u32, limit: u64 },
+}
+
+impl Charge {
+    fn opcodes(&self, cost: u32) -> Vec<Opcode> {
+        match *self {
+            Charge::Call(function) => vec![Opcode::I32Const(cost as i32), Opcode::Call(function)],
+            Charge::Global { index, .. } => {
+                let mut opcodes = vec![Opcode::GetGlobal(index), Opcode::I64Const(cost as i64), Opcode::I64Add];
+                opcodes.extend(self.store_and_check());
+                opcodes
+            },
+        }
+    }
+
+    /// Body of the `grow_memory` replacement charging `page_cost` for every page.
+    ///
+    /// The charge passed to the imported function saturates at `u32::MAX`.
+    fn grow_memory(&self, page_cost: u32) -> FuncBody {
+        let mut locals = Vec::new();
+        let mut opcodes = match *self {
+            Charge::Call(function) => {
+                locals.push(Local::new(1, ValueType::I64));
+                vec![
+                    Opcode::GetLocal(0),
+                    Opcode::I64ExtendUI32,
+                    Opcode::I64Const(page_cost as i64),
+                    Opcode::I64Mul,
+                    Opcode::SetLocal(1),
+                    Opcode::I64Const(u32::MAX as i64),
+                    Opcode::GetLocal(1),
+                    Opcode::GetLocal(1),
+                    Opcode::I64Const(u32::MAX as i64),
+                    Opcode::I64GtU,
+                    Opcode::Select,
+                    Opcode::I32WarpI64,
+                    Opcode::Call(function),
+                ]
+            },
+            Charge::Global { index, .. } => {
+                let mut opcodes = vec![
+                    Opcode::GetGlobal(index),
+                    Opcode::GetLocal(0),
+                    Opcode::I64ExtendUI32,
+                    Opcode::I64Const(page_cost as i64),
+                    Opcode::I64Mul,
+                    Opcode::I64Add,
+                ];
+                opcodes.extend(self.store_and_check());
+                opcodes
+            },
+        };
+        opcodes.extend(vec![Opcode::GetLocal(0), Opcode::GrowMemory(false), Opcode::End]);
+        FuncBody::new(locals, Opcodes::new(opcodes))
+    }
+
+    /// Store the new counter value from the stack and trap if it is over the limit.
+    fn store_and_check(&self) -> Vec<Opcode> {
+        match *self {
+            Charge::Call(_) => Vec::new(),
+            Charge::Global { index, limit } => vec![
+                Opcode::SetGlobal(index),
+                Opcode::GetGlobal(index),
+                Opcode::I64Const(limit as i64),
+                Opcode::I64GtU,
+                Opcode::If(BlockType::NoResult),
+                Opcode::Unreachable,
+                Opcode::End,
+            ],
+        }
+    }
+}
+
+fn ends_block(opcode: &Opcode) -> bool {
+    opcode.category() == OpcodeCategory::Control && *opcode != Opcode::Nop
+}
+
+fn meter(opcodes: Vec<Opcode>, rules: &Rules, charge: &Charge, grow_memory: Option<u32>) -> Vec<Opcode> {
+    let mut result = Vec::with_capacity(opcodes.len());
+    let mut block: Vec<Opcode> = Vec::new();
+    // cost of the original opcodes of the block, before `grow_memory` is replaced
+    let mut cost = 0u32;
+    let flush = |block: &mut Vec<Opcode>, cost: &mut u32, result: &mut Vec<Opcode>| {
+        if *cost > 0 {
+            result.extend(charge.opcodes(*cost));
+        }
+        *cost = 0;
+        result.append(block);
+    };
+
+    for opcode in opcodes {
+        let is_end = ends_block(&opcode);
+        cost = cost.saturating_add(rules.cost(&opcode));
+        block.push(match (opcode, grow_memory) {
+            (Opcode::GrowMemory(_), Some(function)) => Opcode::Call(function),
+            (opcode, _) => opcode,
+        });
+        if is_end {
+            flush(&mut block, &mut cost, &mut result);
+        }
+    }
+    flush(&mut block, &mut cost, &mut result);
+    result
+}
+
+/// Insert gas metering into every function of the module.
+///
+/// Functions added by the pass itself are not metered.
+pub fn inject_gas_counter(module: &mut Module, rules: &Rules, counter: &Counter) -> Result<(), Error> {
+    let charge = match *counter {
+        Counter::Import { module: ref import_module, ref field } =>
+            Charge::Call(add_gas_import(module, import_module.clone(), field.clone())?),
+        Counter::Global { limit } => Charge::Global { index: add_counter_global(module), limit },
+    };
+    inject(module, rules, charge)
+}
+
+/// Import the gas function after the other imported functions, returning its index.
+fn add_gas_import(module: &mut Module, import_module: String, field: String) -> Result<u32, Error> {
+    let type_ref = ensure_type(module, FunctionType::new(vec![ValueType::I32], None));
+    let imported = module.import_section().map(|s| s.functions()).unwrap_or(0) as u32;
+    let defined = module.function_section().map(|s| s.entries().len()).unwrap_or(0) as u32;
+    Remap::new()
+        .with_functions(IndexMap::insert(imported + defined, imported, 1))
+        .apply(module)?;
+
+    let position = ensure_section(module, Section::Import(ImportSection::default()));
+    if let Section::Import(ref mut import_section) = module.sections_mut()[position] {
+        import_section.entries_mut().push(ImportEntry::new(import_module, field, External::Function(type_ref)));
+    }
+    Ok(imported)
+}
+
+/// Define the counter global, returning its index.
+fn add_counter_global(module: &mut Module) -> u32 {
+    let imported = module.import_section().map(|s| s.globals()).unwrap_or(0);
+    let position = ensure_section(module, Section::Global(GlobalSection::default()));
+    match module.sections_mut()[position] {
+        Section::Global(ref mut global_section) => {
+            global_section.entries_mut().push(GlobalEntry::new(
+                GlobalType::new(ValueType::I64, true),
+                InitExpr::new(vec![Opcode::I64Const(0), Opcode::End]),
+            ));
+            (imported + global_section.entries().len() - 1) as u32
+        },
+        _ => unreachable!("ensure_section returns section of the requested kind"),
+    }
+}
+
+fn inject(module: &mut Module, rules: &Rules, charge: Charge) -> Result<(), Error> {
+    let uses_grow_memory = module.code_section()
+        .map(|s| s.bodies().iter().any(|body| body.code().elements().iter().any(|op| matches!(*op, Opcode::GrowMemory(_)))))
+        .unwrap_or(false);
+
+    let grow_memory = if uses_grow_memory && rules.grow_memory_page() > 0 {
+        let type_ref = ensure_type(module, FunctionType::new(vec![ValueType::I32], Some(ValueType::I32)));
+        let imported = module.import_section().map(|s| s.functions()).unwrap_or(0);
+        let defined = module.function_section().map(|s| s.entries().len()).unwrap_or(0);
+        Some(((imported + defined) as u32, type_ref))
+    } else {
+        None
+    };
+
+    for section in module.sections_mut() {
+        if let Section::Code(ref mut code_section) = *section {
+            for body in code_section.bodies_mut() {
+                let opcodes = mem::take(body.code_mut().elements_mut());
+                *body.code_mut().elements_mut() = meter(opcodes, rules, &charge, grow_memory.map(|(index, _)| index));
+            }
+        }
+    }
+
+    if let Some((index, type_ref)) = grow_memory {
+        let added = add_function(module, type_ref, charge.grow_memory(rules.grow_memory_page()));
+        debug_assert_eq!(added, index);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Opcodes, OpcodeCategory, BlockType, ExportEntry, Internal};
+    use validation::validate_module;
+    use super::{inject_gas_counter, Rules, Counter};
+
+    #[test]
+    fn import_counter() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().param().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![
+                    GetLocal(0),
+                    If(BlockType::NoResult),
+                        I32Const(1),
+                        Drop,
+                    End,
+                    Call(1),
+                    End,
+                ])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_export(ExportEntry::new("call".into(), Internal::Function(0)))
+            .build();
+
+        let rules = Rules::new(1).with_opcode(&Call(0), 10).with_category(OpcodeCategory::Constant, 2);
+        inject_gas_counter(&mut module, &rules, &Counter::default()).expect("gas injection to succeed");
+        validate_module(&module).expect("metered module to validate");
+
+        let import = &module.import_section().unwrap().entries()[0];
+        assert_eq!((import.module(), import.field()), ("env", "gas"));
+        assert_eq!(
+            module.code_section().unwrap().bodies()[0].code().elements(),
+            &[
+                I32Const(2), Call(0),
+                GetLocal(0),
+                If(BlockType::NoResult),
+                    I32Const(4), Call(0),
+                    I32Const(1),
+                    Drop,
+                End,
+                I32Const(11), Call(0),
+                Call(2),
+                End,
+            ][..]
+        );
+        assert_eq!(*module.export_section().unwrap().entries()[0].internal(), Internal::Function(1));
+    }
+
+    #[test]
+    fn global_counter_and_grow_memory() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .memory().with_min(1).build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![
+                    Loop(BlockType::NoResult),
+                        I32Const(1),
+                        GrowMemory(false),
+                        BrIf(0),
+                    End,
+                    End,
+                ])).build()
+                .build()
+            .build();
+
+        let rules = Rules::default().with_grow_memory_page(100);
+        inject_gas_counter(&mut module, &rules, &Counter::Global { limit: 1000 }).expect("gas injection to succeed");
+        validate_module(&module).expect("metered module to validate");
+
+        assert!(module.import_section().is_none());
+        assert_eq!(module.global_section().unwrap().entries().len(), 1);
+        let bodies = module.code_section().unwrap().bodies();
+        assert_eq!(bodies.len(), 2);
+        let code = bodies[0].code().elements();
+        assert!(code.contains(&Call(1)));
+        assert!(!code.contains(&GrowMemory(false)));
+        assert_eq!(&code[..3], &[GetGlobal(0), I64Const(1), I64Add][..]);
+        assert!(bodies[1].code().elements().contains(&GrowMemory(false)));
+        assert!(bodies[1].code().elements().contains(&I64Const(100)));
+    }
+
+    #[test]
+    fn grow_memory_keeps_its_own_cost() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .memory().with_min(1).build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![I32Const(1), GrowMemory(false), Drop, End])).build()
+                .build()
+            .build();
+
+        let rules = Rules::new(1).with_opcode(&GrowMemory(false), 1000).with_grow_memory_page(10);
+        inject_gas_counter(&mut module, &rules, &Counter::default()).expect("gas injection to succeed");
+        validate_module(&module).expect("metered module to validate");
+
+        assert_eq!(
+            module.code_section().unwrap().bodies()[0].code().elements(),
+            &[I32Const(1003), Call(0), I32Const(1), Call(2), Drop, End][..]
+        );
+    }
+
+    #[test]
+    fn grow_memory_charge_saturates() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .memory().with_min(1).build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![I32Const(1), GrowMemory(false), Drop, End])).build()
+                .build()
+            .build();
+
+        // 2^16 pages would cost 2^36, more than fits the i32 argument
+        let rules = Rules::new(1).with_grow_memory_page(1 << 20);
+        inject_gas_counter(&mut module, &rules, &Counter::default()).expect("gas injection to succeed");
+        validate_module(&module).expect("metered module to validate");
+
+        assert_eq!(
+            module.code_section().unwrap().bodies()[1].code().elements(),
+            &[
+                GetLocal(0), I64ExtendUI32, I64Const(1 << 20), I64Mul, SetLocal(1),
+                I64Const(0xffff_ffff), GetLocal(1), GetLocal(1), I64Const(0xffff_ffff), I64GtU, Select,
+                I32WarpI64, Call(0),
+                GetLocal(0), GrowMemory(false),
+                End,
+            ][..]
+        );
+    }
+
+    #[test]
+    fn block_cost_is_unsigned() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Nop, Nop, End])).build()
+                .build()
+            .build();
+
+        let rules = Rules::new(1).with_opcode(&Nop, 3_000_000_000);
+        inject_gas_counter(&mut module, &rules, &Counter::default()).expect("gas injection to succeed");
+
+        // the sum saturates and the host reads the argument as u32
+        let code = module.code_section().unwrap().bodies()[0].code().elements();
+        assert_eq!(code[0], I32Const(u32::MAX as i32));
+        assert_eq!(code[1], Call(0));
+    }
+}
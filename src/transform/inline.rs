@@ -0,0 +1,298 @@
+//! Function inlining.
+//!
+//! Calls of small functions, and of functions with the single call site,
+//! are replaced with the bodies of the callees. Arguments are stored into
+//! fresh locals of the caller, the callee locals are reset to zero, and the
+//! callee body is wrapped into a block with the callee result type: `return`
+//! becomes a branch to this block.
+//!
+//! Inlining is done in one round over the original bodies, so recursive
+//! functions never expand infinitely. Callees which are no longer called are
+//! left in the module; `eliminate_dead_code` removes them.
+
+use std::collections::HashMap;
+use elements::{
+    Module, Section, Opcode, BlockType, FuncBody, Local, FunctionType, ValueType,
+    VisitorMut, Editor, walk_func_body_mut,
+};
+use super::Error;
+
+/// Function inliner.
+#[derive(Debug, Clone)]
+pub struct Inliner {
+    max_size: usize,
+    single_call_sites: bool,
+    max_growth: usize,
+}
+
+impl Default for Inliner {
+    fn default() -> Self {
+        Inliner {
+            max_size: 12,
+            single_call_sites: true,
+            max_growth: 1024,
+        }
+    }
+}
+
+impl Inliner {
+    /// New inliner with the default limits.
+    pub fn new() -> Self {
+        Inliner::default()
+    }
+
+    /// Inline functions of at most `max_size` opcodes.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Inline functions with the single call site regardless of their size.
+    pub fn with_single_call_sites(mut self, single_call_sites: bool) -> Self {
+        self.single_call_sites = single_call_sites;
+        self
+    }
+
+    /// Stop inlining once the module has grown by `max_growth` opcodes.
+    pub fn with_max_growth(mut self, max_growth: usize) -> Self {
+        self.max_growth = max_growth;
+        self
+    }
+
+    /// Inline calls in the module, returning the number of inlined call sites.
+    pub fn inline(&self, module: &mut Module) -> Result<usize, Error> {
+        let imported = module.import_section().map(|s| s.functions()).unwrap_or(0) as u32;
+        let signatures = super::defined_signatures(module)?;
+        let bodies: Vec<FuncBody> = module.code_section().map(|s| s.bodies().to_vec()).unwrap_or_default();
+
+        let mut call_sites: HashMap<u32, usize> = HashMap::new();
+        for body in &bodies {
+            for opcode in body.code().elements() {
+                if let Opcode::Call(func) = *opcode {
+                    *call_sites.entry(func).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut callees = HashMap::new();
+        for (index, body) in bodies.iter().enumerate() {
+            let func = imported + index as u32;
+            let size = body.code().elements().len();
+            let single = self.single_call_sites && call_sites.get(&func) == Some(&1);
+            if size <= self.max_size || single {
+                callees.insert(func, Callee { signature: &signatures[index], body, size });
+            }
+        }
+
+        let mut rewriter = Rewriter {
+            callees,
+            growth: 0,
+            max_growth: self.max_growth,
+            inlined: 0,
+            next_local: 0,
+            new_locals: Vec::new(),
+        };
+        for section in module.sections_mut() {
+            if let Section::Code(ref mut code_section) = *section {
+                for (index, body) in code_section.bodies_mut().iter_mut().enumerate() {
+                    let params = signatures[index].params().len() as u32;
+                    rewriter.next_local = body.locals().iter().fold(params, |count, local| count + local.count());
+                    rewriter.visit_func_body(imported + index as u32, body);
+                }
+            }
+        }
+        Ok(rewriter.inlined)
+    }
+}
+
+struct Callee<'a> {
+    signature: &'a FunctionType,
+    body: &'a FuncBody,
+    size: usize,
+}
+
+impl<'a> Callee<'a> {
+    /// Inlined body of the callee with its locals starting from `first_local`.
+    fn expand(&self, first_local: u32) -> (Vec<Opcode>, Vec<ValueType>) {
+        let mut local_types: Vec<ValueType> = self.signature.params().to_vec();
+        for local in self.body.locals() {
+            local_types.extend((0..local.count()).map(|_| local.value_type()));
+        }
+        let params = self.signature.params().len();
+        let local = |index: u32| first_local + index;
+
+        let mut result = Vec::with_capacity(self.size + local_types.len() * 2 + 1);
+        for index in (0..params as u32).rev() {
+            result.push(Opcode::SetLocal(local(index)));
+        }
+        for (index, value_type) in local_types.iter().enumerate().skip(params) {
+            result.push(match *value_type {
+                ValueType::I32 => Opcode::I32Const(0),
+                ValueType::I64 => Opcode::I64Const(0),
+                ValueType::F32 => Opcode::F32Const(0),
+                ValueType::F64 => Opcode::F64Const(0),
+            });
+            result.push(Opcode::SetLocal(local(index as u32)));
+        }
+        result.push(Opcode::Block(match self.signature.return_type() {
+            Some(value_type) => BlockType::Value(value_type),
+            None => BlockType::NoResult,
+        }));
+
+        // depth of the opcode inside the callee body
+        let mut depth = 0u32;
+        for opcode in self.body.code().elements() {
+            result.push(match *opcode {
+                Opcode::GetLocal(index) => Opcode::GetLocal(local(index)),
+                Opcode::SetLocal(index) => Opcode::SetLocal(local(index)),
+                Opcode::TeeLocal(index) => Opcode::TeeLocal(local(index)),
+                Opcode::Return => Opcode::Br(depth),
+                ref opcode => opcode.clone(),
+            });
+            match *opcode {
+                Opcode::Block(_) | Opcode::Loop(_) | Opcode::If(_) => depth += 1,
+                Opcode::End => depth = depth.saturating_sub(1),
+                _ => {},
+            }
+        }
+
+        (result, local_types)
+    }
+}
+
+struct Rewriter<'a> {
+    callees: HashMap<u32, Callee<'a>>,
+    growth: usize,
+    max_growth: usize,
+    inlined: usize,
+    /// Index of the next local of the current caller.
+    next_local: u32,
+    /// Locals added to the current caller.
+    new_locals: Vec<ValueType>,
+}
+
+impl<'a> VisitorMut for Rewriter<'a> {
+    fn visit_func_body(&mut self, func: u32, body: &mut FuncBody) {
+        self.new_locals.clear();
+        walk_func_body_mut(self, func, body);
+        for value_type in self.new_locals.drain(..) {
+            body.locals_mut().push(Local::new(1, value_type));
+        }
+    }
+
+    fn visit_call(&mut self, editor: &mut Editor, opcode: &mut Opcode) {
+        let func = match *opcode {
+            Opcode::Call(func) if func != editor.location().func => func,
+            _ => return,
+        };
+        let callee = match self.callees.get(&func) {
+            Some(callee) => callee,
+            None => return,
+        };
+        let (opcodes, locals) = callee.expand(self.next_local);
+        if self.growth + opcodes.len() > self.max_growth {
+            return;
+        }
+
+        self.growth += opcodes.len();
+        self.inlined += 1;
+        self.next_local += locals.len() as u32;
+        self.new_locals.extend(locals);
+        for opcode in opcodes {
+            editor.insert_before(opcode);
+        }
+        editor.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Opcodes, BlockType, ExportEntry, Internal, Local, ValueType};
+    use validation::validate_module;
+    use super::Inliner;
+
+    #[test]
+    fn accessor() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .memory().with_min(1).build()
+            // 0: accessor
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body()
+                    .with_locals(vec![Local::new(1, ValueType::I32)])
+                    .with_opcodes(Opcodes::new(vec![
+                        GetLocal(0),
+                        I32Eqz,
+                        If(BlockType::NoResult),
+                            I32Const(-1),
+                            Return,
+                        End,
+                        GetLocal(0),
+                        I32Load(2, 0),
+                        TeeLocal(1),
+                        End,
+                    ]))
+                    .build()
+                .build()
+            // 1: caller
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![
+                    GetLocal(0),
+                    Call(0),
+                    GetLocal(0),
+                    Call(0),
+                    I32Add,
+                    End,
+                ])).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(1)))
+            .build();
+
+        let inlined = Inliner::new().inline(&mut module).expect("inlining to succeed");
+        assert_eq!(inlined, 2);
+        validate_module(&module).expect("module to validate after inlining");
+
+        let caller = &module.code_section().unwrap().bodies()[1];
+        assert_eq!(caller.locals().len(), 4);
+        let code = caller.code().elements();
+        assert!(!code.contains(&Call(0)));
+        assert_eq!(&code[..6], &[
+            GetLocal(0),
+            SetLocal(1),
+            I32Const(0),
+            SetLocal(2),
+            Block(BlockType::Value(ValueType::I32)),
+            GetLocal(1),
+        ][..]);
+        assert!(code.contains(&Br(1)));
+        assert!(code.contains(&TeeLocal(4)));
+    }
+
+    #[test]
+    fn budget_and_recursion() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Call(0), End])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Call(0), Call(0), End])).build()
+                .build()
+            .build();
+
+        let inlined = Inliner::new().with_max_growth(3).inline(&mut module).expect("inlining to succeed");
+        assert_eq!(inlined, 1);
+        let bodies = module.code_section().unwrap().bodies();
+        // recursive call is never inlined into the function itself
+        assert_eq!(bodies[0].code().elements(), &[Call(0), End][..]);
+        assert_eq!(bodies[1].code().elements(), &[Block(BlockType::NoResult), Call(0), End, Call(0), End][..]);
+    }
+}
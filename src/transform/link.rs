@@ -0,0 +1,764 @@
+//! Static linking of modules.
+//!
+//! Merges several modules into one, binding imports of one module to the
+//! matching exports of another. Imports which can not be resolved stay
+//! imports of the merged module (identical ones are merged). Types are
+//! deduplicated; functions, globals, tables and memories are concatenated in
+//! the order modules are added, after the remaining imports.
+//!
+//! Constant expressions can only read imported globals, so modules using
+//! an imported global resolved to a definition this way can not be linked.
+
+use std::collections::HashMap;
+use elements::{
+    Module, Section, Type, FunctionType, GlobalType, External, Internal, ImportEntry,
+    ExportEntry, Opcode, Opcodes, Func, FuncBody, NameSection, NameMap,
+    TypeSection, ImportSection, FunctionSection, TableSection, MemorySection,
+    GlobalSection, ExportSection, ElementSection, CodeSection, DataSection,
+};
+use super::Error;
+use super::remap::{Remap, IndexMap, IndexSpace};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Function,
+    Table,
+    Memory,
+    Global,
+}
+
+const KINDS: [Kind; 4] = [Kind::Function, Kind::Table, Kind::Memory, Kind::Global];
+
+impl Kind {
+    fn of_external(external: &External) -> Kind {
+        match *external {
+            External::Function(_) => Kind::Function,
+            External::Table(_) => Kind::Table,
+            External::Memory(_) => Kind::Memory,
+            External::Global(_) => Kind::Global,
+        }
+    }
+
+    fn of_internal(internal: &Internal) -> (Kind, u32) {
+        match *internal {
+            Internal::Function(index) => (Kind::Function, index),
+            Internal::Table(index) => (Kind::Table, index),
+            Internal::Memory(index) => (Kind::Memory, index),
+            Internal::Global(index) => (Kind::Global, index),
+        }
+    }
+}
+
+/// Index spaces of a single module being linked.
+struct Layout {
+    types: Vec<FunctionType>,
+    imports: Vec<ImportEntry>,
+    /// Import entry of every imported item, per kind.
+    imported: [Vec<usize>; 4],
+    /// Number of defined items, per kind.
+    defined: [u32; 4],
+    /// Signatures of all functions, imported first.
+    functions: Vec<Option<FunctionType>>,
+    /// Types of all globals, imported first.
+    globals: Vec<GlobalType>,
+    exports: HashMap<String, Internal>,
+}
+
+impl Layout {
+    fn new(module: &Module) -> Self {
+        let types: Vec<FunctionType> = module.type_section()
+            .map(|s| s.types().iter().map(|t| match *t { Type::Function(ref f) => f.clone() }).collect())
+            .unwrap_or_default();
+        let signature = |type_ref: u32| types.get(type_ref as usize).cloned();
+
+        let imports = module.import_section().map(|s| s.entries().to_vec()).unwrap_or_default();
+        let mut imported: [Vec<usize>; 4] = Default::default();
+        let mut functions = Vec::new();
+        let mut globals = Vec::new();
+        for (position, entry) in imports.iter().enumerate() {
+            imported[Kind::of_external(entry.external()) as usize].push(position);
+            match *entry.external() {
+                External::Function(type_ref) => functions.push(signature(type_ref)),
+                External::Global(ref global_type) => globals.push(global_type.clone()),
+                _ => {},
+            }
+        }
+        if let Some(function_section) = module.function_section() {
+            functions.extend(function_section.entries().iter().map(|f| signature(f.type_ref())));
+        }
+        if let Some(global_section) = module.global_section() {
+            globals.extend(global_section.entries().iter().map(|g| g.global_type().clone()));
+        }
+
+        let defined = [
+            module.function_section().map(|s| s.entries().len() as u32).unwrap_or(0),
+            module.table_section().map(|s| s.entries().len() as u32).unwrap_or(0),
+            module.memory_section().map(|s| s.entries().len() as u32).unwrap_or(0),
+            module.global_section().map(|s| s.entries().len() as u32).unwrap_or(0),
+        ];
+        let exports = module.export_section()
+            .map(|s| s.entries().iter().map(|e| (e.field().to_owned(), *e.internal())).collect())
+            .unwrap_or_default();
+
+        Layout { types, imports, imported, defined, functions, globals, exports }
+    }
+}
+
+/// Final location of an imported item.
+#[derive(Clone, Copy)]
+enum Target {
+    /// Item defined by the module.
+    Defined(usize, u32),
+    /// Unresolved import entry of the module.
+    Import(usize, usize),
+}
+
+/// Static linker.
+#[derive(Default)]
+pub struct Linker {
+    modules: Vec<(String, Module)>,
+}
+
+impl Linker {
+    /// New linker without modules.
+    pub fn new() -> Self {
+        Linker::default()
+    }
+
+    /// Add module under the `name` other modules import it by.
+    pub fn with_module<S: Into<String>>(mut self, name: S, module: Module) -> Self {
+        self.modules.push((name.into(), module));
+        self
+    }
+
+    /// Link added modules into one.
+    ///
+    /// Exports of every module are kept. Multiple start functions are
+    /// called in the order of modules by the generated start function.
+    pub fn link(self) -> Result<Module, Error> {
+        let mut names = HashMap::new();
+        for (index, (name, _)) in self.modules.iter().enumerate() {
+            if names.insert(name.clone(), index).is_some() {
+                return Err(Error::Link(format!("Module {} is added twice", name)));
+            }
+        }
+        let layouts: Vec<Layout> = self.modules.iter().map(|(_, module)| Layout::new(module)).collect();
+        let resolver = Resolver { names: &names, layouts: &layouts };
+
+        let mut types = TypeTable::default();
+        let mut imports: [Vec<ImportEntry>; 4] = Default::default();
+        let mut targets: Vec<[Vec<Target>; 4]> = Vec::new();
+        for (module, layout) in layouts.iter().enumerate() {
+            let mut module_targets: [Vec<Target>; 4] = Default::default();
+            for kind in &KINDS {
+                for index in 0..layout.imported[*kind as usize].len() as u32 {
+                    module_targets[*kind as usize].push(resolver.resolve(module, *kind, index, 0)?);
+                }
+            }
+            targets.push(module_targets);
+        }
+
+        // Unresolved imports come first in every index space of the merged module.
+        let mut import_positions: HashMap<(usize, usize), u32> = HashMap::new();
+        for module_targets in &targets {
+            for kind in &KINDS {
+                for target in &module_targets[*kind as usize] {
+                    if let Target::Import(owner, entry) = *target {
+                        if import_positions.contains_key(&(owner, entry)) {
+                            continue;
+                        }
+                        let mut import = layouts[owner].imports[entry].clone();
+                        if let External::Function(ref mut type_ref) = *import.external_mut() {
+                            let function_type = layouts[owner].types.get(*type_ref as usize)
+                                .ok_or(Error::InvalidIndex { space: IndexSpace::Type, index: *type_ref })?;
+                            *type_ref = types.index(function_type);
+                        }
+                        let same_kind = &mut imports[*kind as usize];
+                        let position = match same_kind.iter().position(|existing| *existing == import) {
+                            Some(position) => position,
+                            None => { same_kind.push(import); same_kind.len() - 1 },
+                        };
+                        import_positions.insert((owner, entry), position as u32);
+                    }
+                }
+            }
+        }
+
+        let mut offsets: Vec<[u32; 4]> = Vec::new();
+        let mut next = [0u32; 4];
+        for kind in &KINDS {
+            next[*kind as usize] = imports[*kind as usize].len() as u32;
+        }
+        for layout in &layouts {
+            offsets.push(next);
+            for kind in &KINDS {
+                next[*kind as usize] += layout.defined[*kind as usize];
+            }
+        }
+        if next[Kind::Table as usize] > 1 {
+            return Err(Error::Link("Linked modules have more than one table".into()));
+        }
+        if next[Kind::Memory as usize] > 1 {
+            return Err(Error::Link("Linked modules have more than one memory".into()));
+        }
+
+        let new_index = |target: Target, kind: Kind| match target {
+            Target::Defined(module, index) =>
+                offsets[module][kind as usize] + index - layouts[module].imported[kind as usize].len() as u32,
+            Target::Import(owner, entry) => import_positions[&(owner, entry)],
+        };
+
+        let mut merged = Merged::default();
+        for (module, (_, mut source)) in self.modules.into_iter().enumerate() {
+            let layout = &layouts[module];
+            let map = |kind: Kind, len: usize| IndexMap::new((0..len as u32).map(|index| Some(
+                if (index as usize) < layout.imported[kind as usize].len() {
+                    new_index(targets[module][kind as usize][index as usize], kind)
+                } else {
+                    new_index(Target::Defined(module, index), kind)
+                }
+            )).collect());
+
+            check_constant_globals(&source, layout, &targets[module][Kind::Global as usize])?;
+            drop_resolved_names(&mut source, &targets[module][Kind::Function as usize])?;
+            Remap::new()
+                .with_functions(map(Kind::Function, layout.functions.len()))
+                .with_globals(map(Kind::Global, layout.globals.len()))
+                .with_types(IndexMap::new(layout.types.iter().map(|t| Some(types.index(t))).collect()))
+                .apply(&mut source)?;
+            merged.add(source)?;
+        }
+
+        merged.into_module(types, imports)
+    }
+}
+
+/// Constant expressions can only read imported globals, so an imported global
+/// resolved to the definition of another module can not be used there.
+fn check_constant_globals(module: &Module, layout: &Layout, targets: &[Target]) -> Result<(), Error> {
+    let mut expressions: Vec<&[Opcode]> = Vec::new();
+    if let Some(global_section) = module.global_section() {
+        expressions.extend(global_section.entries().iter().map(|g| g.init_expr().code()));
+    }
+    if let Some(data_section) = module.data_section() {
+        expressions.extend(data_section.entries().iter().map(|d| d.offset().code()));
+    }
+    if let Some(element_section) = module.elements_section() {
+        expressions.extend(element_section.entries().iter().map(|e| e.offset().code()));
+    }
+    for opcode in expressions.into_iter().flat_map(|code| code.iter()) {
+        if let Opcode::GetGlobal(index) = *opcode {
+            if let Some(&Target::Defined(..)) = targets.get(index as usize) {
+                let entry = &layout.imports[layout.imported[Kind::Global as usize][index as usize]];
+                return Err(Error::Link(format!(
+                    "Import {}.{} is resolved to a defined global, but is used in a constant expression",
+                    entry.module(), entry.field(),
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove names of the imported functions resolved to the definition of
+/// another module, so that the name given by the defining module is kept.
+fn drop_resolved_names(module: &mut Module, targets: &[Target]) -> Result<(), Error> {
+    for section in module.sections_mut() {
+        if let Section::Custom(ref mut custom_section) = *section {
+            let mut names = match NameSection::from_custom(custom_section) {
+                Some(names) => names?,
+                None => continue,
+            };
+            if let Some(ref mut functions) = *names.functions_mut() {
+                functions.names_mut()
+                    .retain(|&index, _| !matches!(targets.get(index as usize), Some(&Target::Defined(..))));
+            }
+            *custom_section = names.into_custom()?;
+        }
+    }
+    Ok(())
+}
+
+struct Resolver<'a> {
+    names: &'a HashMap<String, usize>,
+    layouts: &'a [Layout],
+}
+
+impl<'a> Resolver<'a> {
+    /// Follow the import `index` of the `kind` of the `module` to its definition.
+    fn resolve(&self, module: usize, kind: Kind, index: u32, depth: usize) -> Result<Target, Error> {
+        let layout = &self.layouts[module];
+        let entry_index = match layout.imported[kind as usize].get(index as usize) {
+            Some(entry_index) => *entry_index,
+            None => return Ok(Target::Defined(module, index)),
+        };
+        let entry = &layout.imports[entry_index];
+        let exporter = match self.names.get(entry.module()) {
+            Some(exporter) => *exporter,
+            None => return Ok(Target::Import(module, entry_index)),
+        };
+        let (export_kind, export_index) = match self.layouts[exporter].exports.get(entry.field()) {
+            Some(internal) => Kind::of_internal(internal),
+            None => return Ok(Target::Import(module, entry_index)),
+        };
+
+        let incompatible = || Error::IncompatibleImport {
+            module: entry.module().to_owned(),
+            field: entry.field().to_owned(),
+        };
+        if export_kind != kind {
+            return Err(incompatible());
+        }
+        let compatible = match *entry.external() {
+            External::Function(type_ref) => {
+                let expected = layout.types.get(type_ref as usize);
+                let actual = self.layouts[exporter].functions.get(export_index as usize);
+                expected.is_some() && actual.map(|f| f.as_ref()) == Some(expected)
+            },
+            External::Global(ref global_type) =>
+                self.layouts[exporter].globals.get(export_index as usize) == Some(global_type),
+            _ => true,
+        };
+        if !compatible {
+            return Err(incompatible());
+        }
+        if depth > self.layouts.len() {
+            return Err(Error::Link(format!("Import {}.{} is resolved cyclically", entry.module(), entry.field())));
+        }
+
+        self.resolve(exporter, kind, export_index, depth + 1)
+    }
+}
+
+/// Deduplicated function types of the merged module.
+#[derive(Default)]
+struct TypeTable(Vec<FunctionType>);
+
+impl TypeTable {
+    fn index(&mut self, function_type: &FunctionType) -> u32 {
+        match self.0.iter().position(|existing| existing == function_type) {
+            Some(index) => index as u32,
+            None => {
+                self.0.push(function_type.clone());
+                self.0.len() as u32 - 1
+            },
+        }
+    }
+}
+
+/// Sections of the merged module being assembled.
+#[derive(Default)]
+struct Merged {
+    functions: Vec<Func>,
+    bodies: Vec<FuncBody>,
+    tables: TableSection,
+    memories: MemorySection,
+    globals: GlobalSection,
+    exports: Vec<ExportEntry>,
+    starts: Vec<u32>,
+    elements: ElementSection,
+    data: DataSection,
+    names: Option<NameSection>,
+    custom: Vec<Section>,
+}
+
+impl Merged {
+    /// Append sections of the already renumbered module.
+    fn add(&mut self, module: Module) -> Result<(), Error> {
+        for section in module.into_sections() {
+            match section {
+                Section::Type(_) | Section::Import(_) => {},
+                Section::Function(mut s) => self.functions.append(s.entries_mut()),
+                Section::Code(mut s) => self.bodies.append(s.bodies_mut()),
+                Section::Table(mut s) => self.tables.entries_mut().append(s.entries_mut()),
+                Section::Memory(mut s) => self.memories.entries_mut().append(s.entries_mut()),
+                Section::Global(mut s) => self.globals.entries_mut().append(s.entries_mut()),
+                Section::Export(mut s) => {
+                    for entry in s.entries_mut().drain(..) {
+                        if self.exports.iter().any(|e| e.field() == entry.field()) {
+                            return Err(Error::Link(format!("Export {} is defined twice", entry.field())));
+                        }
+                        self.exports.push(entry);
+                    }
+                },
+                Section::Start(index) => self.starts.push(index),
+                Section::Element(mut s) => self.elements.entries_mut().append(s.entries_mut()),
+                Section::Data(mut s) => self.data.entries_mut().append(s.entries_mut()),
+                Section::Custom(custom) => match NameSection::from_custom(&custom) {
+                    Some(names) => self.add_names(names?),
+                    None => self.custom.push(Section::Custom(custom)),
+                },
+                section @ Section::Unparsed { .. } => self.custom.push(section),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_names(&mut self, mut names: NameSection) {
+        let merged = match self.names {
+            Some(ref mut merged) => merged,
+            None => {
+                self.names = Some(names);
+                return;
+            },
+        };
+        if let Some(functions) = names.functions_mut().take() {
+            merged.functions_mut().get_or_insert_with(NameMap::default)
+                .names_mut().extend(functions.names().clone());
+        }
+        if let Some(locals) = names.locals_mut().take() {
+            merged.locals_mut().get_or_insert_with(Default::default).extend(locals);
+        }
+    }
+
+    fn into_module(mut self, mut types: TypeTable, imports: [Vec<ImportEntry>; 4]) -> Result<Module, Error> {
+        let start = match self.starts.len() {
+            0 => None,
+            1 => Some(self.starts[0]),
+            _ => {
+                let type_ref = types.index(&FunctionType::default());
+                let mut opcodes: Vec<Opcode> = self.starts.iter().map(|index| Opcode::Call(*index)).collect();
+                opcodes.push(Opcode::End);
+                let imported_functions = imports[Kind::Function as usize].len();
+                self.functions.push(Func::new(type_ref));
+                self.bodies.push(FuncBody::new(Vec::new(), Opcodes::new(opcodes)));
+                Some((imported_functions + self.functions.len() - 1) as u32)
+            },
+        };
+
+        let mut sections = Vec::new();
+        if !types.0.is_empty() {
+            sections.push(Section::Type(TypeSection::with_types(types.0.into_iter().map(Type::Function).collect())));
+        }
+        let imports: Vec<ImportEntry> = imports.iter().flat_map(|entries| entries.iter().cloned()).collect();
+        if !imports.is_empty() {
+            sections.push(Section::Import(ImportSection::with_entries(imports)));
+        }
+        if !self.functions.is_empty() {
+            sections.push(Section::Function(FunctionSection::with_entries(self.functions)));
+        }
+        if !self.tables.entries().is_empty() {
+            sections.push(Section::Table(self.tables));
+        }
+        if !self.memories.entries().is_empty() {
+            sections.push(Section::Memory(self.memories));
+        }
+        if !self.globals.entries().is_empty() {
+            sections.push(Section::Global(self.globals));
+        }
+        if !self.exports.is_empty() {
+            sections.push(Section::Export(ExportSection::with_entries(self.exports)));
+        }
+        if let Some(start) = start {
+            sections.push(Section::Start(start));
+        }
+        if !self.elements.entries().is_empty() {
+            sections.push(Section::Element(self.elements));
+        }
+        if !self.bodies.is_empty() {
+            sections.push(Section::Code(CodeSection::with_bodies(self.bodies)));
+        }
+        if !self.data.entries().is_empty() {
+            sections.push(Section::Data(self.data));
+        }
+        if let Some(names) = self.names {
+            sections.push(Section::Custom(names.into_custom()?));
+        }
+        sections.extend(self.custom);
+
+        Ok(Module::new(sections))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::BTreeMap;
+    use builder::{module, signature};
+    use elements::{
+        Module, Section, Opcode, Opcodes, ExportEntry, Internal, ImportEntry, External, GlobalType,
+        ValueType, MemoryType, NameSection, NameMap,
+    };
+    use super::super::Error;
+    use super::Linker;
+
+    fn with_function_names(mut module: Module, names: &[(u32, &str)]) -> Module {
+        let mut name_section = NameSection::default();
+        *name_section.functions_mut() = Some(NameMap::new(
+            names.iter().map(|&(index, name)| (index, name.to_owned())).collect::<BTreeMap<_, _>>()
+        ));
+        module.sections_mut().push(Section::Custom(name_section.into_custom().unwrap()));
+        module
+    }
+
+    fn function_names(module: &Module) -> BTreeMap<u32, String> {
+        module.sections().iter()
+            .filter_map(|section| match *section {
+                Section::Custom(ref custom) => NameSection::from_custom(custom),
+                _ => None,
+            })
+            .next()
+            .expect("name section to exist")
+            .expect("name section to parse")
+            .functions()
+            .map(|functions| functions.names().clone())
+            .unwrap_or_default()
+    }
+
+    fn link_error(linker: Linker) -> String {
+        match linker.link() {
+            Err(Error::Link(message)) => message,
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn resolves_imports() {
+        use elements::Opcode::*;
+
+        let lib = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .function()
+                .signature().param().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![GetLocal(0), Call(0), End])).build()
+                .build()
+            .global().value_type().i32().init_expr(Opcode::I32Const(42)).build()
+            .with_export(ExportEntry::new("print".into(), Internal::Function(1)))
+            .with_export(ExportEntry::new("answer".into(), Internal::Global(0)))
+            .build();
+
+        let main = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .with_import(ImportEntry::new("lib".into(), "print".into(), External::Function(0)))
+            .with_import(ImportEntry::new("lib".into(), "answer".into(), External::Global(GlobalType::new(ValueType::I32, false))))
+            .function()
+                .signature().param().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![GetGlobal(0), Call(1), GetLocal(0), Call(0), End])).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(2)))
+            .build();
+
+        let linked = Linker::new()
+            .with_module("lib", lib)
+            .with_module("main", main)
+            .link()
+            .expect("link to succeed");
+
+        // env.log is shared, lib.print and lib.answer are resolved
+        let imports = linked.import_section().expect("env.log to stay imported").entries();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].field(), "log");
+        assert_eq!(linked.type_section().unwrap().types().len(), 1);
+        assert_eq!(linked.function_section().unwrap().entries().len(), 2);
+        assert_eq!(linked.global_section().unwrap().entries().len(), 1);
+
+        let bodies = linked.code_section().unwrap().bodies();
+        assert_eq!(bodies[0].code().elements(), &[GetLocal(0), Call(0), End][..]);
+        assert_eq!(bodies[1].code().elements(), &[GetGlobal(0), Call(1), GetLocal(0), Call(0), End][..]);
+
+        let exports = linked.export_section().unwrap().entries();
+        assert_eq!(exports.len(), 3);
+        assert_eq!(*exports[2].internal(), Internal::Function(2));
+    }
+
+    #[test]
+    fn multiple_starts_and_incompatible_import() {
+        let a = module()
+            .function().signature().build().body().build().build()
+            .with_section(::elements::Section::Start(0))
+            .with_export(ExportEntry::new("f".into(), Internal::Function(0)))
+            .build();
+        let b = module()
+            .function().signature().build().body().build().build()
+            .with_section(::elements::Section::Start(0))
+            .build();
+
+        let linked = Linker::new().with_module("a", a.clone()).with_module("b", b).link().expect("link to succeed");
+        let start = linked.start_section().expect("start function to be generated");
+        assert_eq!(start, 2);
+        assert_eq!(
+            linked.code_section().unwrap().bodies()[2].code().elements(),
+            &[Opcode::Call(0), Opcode::Call(1), Opcode::End][..]
+        );
+
+        let c = module()
+            .with_import(ImportEntry::new("a".into(), "f".into(), External::Function(0)))
+            .function().signature().param().i32().build().body().build().build()
+            .build();
+        match Linker::new().with_module("a", a).with_module("c", c).link() {
+            Err(Error::IncompatibleImport { ref module, ref field }) if module == "a" && field == "f" => {},
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn resolved_global_in_constant_expression() {
+        let lib = module()
+            .global().value_type().i32().init_expr(Opcode::I32Const(42)).build()
+            .with_export(ExportEntry::new("answer".into(), Internal::Global(0)))
+            .build();
+        let main = module()
+            .with_import(ImportEntry::new("lib".into(), "answer".into(), External::Global(GlobalType::new(ValueType::I32, false))))
+            .global().value_type().i32().init_expr(Opcode::GetGlobal(0)).build()
+            .build();
+
+        let message = link_error(Linker::new().with_module("lib", lib).with_module("main", main));
+        assert_eq!(message, "Import lib.answer is resolved to a defined global, but is used in a constant expression");
+    }
+
+    #[test]
+    fn unresolved_global_in_constant_expression() {
+        let main = module()
+            .with_import(ImportEntry::new("env".into(), "base".into(), External::Global(GlobalType::new(ValueType::I32, false))))
+            .global().value_type().i32().init_expr(Opcode::GetGlobal(0)).build()
+            .build();
+        let other = module()
+            .global().value_type().i64().init_expr(Opcode::I64Const(1)).build()
+            .build();
+
+        let linked = Linker::new()
+            .with_module("other", other)
+            .with_module("main", main)
+            .link()
+            .expect("link to succeed");
+        let globals = linked.global_section().unwrap().entries();
+        assert_eq!(globals.len(), 2);
+        assert_eq!(globals[1].init_expr().code(), &[Opcode::GetGlobal(0), Opcode::End][..]);
+    }
+
+    #[test]
+    fn names_of_defining_module() {
+        let lib = with_function_names(module()
+            .function().signature().build().body().build().build()
+            .with_export(ExportEntry::new("f".into(), Internal::Function(0)))
+            .build(), &[(0, "lib_f")]);
+        let main = with_function_names(module()
+            .with_import(ImportEntry::new("lib".into(), "f".into(), External::Function(0)))
+            .with_import(ImportEntry::new("env".into(), "g".into(), External::Function(0)))
+            .function().signature().build().body().build().build()
+            .build(), &[(0, "imported_f"), (1, "env_g"), (2, "main")]);
+
+        // whatever the order of modules, the defining module names the function
+        let linked = Linker::new()
+            .with_module("lib", lib.clone())
+            .with_module("main", main.clone())
+            .link()
+            .expect("link to succeed");
+        let names = function_names(&linked);
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[&0], "env_g");
+        assert_eq!(names[&1], "lib_f");
+        assert_eq!(names[&2], "main");
+
+        let linked = Linker::new()
+            .with_module("main", main)
+            .with_module("lib", lib)
+            .link()
+            .expect("link to succeed");
+        let names = function_names(&linked);
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[&0], "env_g");
+        assert_eq!(names[&1], "main");
+        assert_eq!(names[&2], "lib_f");
+    }
+
+    #[test]
+    fn transitive_import() {
+        let a = module()
+            .function().signature().build().body().build().build()
+            .with_export(ExportEntry::new("f".into(), Internal::Function(0)))
+            .build();
+        let b = module()
+            .with_signatures(vec![signature().build_sig()])
+            .with_import(ImportEntry::new("a".into(), "f".into(), External::Function(0)))
+            .with_export(ExportEntry::new("g".into(), Internal::Function(0)))
+            .build();
+        let c = module()
+            .with_import(ImportEntry::new("b".into(), "g".into(), External::Function(0)))
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Opcode::Call(0), Opcode::End])).build()
+                .build()
+            .build();
+
+        let linked = Linker::new().with_module("a", a).with_module("b", b).with_module("c", c)
+            .link()
+            .expect("link to succeed");
+        assert!(linked.import_section().is_none());
+        let exports = linked.export_section().unwrap().entries();
+        assert_eq!(*exports[1].internal(), Internal::Function(0));
+        assert_eq!(linked.code_section().unwrap().bodies()[1].code().elements(), &[Opcode::Call(0), Opcode::End][..]);
+    }
+
+    #[test]
+    fn unresolved_imports() {
+        let a = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .with_import(ImportEntry::new("b".into(), "missing".into(), External::Function(0)))
+            .function().signature().build().body().build().build()
+            .build();
+        let b = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .function().signature().build().body().build().build()
+            .build();
+
+        let linked = Linker::new().with_module("a", a).with_module("b", b).link().expect("link to succeed");
+        let imports = linked.import_section().unwrap().entries();
+        assert_eq!(imports.len(), 2);
+        assert_eq!((imports[0].module(), imports[0].field()), ("env", "log"));
+        assert_eq!((imports[1].module(), imports[1].field()), ("b", "missing"));
+        assert_eq!(linked.function_section().unwrap().entries().len(), 2);
+    }
+
+    #[test]
+    fn cyclic_import() {
+        let a = module()
+            .with_signatures(vec![signature().build_sig()])
+            .with_import(ImportEntry::new("b".into(), "f".into(), External::Function(0)))
+            .with_export(ExportEntry::new("f".into(), Internal::Function(0)))
+            .build();
+        let b = module()
+            .with_signatures(vec![signature().build_sig()])
+            .with_import(ImportEntry::new("a".into(), "f".into(), External::Function(0)))
+            .with_export(ExportEntry::new("f".into(), Internal::Function(0)))
+            .build();
+
+        let message = link_error(Linker::new().with_module("a", a).with_module("b", b));
+        assert!(message.ends_with("is resolved cyclically"), "{}", message);
+    }
+
+    #[test]
+    fn conflicts() {
+        let exporter = || module()
+            .function().signature().build().body().build().build()
+            .with_export(ExportEntry::new("f".into(), Internal::Function(0)))
+            .build();
+        let message = link_error(Linker::new().with_module("a", exporter()).with_module("b", exporter()));
+        assert_eq!(message, "Export f is defined twice");
+
+        let message = link_error(Linker::new().with_module("a", module().build()).with_module("a", module().build()));
+        assert_eq!(message, "Module a is added twice");
+
+        let memory = || module().with_section(Section::Memory(::elements::MemorySection::with_entries(vec![
+            MemoryType::new(1, None),
+        ]))).build();
+        let message = link_error(Linker::new().with_module("a", memory()).with_module("b", memory()));
+        assert_eq!(message, "Linked modules have more than one memory");
+    }
+
+    #[test]
+    fn incompatible_global_import() {
+        let lib = module()
+            .global().value_type().i32().init_expr(Opcode::I32Const(42)).build()
+            .with_export(ExportEntry::new("answer".into(), Internal::Global(0)))
+            .build();
+        let main = module()
+            .with_import(ImportEntry::new("lib".into(), "answer".into(), External::Global(GlobalType::new(ValueType::I64, false))))
+            .build();
+
+        match Linker::new().with_module("lib", lib).with_module("main", main).link() {
+            Err(Error::IncompatibleImport { ref module, ref field }) if module == "lib" && field == "answer" => {},
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
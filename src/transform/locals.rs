@@ -0,0 +1,256 @@
+//! Unused local removal.
+//!
+//! Stores to locals which are never read are replaced by `drop`, locals
+//! which are only read are replaced by zero constants, and the locals left
+//! unreferenced are removed. The remaining declarations are grouped by value
+//! type, so every type takes a single `Local` entry.
+
+use elements::{Module, Section, Opcode, FuncBody, Local, ValueType};
+use super::Error;
+
+/// Number of items removed from the function bodies.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Report {
+    /// Removed local declarations.
+    pub locals: usize,
+    /// Removed stores to the locals which are never read.
+    pub stores: usize,
+}
+
+/// Remove unused locals and dead stores from every function of the module.
+pub fn compact_locals(module: &mut Module) -> Result<Report, Error> {
+    let signatures = super::defined_signatures(module)?;
+    let mut report = Report::default();
+    for section in module.sections_mut() {
+        if let Section::Code(ref mut code_section) = *section {
+            for (body, signature) in code_section.bodies_mut().iter_mut().zip(&signatures) {
+                let func_report = compact_func_locals(signature.params().len() as u32, body);
+                report.locals += func_report.locals;
+                report.stores += func_report.stores;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    reads: bool,
+    writes: bool,
+}
+
+/// Remove unused locals and dead stores from the function body with `params` parameters.
+pub fn compact_func_locals(params: u32, body: &mut FuncBody) -> Report {
+    let mut types = Vec::new();
+    for local in body.locals() {
+        types.extend((0..local.count()).map(|_| local.value_type()));
+    }
+    let total = params as usize + types.len();
+
+    let mut usage = vec![Usage::default(); total];
+    for opcode in body.code().elements() {
+        match *opcode {
+            Opcode::GetLocal(index) => if let Some(used) = usage.get_mut(index as usize) {
+                used.reads = true;
+            },
+            Opcode::SetLocal(index) | Opcode::TeeLocal(index) => if let Some(used) = usage.get_mut(index as usize) {
+                used.writes = true;
+            },
+            _ => {},
+        }
+    }
+    // out of range indices are left as they are for the validation to report
+    let is_declared = |index: u32| index >= params && (index as usize) < total;
+    let is_read = |index: u32| usage.get(index as usize).map_or(true, |used| used.reads);
+
+    let mut report = Report::default();
+    let opcodes = body.code_mut().elements_mut();
+    let mut code = Vec::with_capacity(opcodes.len());
+    for opcode in opcodes.drain(..) {
+        match opcode {
+            Opcode::SetLocal(index) if !is_read(index) => {
+                report.stores += 1;
+                code.push(Opcode::Drop);
+            },
+            Opcode::TeeLocal(index) if !is_read(index) => {
+                report.stores += 1;
+            },
+            Opcode::GetLocal(index) if is_declared(index) && !usage[index as usize].writes => {
+                code.push(match types[(index - params) as usize] {
+                    ValueType::I32 => Opcode::I32Const(0),
+                    ValueType::I64 => Opcode::I64Const(0),
+                    ValueType::F32 => Opcode::F32Const(0),
+                    ValueType::F64 => Opcode::F64Const(0),
+                });
+            },
+            opcode => code.push(opcode),
+        }
+    }
+    *opcodes = code;
+
+    // only locals both read and written are referenced now; group them by type
+    // in the order the types are first declared
+    let mut order: Vec<ValueType> = Vec::new();
+    for value_type in &types {
+        if !order.contains(value_type) {
+            order.push(*value_type);
+        }
+    }
+    let mut mapping = vec![None; types.len()];
+    let mut locals = Vec::new();
+    let mut next = params;
+    for value_type in order {
+        let mut count = 0;
+        for (index, local_type) in types.iter().enumerate() {
+            let used = usage[params as usize + index];
+            if *local_type == value_type && used.reads && used.writes {
+                mapping[index] = Some(next + count);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            locals.push(Local::new(count, value_type));
+            next += count;
+        }
+    }
+    report.locals = types.len() - (next - params) as usize;
+
+    let remap = |index: u32| if is_declared(index) {
+        mapping[(index - params) as usize].expect("referenced locals are kept")
+    } else {
+        index
+    };
+    for opcode in body.code_mut().elements_mut() {
+        match *opcode {
+            Opcode::GetLocal(ref mut index) | Opcode::SetLocal(ref mut index) | Opcode::TeeLocal(ref mut index) => {
+                *index = remap(*index);
+            },
+            _ => {},
+        }
+    }
+    *body.locals_mut() = locals;
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Opcodes, Local, ValueType};
+    use validation::validate_module;
+    use super::{compact_locals, Report};
+
+    #[test]
+    fn unused_and_dead_stores() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body()
+                    .with_locals(vec![
+                        Local::new(2, ValueType::I32),
+                        Local::new(1, ValueType::I64),
+                        Local::new(1, ValueType::I32),
+                        Local::new(3, ValueType::F64),
+                    ])
+                    .with_opcodes(Opcodes::new(vec![
+                        // local 1 is never touched, locals 2 and 3 are only written
+                        GetLocal(0),
+                        SetLocal(2),
+                        I64Const(1),
+                        TeeLocal(3),
+                        Drop,
+                        // local 4 is read and written
+                        GetLocal(0),
+                        SetLocal(4),
+                        // local 5 is only read, locals 6 and 7 are never touched
+                        GetLocal(5),
+                        Drop,
+                        GetLocal(4),
+                        End,
+                    ]))
+                    .build()
+                .build()
+            .build();
+
+        let report = compact_locals(&mut module).expect("compaction to succeed");
+        assert_eq!(report, Report { locals: 6, stores: 2 });
+        validate_module(&module).expect("module to validate after compaction");
+
+        let body = &module.code_section().unwrap().bodies()[0];
+        assert_eq!(body.locals(), &[Local::new(1, ValueType::I32)][..]);
+        assert_eq!(body.code().elements(), &[
+            GetLocal(0),
+            Drop,
+            I64Const(1),
+            Drop,
+            GetLocal(0),
+            SetLocal(1),
+            F64Const(0),
+            Drop,
+            GetLocal(1),
+            End,
+        ][..]);
+    }
+
+    #[test]
+    fn grouping() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body()
+                    .with_locals(vec![
+                        Local::new(1, ValueType::I32),
+                        Local::new(1, ValueType::F32),
+                        Local::new(1, ValueType::I32),
+                    ])
+                    .with_opcodes(Opcodes::new(vec![
+                        GetLocal(2), SetLocal(1),
+                        GetLocal(1), SetLocal(2),
+                        GetLocal(0), SetLocal(0),
+                        End,
+                    ]))
+                    .build()
+                .build()
+            .build();
+
+        let report = compact_locals(&mut module).expect("compaction to succeed");
+        assert_eq!(report, Report { locals: 0, stores: 0 });
+
+        let body = &module.code_section().unwrap().bodies()[0];
+        assert_eq!(body.locals(), &[Local::new(2, ValueType::I32), Local::new(1, ValueType::F32)][..]);
+        assert_eq!(body.code().elements(), &[
+            GetLocal(1), SetLocal(2),
+            GetLocal(2), SetLocal(1),
+            GetLocal(0), SetLocal(0),
+            End,
+        ][..]);
+    }
+
+    #[test]
+    fn section_mismatch() {
+        use elements::{Section, CodeSection};
+        use transform::Error;
+
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .build();
+        for section in module.sections_mut() {
+            if let Section::Code(ref mut code_section) = *section {
+                *code_section = CodeSection::default();
+            }
+        }
+
+        match compact_locals(&mut module) {
+            Err(Error::SectionMismatch { functions: 1, bodies: 0 }) => {},
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Transformations of the module.
+
+use std::fmt;
+use elements;
+use analysis;
+
+pub mod remap;
+pub mod dce;
+pub mod link;
+pub mod gas;
+pub mod stack_height;
+pub mod trace;
+pub mod optimize;
+pub mod inline;
+pub mod locals;
+pub mod data;
+pub mod canonical;
+pub mod reduce;
+
+pub use self::remap::{Remap, IndexMap, IndexSpace};
+pub use self::dce::{eliminate_dead_code, Report as DceReport};
+pub use self::link::Linker;
+pub use self::gas::{inject_gas_counter, Rules as GasRules, Counter as GasCounter};
+pub use self::stack_height::limit_stack_height;
+pub use self::trace::Trace;
+pub use self::optimize::{optimize, optimize_func_body};
+pub use self::inline::Inliner;
+pub use self::locals::{compact_locals, compact_func_locals, Report as LocalsReport};
+pub use self::data::{DataPacker, Report as DataReport};
+pub use self::canonical::{canonicalize, content_hash};
+pub use self::reduce::{Reducer, Report as ReduceReport};
+
+/// Transformation error.
+#[derive(Debug)]
+pub enum Error {
+    /// Custom section of the module could not be (de)serialized.
+    Serialization(elements::Error),
+    /// Function body could not be analyzed.
+    Analysis(analysis::Error),
+    /// Reference to the item removed by the renumbering.
+    RemovedIndex {
+        /// Index space of the item.
+        space: IndexSpace,
+        /// Original index of the item.
+        index: u32,
+    },
+    /// Reference to the item out of the index space.
+    InvalidIndex {
+        /// Index space of the item.
+        space: IndexSpace,
+        /// Referenced index.
+        index: u32,
+    },
+    /// Import is bound to the export of the different kind or type.
+    IncompatibleImport {
+        /// Module of the import.
+        module: String,
+        /// Field of the import.
+        field: String,
+    },
+    /// Modules can not be linked together.
+    Link(String),
+    /// Function and code sections declare different number of functions.
+    SectionMismatch {
+        /// Entries of the function section.
+        functions: usize,
+        /// Bodies of the code section.
+        bodies: usize,
+    },
+    /// Module is not valid.
+    Validation(String),
+    /// Module to reduce does not satisfy the predicate.
+    Uninteresting,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Serialization(ref err) => write!(f, "Serialization error: {:?}", err),
+            Error::Analysis(ref err) => write!(f, "Analysis error: {}", err),
+            Error::RemovedIndex { space, index } => write!(f, "Reference to removed {:?} {}", space, index),
+            Error::InvalidIndex { space, index } => write!(f, "Reference to unknown {:?} {}", space, index),
+            Error::IncompatibleImport { ref module, ref field } =>
+                write!(f, "Import {}.{} does not match the export", module, field),
+            Error::Link(ref msg) => write!(f, "Link error: {}", msg),
+            Error::SectionMismatch { functions, bodies } =>
+                write!(f, "Function section has {} entries, but code section has {} bodies", functions, bodies),
+            Error::Validation(ref msg) => write!(f, "Validation error: {}", msg),
+            Error::Uninteresting => write!(f, "Module does not satisfy the predicate"),
+        }
+    }
+}
+
+impl From<elements::Error> for Error {
+    fn from(err: elements::Error) -> Self {
+        Error::Serialization(err)
+    }
+}
+
+impl From<analysis::Error> for Error {
+    fn from(err: analysis::Error) -> Self {
+        Error::Analysis(err)
+    }
+}
+
+/// Position of the known section in the module, `None` for custom sections.
+fn section_order(section: &elements::Section) -> Option<u8> {
+    use elements::Section;
+
+    match *section {
+        Section::Custom(_) | Section::Unparsed { .. } => None,
+        Section::Type(_) => Some(1),
+        Section::Import(_) => Some(2),
+        Section::Function(_) => Some(3),
+        Section::Table(_) => Some(4),
+        Section::Memory(_) => Some(5),
+        Section::Global(_) => Some(6),
+        Section::Export(_) => Some(7),
+        Section::Start(_) => Some(8),
+        Section::Element(_) => Some(9),
+        Section::Code(_) => Some(10),
+        Section::Data(_) => Some(11),
+    }
+}
+
+/// Position of the section of the same kind as `section`, inserting
+/// `section` at its place if the module has none.
+fn ensure_section(module: &mut elements::Module, section: elements::Section) -> usize {
+    let order = section_order(&section);
+    let sections = module.sections_mut();
+    if let Some(position) = sections.iter().position(|s| order.is_some() && section_order(s) == order) {
+        return position;
+    }
+    let position = sections.iter()
+        .position(|s| match (section_order(s), order) {
+            (Some(existing), Some(new)) => existing > new,
+            _ => false,
+        })
+        .unwrap_or(sections.len());
+    sections.insert(position, section);
+    position
+}
+
+/// Index of the function type in the module, adding it if there is none.
+fn ensure_type(module: &mut elements::Module, function_type: elements::FunctionType) -> u32 {
+    use elements::{Section, Type, TypeSection};
+
+    let position = ensure_section(module, Section::Type(TypeSection::default()));
+    match module.sections_mut()[position] {
+        Section::Type(ref mut type_section) => {
+            let types = type_section.types_mut();
+            let existing = types.iter().position(|t| match *t {
+                Type::Function(ref existing) => *existing == function_type,
+            });
+            match existing {
+                Some(index) => index as u32,
+                None => {
+                    types.push(Type::Function(function_type));
+                    types.len() as u32 - 1
+                },
+            }
+        },
+        _ => unreachable!("ensure_section returns section of the requested kind"),
+    }
+}
+
+/// Append the function defined by `body` with the signature `type_ref`, returning its index.
+fn add_function(module: &mut elements::Module, type_ref: u32, body: elements::FuncBody) -> u32 {
+    use elements::{Section, Func, FunctionSection, CodeSection};
+
+    let imported = module.import_section().map(|s| s.functions()).unwrap_or(0);
+    let position = ensure_section(module, Section::Function(FunctionSection::default()));
+    let defined = match module.sections_mut()[position] {
+        Section::Function(ref mut function_section) => {
+            function_section.entries_mut().push(Func::new(type_ref));
+            function_section.entries().len()
+        },
+        _ => unreachable!("ensure_section returns section of the requested kind"),
+    };
+    let position = ensure_section(module, Section::Code(CodeSection::default()));
+    if let Section::Code(ref mut code_section) = module.sections_mut()[position] {
+        code_section.bodies_mut().push(body);
+    }
+    (imported + defined - 1) as u32
+}
+
+/// Signatures of the functions defined in the module, in the order of the code section.
+fn defined_signatures(module: &elements::Module) -> Result<Vec<elements::FunctionType>, Error> {
+    use elements::Type;
+
+    let types = module.type_section().map(|s| s.types()).unwrap_or(&[]);
+    let mut signatures = Vec::new();
+    if let Some(function_section) = module.function_section() {
+        for func in function_section.entries() {
+            match types.get(func.type_ref() as usize) {
+                Some(Type::Function(signature)) => signatures.push(signature.clone()),
+                None => return Err(Error::InvalidIndex { space: IndexSpace::Type, index: func.type_ref() }),
+            }
+        }
+    }
+    let bodies = module.code_section().map(|s| s.bodies().len()).unwrap_or(0);
+    if signatures.len() != bodies {
+        return Err(Error::SectionMismatch { functions: signatures.len(), bodies });
+    }
+    Ok(signatures)
+}
@@ -0,0 +1,377 @@
+//! Peephole optimization of function bodies.
+//!
+//! Every function body is rewritten until nothing changes:
+//!
+//! - integer constant expressions are folded (never the ones which trap,
+//!   floating point ones are left as is);
+//! - `nop`s, pure values which are immediately dropped, and code following
+//!   `unreachable`, `br`, `br_table` and `return` are removed;
+//! - `set_local x; get_local x` becomes `tee_local x`, and
+//!   `tee_local x; drop` becomes `set_local x`;
+//! - blocks and loops which are never branched to are merged into the
+//!   enclosing block.
+
+use std::collections::HashSet;
+use elements::{Module, Section, Opcode, Opcodes, FuncBody};
+use analysis::tree::{Tree, Node, Label};
+use super::Error;
+
+/// Maximal number of rewriting rounds of the single function body.
+const MAX_ROUNDS: usize = 16;
+
+/// Optimize every function body of the module.
+pub fn optimize(module: &mut Module) -> Result<(), Error> {
+    for section in module.sections_mut() {
+        if let Section::Code(ref mut code_section) = *section {
+            for body in code_section.bodies_mut() {
+                optimize_func_body(body)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Optimize the function body, returning whether it has changed.
+pub fn optimize_func_body(body: &mut FuncBody) -> Result<bool, Error> {
+    let original = body.code().clone();
+    for _ in 0..MAX_ROUNDS {
+        let before = body.code().clone();
+        let opcodes = peephole(body.code().elements());
+        let mut tree = Tree::from_opcodes(&Opcodes::new(opcodes))?;
+        simplify_structure(&mut tree);
+        *body.code_mut() = tree.to_opcodes()?;
+        if *body.code() == before {
+            break;
+        }
+    }
+    Ok(*body.code() != original)
+}
+
+/// Remove dead code and merge blocks which are never branched to.
+fn simplify_structure(tree: &mut Tree) {
+    let mut referenced = HashSet::new();
+    collect_targets(tree.body(), &mut referenced);
+    simplify_nodes(tree.body_mut(), &referenced);
+}
+
+fn collect_targets(nodes: &[Node], referenced: &mut HashSet<Label>) {
+    for node in nodes {
+        match *node {
+            Node::Opcode(_) => {},
+            Node::Block(ref block) | Node::Loop(ref block) => collect_targets(&block.body, referenced),
+            Node::If(ref if_) => {
+                collect_targets(&if_.then_body, referenced);
+                if let Some(ref else_body) = if_.else_body {
+                    collect_targets(else_body, referenced);
+                }
+            },
+            Node::Br(label) | Node::BrIf(label) => { referenced.insert(label); },
+            Node::BrTable(ref table, default) => {
+                referenced.extend(table.iter().cloned());
+                referenced.insert(default);
+            },
+        }
+    }
+}
+
+fn is_terminal(node: &Node) -> bool {
+    matches!(*node, Node::Br(_) | Node::BrTable(_, _) | Node::Opcode(Opcode::Return) | Node::Opcode(Opcode::Unreachable))
+}
+
+fn simplify_nodes(nodes: &mut Vec<Node>, referenced: &HashSet<Label>) {
+    if let Some(position) = nodes.iter().position(is_terminal) {
+        nodes.truncate(position + 1);
+    }
+
+    let mut result = Vec::with_capacity(nodes.len());
+    for mut node in nodes.drain(..) {
+        match node {
+            Node::Block(ref mut block) | Node::Loop(ref mut block) => simplify_nodes(&mut block.body, referenced),
+            Node::If(ref mut if_) => {
+                simplify_nodes(&mut if_.then_body, referenced);
+                if let Some(ref mut else_body) = if_.else_body {
+                    simplify_nodes(else_body, referenced);
+                }
+            },
+            _ => {},
+        }
+        match node {
+            Node::Block(block) | Node::Loop(block) if !referenced.contains(&block.label) => {
+                result.extend(block.body);
+            },
+            node => result.push(node),
+        }
+    }
+    // merged block could end with the terminal node
+    if let Some(position) = result.iter().position(is_terminal) {
+        result.truncate(position + 1);
+    }
+    *nodes = result;
+}
+
+/// Local rewriting of the opcode sequence.
+fn peephole(opcodes: &[Opcode]) -> Vec<Opcode> {
+    let mut result: Vec<Opcode> = Vec::with_capacity(opcodes.len());
+    for opcode in opcodes {
+        if *opcode == Opcode::Nop {
+            continue;
+        }
+        result.push(opcode.clone());
+        while reduce(&mut result) {}
+    }
+    result
+}
+
+fn is_pure_value(opcode: &Opcode) -> bool {
+    matches!(
+        *opcode,
+        Opcode::I32Const(_) | Opcode::I64Const(_) | Opcode::F32Const(_) | Opcode::F64Const(_) |
+        Opcode::GetLocal(_) | Opcode::GetGlobal(_) | Opcode::CurrentMemory(_)
+    )
+}
+
+/// Rewrite the tail of the opcode sequence once, returning whether it has changed.
+fn reduce(opcodes: &mut Vec<Opcode>) -> bool {
+    let len = opcodes.len();
+    if len >= 3 {
+        if let Some(folded) = fold_binary(&opcodes[len - 3], &opcodes[len - 2], &opcodes[len - 1]) {
+            opcodes.truncate(len - 3);
+            opcodes.push(folded);
+            return true;
+        }
+    }
+    if len >= 2 {
+        let replacement = match (&opcodes[len - 2], &opcodes[len - 1]) {
+            (value, &Opcode::Drop) if is_pure_value(value) => Some(None),
+            (&Opcode::TeeLocal(local), &Opcode::Drop) => Some(Some(Opcode::SetLocal(local))),
+            (&Opcode::SetLocal(set), &Opcode::GetLocal(get)) if set == get => Some(Some(Opcode::TeeLocal(set))),
+            (value, operator) => fold_unary(value, operator).map(Some),
+        };
+        if let Some(replacement) = replacement {
+            opcodes.truncate(len - 2);
+            opcodes.extend(replacement);
+            return true;
+        }
+    }
+    false
+}
+
+fn fold_unary(value: &Opcode, operator: &Opcode) -> Option<Opcode> {
+    use elements::Opcode::*;
+
+    Some(match (value, operator) {
+        (&I32Const(a), &I32Eqz) => I32Const((a == 0) as i32),
+        (&I32Const(a), &I32Clz) => I32Const(a.leading_zeros() as i32),
+        (&I32Const(a), &I32Ctz) => I32Const(a.trailing_zeros() as i32),
+        (&I32Const(a), &I32Popcnt) => I32Const(a.count_ones() as i32),
+        (&I32Const(a), &I64ExtendSI32) => I64Const(a as i64),
+        (&I32Const(a), &I64ExtendUI32) => I64Const(a as u32 as i64),
+        (&I64Const(a), &I64Eqz) => I32Const((a == 0) as i32),
+        (&I64Const(a), &I64Clz) => I64Const(a.leading_zeros() as i64),
+        (&I64Const(a), &I64Ctz) => I64Const(a.trailing_zeros() as i64),
+        (&I64Const(a), &I64Popcnt) => I64Const(a.count_ones() as i64),
+        (&I64Const(a), &I32WarpI64) => I32Const(a as i32),
+        _ => return None,
+    })
+}
+
+fn fold_binary(lhs: &Opcode, rhs: &Opcode, operator: &Opcode) -> Option<Opcode> {
+    match (lhs, rhs) {
+        (&Opcode::I32Const(a), &Opcode::I32Const(b)) => fold_i32(a, b, operator),
+        (&Opcode::I64Const(a), &Opcode::I64Const(b)) => fold_i64(a, b, operator),
+        _ => None,
+    }
+}
+
+fn fold_i32(a: i32, b: i32, operator: &Opcode) -> Option<Opcode> {
+    use elements::Opcode::*;
+
+    let (ua, ub) = (a as u32, b as u32);
+    let value = match *operator {
+        I32Add => a.wrapping_add(b),
+        I32Sub => a.wrapping_sub(b),
+        I32Mul => a.wrapping_mul(b),
+        I32DivS => a.checked_div(b)?,
+        I32DivU => ua.checked_div(ub)? as i32,
+        I32RemS if b != 0 => a.wrapping_rem(b),
+        I32RemU => ua.checked_rem(ub)? as i32,
+        I32And => a & b,
+        I32Or => a | b,
+        I32Xor => a ^ b,
+        I32Shl => a.wrapping_shl(ub),
+        I32ShrS => a.wrapping_shr(ub),
+        I32ShrU => ua.wrapping_shr(ub) as i32,
+        I32Rotl => ua.rotate_left(ub % 32) as i32,
+        I32Rotr => ua.rotate_right(ub % 32) as i32,
+        I32Eq => (a == b) as i32,
+        I32Ne => (a != b) as i32,
+        I32LtS => (a < b) as i32,
+        I32LtU => (ua < ub) as i32,
+        I32GtS => (a > b) as i32,
+        I32GtU => (ua > ub) as i32,
+        I32LeS => (a <= b) as i32,
+        I32LeU => (ua <= ub) as i32,
+        I32GeS => (a >= b) as i32,
+        I32GeU => (ua >= ub) as i32,
+        _ => return None,
+    };
+    Some(I32Const(value))
+}
+
+fn fold_i64(a: i64, b: i64, operator: &Opcode) -> Option<Opcode> {
+    use elements::Opcode::*;
+
+    let (ua, ub) = (a as u64, b as u64);
+    let compare = |result: bool| Some(I32Const(result as i32));
+    let value = match *operator {
+        I64Add => a.wrapping_add(b),
+        I64Sub => a.wrapping_sub(b),
+        I64Mul => a.wrapping_mul(b),
+        I64DivS => a.checked_div(b)?,
+        I64DivU => ua.checked_div(ub)? as i64,
+        I64RemS if b != 0 => a.wrapping_rem(b),
+        I64RemU => ua.checked_rem(ub)? as i64,
+        I64And => a & b,
+        I64Or => a | b,
+        I64Xor => a ^ b,
+        I64Shl => a.wrapping_shl(ub as u32),
+        I64ShrS => a.wrapping_shr(ub as u32),
+        I64ShrU => ua.wrapping_shr(ub as u32) as i64,
+        I64Rotl => ua.rotate_left((ub % 64) as u32) as i64,
+        I64Rotr => ua.rotate_right((ub % 64) as u32) as i64,
+        I64Eq => return compare(a == b),
+        I64Ne => return compare(a != b),
+        I64LtS => return compare(a < b),
+        I64LtU => return compare(ua < ub),
+        I64GtS => return compare(a > b),
+        I64GtU => return compare(ua > ub),
+        I64LeS => return compare(a <= b),
+        I64LeU => return compare(ua <= ub),
+        I64GeS => return compare(a >= b),
+        I64GeU => return compare(ua >= ub),
+        _ => return None,
+    };
+    Some(I64Const(value))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Opcode, Opcodes, FuncBody, BlockType, ValueType, Local};
+    use validation::validate_module;
+    use super::{optimize, optimize_func_body};
+
+    fn optimized(opcodes: Vec<Opcode>) -> Vec<Opcode> {
+        let mut body = FuncBody::new(Vec::new(), Opcodes::new(opcodes));
+        optimize_func_body(&mut body).expect("optimization to succeed");
+        body.code().elements().to_vec()
+    }
+
+    #[test]
+    fn folding() {
+        use elements::Opcode::*;
+
+        assert_eq!(
+            optimized(vec![I32Const(2), I32Const(3), I32Add, I32Const(4), I32Mul, Nop, End]),
+            vec![I32Const(20), End]
+        );
+        assert_eq!(
+            optimized(vec![I64Const(-1), I64Const(60), I64ShrU, I64Const(15), I64Eq, End]),
+            vec![I32Const(1), End]
+        );
+        // traps are preserved
+        assert_eq!(
+            optimized(vec![I32Const(1), I32Const(0), I32DivS, End]),
+            vec![I32Const(1), I32Const(0), I32DivS, End]
+        );
+        assert_eq!(
+            optimized(vec![I32Const(::std::i32::MIN), I32Const(-1), I32DivS, End]),
+            vec![I32Const(::std::i32::MIN), I32Const(-1), I32DivS, End]
+        );
+        assert_eq!(
+            optimized(vec![I32Const(::std::i32::MIN), I32Const(-1), I32RemS, End]),
+            vec![I32Const(0), End]
+        );
+    }
+
+    #[test]
+    fn locals_and_drops() {
+        use elements::Opcode::*;
+
+        assert_eq!(
+            optimized(vec![GetLocal(0), SetLocal(1), GetLocal(1), GetLocal(0), Drop, End]),
+            vec![GetLocal(0), TeeLocal(1), End]
+        );
+        assert_eq!(
+            optimized(vec![GetLocal(0), TeeLocal(1), Drop, I32Const(1), Drop, End]),
+            vec![GetLocal(0), SetLocal(1), End]
+        );
+    }
+
+    #[test]
+    fn structure() {
+        use elements::Opcode::*;
+
+        assert_eq!(
+            optimized(vec![
+                Block(BlockType::NoResult),
+                    Block(BlockType::NoResult),
+                        GetLocal(0),
+                        BrIf(1),
+                        Return,
+                        GetLocal(0),
+                        Drop,
+                    End,
+                End,
+                Unreachable,
+                I32Const(1),
+                Drop,
+                End,
+            ]),
+            vec![
+                Block(BlockType::NoResult),
+                    GetLocal(0),
+                    BrIf(0),
+                    Return,
+                End,
+                Unreachable,
+                End,
+            ]
+        );
+    }
+
+    #[test]
+    fn module_stays_valid() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body()
+                    .with_locals(vec![Local::new(1, ValueType::I32)])
+                    .with_opcodes(Opcodes::new(vec![
+                        Block(BlockType::Value(ValueType::I32)),
+                            I32Const(6),
+                            I32Const(7),
+                            I32Mul,
+                            SetLocal(1),
+                            GetLocal(1),
+                            GetLocal(0),
+                            I32Add,
+                        End,
+                        Nop,
+                        End,
+                    ]))
+                    .build()
+                .build()
+            .build();
+
+        optimize(&mut module).expect("optimization to succeed");
+        validate_module(&module).expect("optimized module to validate");
+        assert_eq!(
+            module.code_section().unwrap().bodies()[0].code().elements(),
+            &[I32Const(42), TeeLocal(1), GetLocal(0), I32Add, End][..]
+        );
+    }
+}
@@ -0,0 +1,469 @@
+//! Test case reduction.
+//!
+//! `Reducer` shrinks the module while the user predicate keeps holding for
+//! it, for example while the module still triggers the bug being hunted.
+//! It repeatedly tries to remove sections, exports, element and data
+//! segments, functions and runs of instructions, to stub function bodies,
+//! to truncate data and to zero constants, keeping every candidate that is
+//! valid and satisfies the predicate, until no more candidates are kept.
+
+use std::{env, fs, process};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use elements::{self, Module, Section, Opcode, Opcodes, External, FuncBody};
+use validation::validate_module;
+use super::Error;
+use super::remap::{Remap, IndexMap};
+
+/// Statistics of the reduction.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Report {
+    /// Rounds over all reductions.
+    pub rounds: usize,
+    /// Candidates checked.
+    pub attempts: usize,
+    /// Candidates kept.
+    pub reductions: usize,
+    /// Serialized size of the original module.
+    pub original_size: usize,
+    /// Serialized size of the reduced module.
+    pub reduced_size: usize,
+}
+
+/// Reducer of the modules.
+#[derive(Debug, Clone)]
+pub struct Reducer {
+    max_rounds: usize,
+}
+
+impl Default for Reducer {
+    fn default() -> Self {
+        Reducer { max_rounds: 32 }
+    }
+}
+
+impl Reducer {
+    /// New reducer running at most 32 rounds.
+    pub fn new() -> Self {
+        Reducer::default()
+    }
+
+    /// Stop after `max_rounds` rounds even if the module can still be reduced.
+    pub fn with_max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    /// Reduce the module while `predicate` holds for it.
+    ///
+    /// Fails if the original module is not valid or does not satisfy the
+    /// predicate; the module is left untouched in that case.
+    pub fn reduce<F: FnMut(&Module) -> bool>(&self, module: &mut Module, predicate: F) -> Result<Report, Error> {
+        validate_module(module).map_err(|err| Error::Validation(err.to_string()))?;
+        let mut predicate = predicate;
+        if !predicate(module) {
+            return Err(Error::Uninteresting);
+        }
+
+        let original_size = elements::serialize(module.clone())?.len();
+        let mut reduction = Reduction {
+            module: module.clone(),
+            predicate,
+            report: Report { original_size, ..Report::default() },
+        };
+        while reduction.report.rounds < self.max_rounds {
+            reduction.report.rounds += 1;
+            if !reduction.round() {
+                break;
+            }
+        }
+
+        let Reduction { module: reduced, mut report, .. } = reduction;
+        report.reduced_size = elements::serialize(reduced.clone())?.len();
+        *module = reduced;
+        Ok(report)
+    }
+
+    /// Reduce the module while `program` with `args` exits successfully.
+    ///
+    /// Every candidate is written to a temporary file, unique to this call,
+    /// whose path is passed to the program as the last argument.
+    pub fn reduce_with_command(&self, module: &mut Module, program: &str, args: &[String]) -> Result<Report, Error> {
+        let candidate = CandidateFile::new();
+        self.reduce(module, |module| {
+            let bytes = match elements::serialize(module.clone()) {
+                Ok(bytes) => bytes,
+                Err(_) => return false,
+            };
+            if fs::write(&candidate.path, bytes).is_err() {
+                return false;
+            }
+            process::Command::new(program)
+                .args(args)
+                .arg(&candidate.path)
+                .stdout(process::Stdio::null())
+                .stderr(process::Stdio::null())
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Temporary file for the candidates of one reduction, removed when dropped.
+struct CandidateFile {
+    path: PathBuf,
+}
+
+impl CandidateFile {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("reduce-{}-{}.wasm", process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        CandidateFile { path: env::temp_dir().join(name) }
+    }
+}
+
+impl Drop for CandidateFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct Reduction<F> {
+    module: Module,
+    predicate: F,
+    report: Report,
+}
+
+impl<F: FnMut(&Module) -> bool> Reduction<F> {
+    /// Keep the result of `edit` if it is valid and interesting.
+    ///
+    /// `edit` returns `false` if it does not apply to the module.
+    fn attempt<E: FnOnce(&mut Module) -> bool>(&mut self, edit: E) -> bool {
+        let mut candidate = self.module.clone();
+        if !edit(&mut candidate) {
+            return false;
+        }
+        self.report.attempts += 1;
+        if validate_module(&candidate).is_err() || !(self.predicate)(&candidate) {
+            return false;
+        }
+        self.report.reductions += 1;
+        self.module = candidate;
+        true
+    }
+
+    /// Try every reduction once, returning whether any candidate was kept.
+    fn round(&mut self) -> bool {
+        let before = self.report.reductions;
+        self.sections();
+        self.entries();
+        self.functions();
+        self.bodies();
+        self.instructions();
+        self.constants();
+        self.data();
+        self.report.reductions > before
+    }
+
+    fn sections(&mut self) {
+        let mut index = 0;
+        while index < self.module.sections().len() {
+            if !self.attempt(|module| { module.sections_mut().remove(index); true }) {
+                index += 1;
+            }
+        }
+    }
+
+    /// Exports, element segments and data segments one by one.
+    fn entries(&mut self) {
+        for section in 0..self.module.sections().len() {
+            let mut index = 0;
+            while index < entry_count(&self.module.sections()[section]) {
+                let removed = self.attempt(|module| {
+                    match module.sections_mut()[section] {
+                        Section::Export(ref mut s) => { s.entries_mut().remove(index); },
+                        Section::Element(ref mut s) => { s.entries_mut().remove(index); },
+                        Section::Data(ref mut s) => { s.entries_mut().remove(index); },
+                        _ => return false,
+                    }
+                    true
+                });
+                if !removed {
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    /// Functions not referenced from anything else, imported ones included.
+    fn functions(&mut self) {
+        let mut index = 0;
+        while index < function_count(&self.module) {
+            if !self.attempt(|module| remove_function(module, index)) {
+                index += 1;
+            }
+        }
+    }
+
+    /// Bodies replaced by `unreachable`, which is valid for any signature.
+    fn bodies(&mut self) {
+        let stub = FuncBody::new(Vec::new(), Opcodes::new(vec![Opcode::Unreachable, Opcode::End]));
+        let count = self.module.code_section().map(|s| s.bodies().len()).unwrap_or(0);
+        for index in 0..count {
+            self.attempt(|module| match code_bodies(module).and_then(|bodies| bodies.get_mut(index)) {
+                Some(body) if !body.locals().is_empty() || body.code().elements() != stub.code().elements() => {
+                    *body = stub.clone();
+                    true
+                },
+                _ => false,
+            });
+        }
+    }
+
+    /// Runs of instructions, halving the run length down to one.
+    fn instructions(&mut self) {
+        let count = self.module.code_section().map(|s| s.bodies().len()).unwrap_or(0);
+        for body in 0..count {
+            // the final `end` is never removed
+            let len = |module: &Module| module.code_section()
+                .map(|s| s.bodies()[body].code().elements().len().saturating_sub(1))
+                .unwrap_or(0);
+            let mut run = len(&self.module);
+            while run > 0 {
+                let mut start = 0;
+                while start + run <= len(&self.module) {
+                    let removed = self.attempt(|module| match code_bodies(module) {
+                        Some(bodies) => {
+                            bodies[body].code_mut().elements_mut().drain(start..start + run);
+                            true
+                        },
+                        None => false,
+                    });
+                    if !removed {
+                        start += run;
+                    }
+                }
+                run /= 2;
+            }
+        }
+    }
+
+    /// Constants of the function bodies and global initializers set to zero.
+    fn constants(&mut self) {
+        for section in 0..self.module.sections().len() {
+            let count = match self.module.sections()[section] {
+                Section::Code(ref s) => s.bodies().len(),
+                Section::Global(ref s) => s.entries().len(),
+                _ => continue,
+            };
+            for entry in 0..count {
+                let len = match self.module.sections()[section] {
+                    Section::Code(ref s) => s.bodies()[entry].code().elements().len(),
+                    Section::Global(ref s) => s.entries()[entry].init_expr().code().len(),
+                    _ => 0,
+                };
+                for position in 0..len {
+                    self.attempt(|module| {
+                        let opcode = match module.sections_mut()[section] {
+                            Section::Code(ref mut s) => &mut s.bodies_mut()[entry].code_mut().elements_mut()[position],
+                            Section::Global(ref mut s) => &mut s.entries_mut()[entry].init_expr_mut().code_mut()[position],
+                            _ => return false,
+                        };
+                        zero_constant(opcode)
+                    });
+                }
+            }
+        }
+    }
+
+    /// Data segments truncated to the half.
+    fn data(&mut self) {
+        let count = self.module.data_section().map(|s| s.entries().len()).unwrap_or(0);
+        for index in 0..count {
+            loop {
+                let truncated = self.attempt(|module| {
+                    for section in module.sections_mut() {
+                        if let Section::Data(ref mut s) = *section {
+                            let value = s.entries_mut()[index].value_mut();
+                            if value.is_empty() {
+                                return false;
+                            }
+                            let len = value.len() / 2;
+                            value.truncate(len);
+                            return true;
+                        }
+                    }
+                    false
+                });
+                if !truncated {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn entry_count(section: &Section) -> usize {
+    match *section {
+        Section::Export(ref s) => s.entries().len(),
+        Section::Element(ref s) => s.entries().len(),
+        Section::Data(ref s) => s.entries().len(),
+        _ => 0,
+    }
+}
+
+fn function_count(module: &Module) -> u32 {
+    let imported = module.import_section().map(|s| s.functions()).unwrap_or(0);
+    let defined = module.function_section().map(|s| s.entries().len()).unwrap_or(0);
+    (imported + defined) as u32
+}
+
+fn code_bodies(module: &mut Module) -> Option<&mut Vec<FuncBody>> {
+    module.sections_mut().iter_mut().filter_map(|section| match *section {
+        Section::Code(ref mut s) => Some(s.bodies_mut()),
+        _ => None,
+    }).next()
+}
+
+/// Remove the function and renumber the rest, `false` if it is referenced.
+fn remove_function(module: &mut Module, index: u32) -> bool {
+    let imported = module.import_section().map(|s| s.functions()).unwrap_or(0) as u32;
+    let map = IndexMap::retain(function_count(module), |i| i != index);
+    if Remap::new().with_functions(map).apply(module).is_err() {
+        return false;
+    }
+
+    for section in module.sections_mut() {
+        match *section {
+            Section::Import(ref mut s) if index < imported => {
+                let mut function = 0;
+                s.entries_mut().retain(|entry| match *entry.external() {
+                    External::Function(_) => { function += 1; function - 1 != index },
+                    _ => true,
+                });
+            },
+            Section::Function(ref mut s) if index >= imported => {
+                s.entries_mut().remove((index - imported) as usize);
+            },
+            Section::Code(ref mut s) if index >= imported => {
+                s.bodies_mut().remove((index - imported) as usize);
+            },
+            _ => {},
+        }
+    }
+    true
+}
+
+/// Replace the nonzero constant with zero, `false` if it is not one.
+fn zero_constant(opcode: &mut Opcode) -> bool {
+    let zero = match *opcode {
+        Opcode::I32Const(value) if value != 0 => Opcode::I32Const(0),
+        Opcode::I64Const(value) if value != 0 => Opcode::I64Const(0),
+        Opcode::F32Const(bits) if bits != 0 => Opcode::F32Const(0),
+        Opcode::F64Const(bits) if bits != 0 => Opcode::F64Const(0),
+        _ => return false,
+    };
+    *opcode = zero;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Module, Opcodes, Opcode, ExportEntry, Internal, DataSegment, InitExpr, Section};
+    use generator::Generator;
+    use validation::validate_module;
+    use super::super::Error;
+    use super::Reducer;
+
+    fn has_opcode(module: &Module, opcode: &Opcode) -> bool {
+        module.code_section()
+            .map(|s| s.bodies().iter().any(|b| b.code().elements().contains(opcode)))
+            .unwrap_or(false)
+    }
+
+    #[test]
+    fn keeps_interesting_instruction() {
+        let mut module = module()
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![
+                    Opcode::GetLocal(0),
+                    Opcode::I32Const(7),
+                    Opcode::I32Add,
+                    Opcode::I32Const(3),
+                    Opcode::I32Mul,
+                    Opcode::End,
+                ])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![
+                    Opcode::I32Const(5),
+                    Opcode::Call(0),
+                    Opcode::Drop,
+                    Opcode::End,
+                ])).build()
+                .build()
+            .memory().with_min(1).build()
+            .with_data_segment(DataSegment::new(0, InitExpr::new(vec![Opcode::I32Const(16), Opcode::End]), vec![1; 32]))
+            .with_export(ExportEntry::new("main".into(), Internal::Function(1)))
+            .with_export(ExportEntry::new("add".into(), Internal::Function(0)))
+            .build();
+
+        let report = Reducer::new()
+            .reduce(&mut module, |m| has_opcode(m, &Opcode::I32Mul))
+            .expect("reduction to succeed");
+
+        validate_module(&module).expect("reduced module to be valid");
+        assert!(has_opcode(&module, &Opcode::I32Mul));
+        assert!(report.reduced_size < report.original_size);
+        assert!(report.reductions > 0);
+        assert_eq!(module.function_section().unwrap().entries().len(), 1);
+        assert!(module.export_section().is_none());
+        assert!(module.data_section().is_none());
+        assert_eq!(module.code_section().unwrap().bodies()[0].code().elements(), &[
+            Opcode::GetLocal(0),
+            Opcode::I32Const(0),
+            Opcode::I32Mul,
+            Opcode::End,
+        ][..]);
+    }
+
+    #[test]
+    fn generated_modules() {
+        let generator = Generator::default();
+        for seed in 0..20 {
+            let mut module = generator.from_seed(seed);
+            let memory = module.memory_section().is_some();
+            Reducer::new()
+                .reduce(&mut module, |m| m.memory_section().is_some() == memory)
+                .expect("reduction to succeed");
+            validate_module(&module).expect("reduced module to be valid");
+            assert!(module.sections().iter().all(|s| matches!(*s, Section::Memory(_))));
+        }
+    }
+
+    #[test]
+    fn uninteresting() {
+        let mut module = module().build();
+        match Reducer::new().reduce(&mut module, |_| false) {
+            Err(Error::Uninteresting) => {},
+            other => panic!("expected uninteresting module error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn candidate_files_are_unique() {
+        let first = super::CandidateFile::new();
+        let second = super::CandidateFile::new();
+        assert_ne!(first.path, second.path);
+
+        ::std::fs::write(&first.path, b"candidate").expect("temporary file to be written");
+        let path = first.path.clone();
+        drop(first);
+        assert!(!path.exists());
+    }
+}
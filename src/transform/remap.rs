@@ -0,0 +1,317 @@
+//! Renumbering of the module index spaces.
+//!
+//! `Remap` holds old-to-new index maps for the function, global and type
+//! index spaces and rewrites every reference to them in the module: call
+//! sites, `call_indirect` signatures, global accesses, init expressions,
+//! function and import signatures, exports, element segments, the start
+//! function and the `name` section.
+//!
+//! Removing or inserting the items themselves is up to the caller; `Remap`
+//! only keeps the references consistent with the new numbering.
+
+use std::mem;
+use elements::{Module, Section, Opcode, External, Internal, NameSection, NameMap};
+use super::Error;
+
+/// Index space of the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexSpace {
+    /// Functions, imported ones first.
+    Function,
+    /// Globals, imported ones first.
+    Global,
+    /// Function types.
+    Type,
+}
+
+/// Old-to-new mapping of one index space.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexMap(Vec<Option<u32>>);
+
+impl IndexMap {
+    /// Map from the explicit list, `None` for the removed items.
+    pub fn new(map: Vec<Option<u32>>) -> Self {
+        IndexMap(map)
+    }
+
+    /// Map of `len` items keeping every index.
+    pub fn identity(len: u32) -> Self {
+        IndexMap((0..len).map(Some).collect())
+    }
+
+    /// Map of `len` items keeping those matching `keep`, in the original order.
+    pub fn retain<F: FnMut(u32) -> bool>(len: u32, mut keep: F) -> Self {
+        let mut next = 0;
+        IndexMap((0..len).map(|index| if keep(index) {
+            next += 1;
+            Some(next - 1)
+        } else {
+            None
+        }).collect())
+    }
+
+    /// Map of `len` items after inserting `count` new items at the index `at`.
+    pub fn insert(len: u32, at: u32, count: u32) -> Self {
+        IndexMap((0..len).map(|index| Some(if index < at { index } else { index + count })).collect())
+    }
+
+    /// Number of the old indices.
+    pub fn len(&self) -> usize { self.0.len() }
+
+    /// Is map empty.
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// New index of the item, `None` if it is removed or out of the map.
+    pub fn get(&self, index: u32) -> Option<u32> {
+        self.0.get(index as usize).cloned().unwrap_or(None)
+    }
+
+    /// Is item removed by this map.
+    pub fn is_removed(&self, index: u32) -> bool {
+        self.0.get(index as usize) == Some(&None)
+    }
+
+    fn resolve(&self, space: IndexSpace, index: u32) -> Result<u32, Error> {
+        match self.0.get(index as usize) {
+            Some(&Some(new)) => Ok(new),
+            Some(&None) => Err(Error::RemovedIndex { space, index }),
+            None => Err(Error::InvalidIndex { space, index }),
+        }
+    }
+}
+
+/// Renumbering of the module index spaces.
+///
+/// Index spaces without a map are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct Remap {
+    functions: Option<IndexMap>,
+    globals: Option<IndexMap>,
+    types: Option<IndexMap>,
+}
+
+impl Remap {
+    /// New renumbering keeping every index space unchanged.
+    pub fn new() -> Self {
+        Remap::default()
+    }
+
+    /// Renumber functions with `map`.
+    pub fn with_functions(mut self, map: IndexMap) -> Self {
+        self.functions = Some(map);
+        self
+    }
+
+    /// Renumber globals with `map`.
+    pub fn with_globals(mut self, map: IndexMap) -> Self {
+        self.globals = Some(map);
+        self
+    }
+
+    /// Renumber types with `map`.
+    pub fn with_types(mut self, map: IndexMap) -> Self {
+        self.types = Some(map);
+        self
+    }
+
+    /// Map of the index space, if any.
+    pub fn map(&self, space: IndexSpace) -> Option<&IndexMap> {
+        match space {
+            IndexSpace::Function => self.functions.as_ref(),
+            IndexSpace::Global => self.globals.as_ref(),
+            IndexSpace::Type => self.types.as_ref(),
+        }
+    }
+
+    /// New index of the item referenced as `index`.
+    pub fn index(&self, space: IndexSpace, index: u32) -> Result<u32, Error> {
+        match self.map(space) {
+            Some(map) => map.resolve(space, index),
+            None => Ok(index),
+        }
+    }
+
+    /// Rewrite every index reference of the module.
+    ///
+    /// Fails if a reference points to a removed item or outside of the map;
+    /// the module is left untouched in that case. Names of the removed
+    /// functions are dropped from the `name` section.
+    pub fn apply(&self, module: &mut Module) -> Result<(), Error> {
+        let mut sections = module.sections().to_vec();
+        for section in &mut sections {
+            self.apply_section(section)?;
+        }
+        *module.sections_mut() = sections;
+        Ok(())
+    }
+
+    fn rewrite(&self, space: IndexSpace, index: &mut u32) -> Result<(), Error> {
+        *index = self.index(space, *index)?;
+        Ok(())
+    }
+
+    fn apply_opcodes(&self, opcodes: &mut [Opcode]) -> Result<(), Error> {
+        for opcode in opcodes {
+            match *opcode {
+                Opcode::Call(ref mut index) => self.rewrite(IndexSpace::Function, index)?,
+                Opcode::CallIndirect(ref mut index, _) => self.rewrite(IndexSpace::Type, index)?,
+                Opcode::GetGlobal(ref mut index) | Opcode::SetGlobal(ref mut index) =>
+                    self.rewrite(IndexSpace::Global, index)?,
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_names(&self, map: &mut NameMap) {
+        let functions = match self.functions {
+            Some(ref functions) => functions,
+            None => return,
+        };
+        let names = mem::take(map.names_mut());
+        *map.names_mut() = names.into_iter()
+            .filter(|&(index, _)| !functions.is_removed(index))
+            .map(|(index, name)| (functions.get(index).unwrap_or(index), name))
+            .collect();
+    }
+
+    fn apply_section(&self, section: &mut Section) -> Result<(), Error> {
+        match *section {
+            Section::Type(_) | Section::Table(_) | Section::Memory(_) | Section::Unparsed { .. } => {},
+            Section::Import(ref mut import_section) => {
+                for entry in import_section.entries_mut() {
+                    if let External::Function(ref mut type_ref) = *entry.external_mut() {
+                        self.rewrite(IndexSpace::Type, type_ref)?;
+                    }
+                }
+            },
+            Section::Function(ref mut function_section) => {
+                for func in function_section.entries_mut() {
+                    self.rewrite(IndexSpace::Type, func.type_ref_mut())?;
+                }
+            },
+            Section::Global(ref mut global_section) => {
+                for entry in global_section.entries_mut() {
+                    self.apply_opcodes(entry.init_expr_mut().code_mut())?;
+                }
+            },
+            Section::Export(ref mut export_section) => {
+                for entry in export_section.entries_mut() {
+                    match *entry.internal_mut() {
+                        Internal::Function(ref mut index) => self.rewrite(IndexSpace::Function, index)?,
+                        Internal::Global(ref mut index) => self.rewrite(IndexSpace::Global, index)?,
+                        _ => {},
+                    }
+                }
+            },
+            Section::Start(ref mut index) => self.rewrite(IndexSpace::Function, index)?,
+            Section::Element(ref mut elements_section) => {
+                for segment in elements_section.entries_mut() {
+                    self.apply_opcodes(segment.offset_mut().code_mut())?;
+                    for member in segment.members_mut() {
+                        self.rewrite(IndexSpace::Function, member)?;
+                    }
+                }
+            },
+            Section::Code(ref mut code_section) => {
+                for body in code_section.bodies_mut() {
+                    self.apply_opcodes(body.code_mut().elements_mut())?;
+                }
+            },
+            Section::Data(ref mut data_section) => {
+                for segment in data_section.entries_mut() {
+                    self.apply_opcodes(segment.offset_mut().code_mut())?;
+                }
+            },
+            Section::Custom(ref mut custom_section) => {
+                let mut names = match NameSection::from_custom(custom_section) {
+                    Some(names) => names?,
+                    None => return Ok(()),
+                };
+                if let Some(ref mut functions) = *names.functions_mut() {
+                    self.apply_names(functions);
+                }
+                if let Some(ref functions) = self.functions {
+                    if let Some(ref mut locals) = *names.locals_mut() {
+                        *locals = mem::take(locals).into_iter()
+                            .filter(|&(index, _)| !functions.is_removed(index))
+                            .map(|(index, names)| (functions.get(index).unwrap_or(index), names))
+                            .collect();
+                    }
+                }
+                *custom_section = names.into_custom()?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Opcodes, ExportEntry, Internal, ImportEntry, External};
+    use super::super::Error;
+    use super::{Remap, IndexMap, IndexSpace};
+
+    #[test]
+    fn index_maps() {
+        let map = IndexMap::retain(4, |index| index != 1);
+        assert_eq!(map.get(0), Some(0));
+        assert_eq!(map.get(1), None);
+        assert!(map.is_removed(1));
+        assert_eq!(map.get(3), Some(2));
+        assert!(!map.is_removed(4));
+
+        let map = IndexMap::insert(3, 1, 2);
+        assert_eq!(map.get(0), Some(0));
+        assert_eq!(map.get(1), Some(3));
+        assert_eq!(map.get(2), Some(4));
+    }
+
+    #[test]
+    fn insert_import_at_front() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![Call(1), End])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(0)))
+            .build();
+
+        Remap::new()
+            .with_functions(IndexMap::insert(2, 0, 1))
+            .apply(&mut module)
+            .expect("remap to succeed");
+
+        let mut module = ::builder::from_module(module)
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .build();
+        assert_eq!(module.import_section().unwrap().functions(), 1);
+        assert_eq!(
+            module.code_section().unwrap().bodies()[0].code().elements(),
+            &[Call(2), End][..]
+        );
+        match *module.export_section().unwrap().entries()[0].internal() {
+            Internal::Function(index) => assert_eq!(index, 1),
+            _ => panic!("Function export expected"),
+        }
+
+        // reference to the removed function is an error and leaves module untouched
+        let before = module.code_section().unwrap().bodies()[0].code().elements().to_vec();
+        let result = Remap::new()
+            .with_functions(IndexMap::retain(3, |index| index != 2))
+            .apply(&mut module);
+        match result {
+            Err(Error::RemovedIndex { space: IndexSpace::Function, index: 2 }) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(module.code_section().unwrap().bodies()[0].code().elements(), &before[..]);
+    }
+}
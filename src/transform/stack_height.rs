@@ -0,0 +1,359 @@
+//! Deterministic stack height limit.
+//!
+//! The cost of the function frame is its maximal operand stack height plus
+//! the number of its parameters and locals. A mutable global accumulates the
+//! costs of the active frames: every direct call adds the cost of the callee
+//! before the call, traps if the limit is exceeded, and subtracts the cost
+//! after the call returns. Functions entered from the outside (exports, table
+//! members and the start function) are replaced with thunks accounting the
+//! frame of the original function the same way.
+
+use std::collections::BTreeMap;
+use std::mem;
+use elements::{
+    Module, Section, Opcode, OpcodeCategory, BlockType, Type, FunctionType, ValueType,
+    External, Internal, GlobalEntry, GlobalType, GlobalSection, InitExpr, FuncBody, Opcodes,
+};
+use super::{Error, ensure_section, add_function};
+use super::remap::IndexSpace;
+
+/// Signatures of the module index spaces.
+struct Signatures {
+    types: Vec<FunctionType>,
+    /// Type reference of every function, imported first.
+    functions: Vec<u32>,
+}
+
+impl Signatures {
+    fn new(module: &Module) -> Self {
+        let types = module.type_section()
+            .map(|s| s.types().iter().map(|t| match *t { Type::Function(ref f) => f.clone() }).collect())
+            .unwrap_or_default();
+        let mut functions = Vec::new();
+        if let Some(import_section) = module.import_section() {
+            for entry in import_section.entries() {
+                if let External::Function(type_ref) = *entry.external() {
+                    functions.push(type_ref);
+                }
+            }
+        }
+        if let Some(function_section) = module.function_section() {
+            functions.extend(function_section.entries().iter().map(|f| f.type_ref()));
+        }
+        Signatures { types, functions }
+    }
+
+    fn type_ref(&self, type_ref: u32) -> Result<&FunctionType, Error> {
+        self.types.get(type_ref as usize).ok_or(Error::InvalidIndex { space: IndexSpace::Type, index: type_ref })
+    }
+
+    fn function(&self, func: u32) -> Result<&FunctionType, Error> {
+        let type_ref = *self.functions.get(func as usize)
+            .ok_or(Error::InvalidIndex { space: IndexSpace::Function, index: func })?;
+        self.type_ref(type_ref)
+    }
+}
+
+/// Number of values popped and pushed by the non-control opcode.
+fn stack_effect(opcode: &Opcode, signatures: &Signatures) -> Result<(u32, u32), Error> {
+    use elements::Opcode::*;
+
+    let result = |f: &FunctionType| if f.return_type().is_some() { 1 } else { 0 };
+    Ok(match *opcode {
+        Call(func) => {
+            let signature = signatures.function(func)?;
+            (signature.params().len() as u32, result(signature))
+        },
+        CallIndirect(type_ref, _) => {
+            let signature = signatures.type_ref(type_ref)?;
+            (signature.params().len() as u32 + 1, result(signature))
+        },
+        Drop | SetLocal(_) | SetGlobal(_) => (1, 0),
+        Select => (3, 1),
+        GetLocal(_) | GetGlobal(_) | CurrentMemory(_) |
+        I32Const(_) | I64Const(_) | F32Const(_) | F64Const(_) => (0, 1),
+        TeeLocal(_) | GrowMemory(_) => (1, 1),
+
+        I32Store(_, _) | I64Store(_, _) | F32Store(_, _) | F64Store(_, _) |
+        I32Store8(_, _) | I32Store16(_, _) | I64Store8(_, _) | I64Store16(_, _) | I64Store32(_, _) => (2, 0),
+
+        I32Eqz | I64Eqz |
+        I32Clz | I32Ctz | I32Popcnt | I64Clz | I64Ctz | I64Popcnt |
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt |
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt |
+        I32WarpI64 | I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 |
+        I64ExtendSI32 | I64ExtendUI32 | I64TruncSF32 | I64TruncUF32 | I64TruncSF64 | I64TruncUF64 |
+        F32ConvertSI32 | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64 | F32DemoteF64 |
+        F64ConvertSI32 | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64 | F64PromoteF32 |
+        I32ReinterpretF32 | I64ReinterpretF64 | F32ReinterpretI32 | F64ReinterpretI64 => (1, 1),
+
+        ref opcode => match opcode.category() {
+            // loads
+            OpcodeCategory::Memory => (1, 1),
+            // binary operators and comparisons
+            _ => (2, 1),
+        },
+    })
+}
+
+/// Operand stack frame of the block.
+struct Frame {
+    /// Stack height at the block entry.
+    start: u32,
+    /// Number of block results.
+    arity: u32,
+}
+
+fn block_arity(block_type: BlockType) -> u32 {
+    match block_type {
+        BlockType::NoResult => 0,
+        BlockType::Value(_) => 1,
+    }
+}
+
+/// Maximal operand stack height of the function body.
+fn max_height(opcodes: &[Opcode], signatures: &Signatures) -> Result<u32, Error> {
+    let mut frames = vec![Frame { start: 0, arity: 0 }];
+    let mut height = 0u32;
+    let mut max = 0u32;
+
+    for opcode in opcodes {
+        let start = frames.last().map(|frame| frame.start).unwrap_or(0);
+        match *opcode {
+            Opcode::Nop => {},
+            Opcode::Block(block_type) | Opcode::Loop(block_type) => {
+                frames.push(Frame { start: height, arity: block_arity(block_type) });
+            },
+            Opcode::If(block_type) => {
+                height = height.saturating_sub(1);
+                frames.push(Frame { start: height, arity: block_arity(block_type) });
+            },
+            Opcode::Else => height = start,
+            Opcode::End => {
+                if let Some(frame) = frames.pop() {
+                    height = frame.start + frame.arity;
+                }
+            },
+            Opcode::BrIf(_) => height = height.saturating_sub(1),
+            // the rest of the block is unreachable
+            Opcode::Br(_) | Opcode::BrTable(_, _) | Opcode::Return | Opcode::Unreachable => height = start,
+            ref opcode => {
+                let (pop, push) = stack_effect(opcode, signatures)?;
+                height = height.saturating_sub(pop).max(start) + push;
+            },
+        }
+        max = max.max(height);
+    }
+
+    Ok(max)
+}
+
+/// Frame cost of every function, `None` for the imported ones.
+fn frame_costs(module: &Module, signatures: &Signatures) -> Result<Vec<Option<u32>>, Error> {
+    let imported = module.import_section().map(|s| s.functions()).unwrap_or(0);
+    let mut costs = vec![None; imported];
+    if let Some(code_section) = module.code_section() {
+        for (index, body) in code_section.bodies().iter().enumerate() {
+            let func = (imported + index) as u32;
+            let params = signatures.function(func)?.params().len() as u32;
+            let locals = body.locals().iter().fold(0u32, |count, local| count.saturating_add(local.count()));
+            let height = max_height(body.code().elements(), signatures)?;
+            costs.push(Some(height.saturating_add(params).saturating_add(locals)));
+        }
+    }
+    Ok(costs)
+}
+
+/// Call of the function accounting its frame cost in the `global`.
+fn instrumented_call(func: u32, cost: u32, global: u32, limit: u32) -> Vec<Opcode> {
+    vec![
+        Opcode::GetGlobal(global),
+        Opcode::I32Const(cost as i32),
+        Opcode::I32Add,
+        Opcode::SetGlobal(global),
+        Opcode::GetGlobal(global),
+        Opcode::I32Const(limit as i32),
+        Opcode::I32GtU,
+        Opcode::If(BlockType::NoResult),
+        Opcode::Unreachable,
+        Opcode::End,
+        Opcode::Call(func),
+        Opcode::GetGlobal(global),
+        Opcode::I32Const(cost as i32),
+        Opcode::I32Sub,
+        Opcode::SetGlobal(global),
+    ]
+}
+
+/// Limit the total cost of active frames to `limit`, trapping once it is exceeded.
+pub fn limit_stack_height(module: &mut Module, limit: u32) -> Result<(), Error> {
+    let signatures = Signatures::new(module);
+    let costs = frame_costs(module, &signatures)?;
+    let cost = |func: u32| costs.get(func as usize).cloned().unwrap_or(None);
+
+    let imported_globals = module.import_section().map(|s| s.globals()).unwrap_or(0);
+    let position = ensure_section(module, Section::Global(GlobalSection::default()));
+    let global = match module.sections_mut()[position] {
+        Section::Global(ref mut global_section) => {
+            global_section.entries_mut().push(GlobalEntry::new(
+                GlobalType::new(ValueType::I32, true),
+                InitExpr::new(vec![Opcode::I32Const(0), Opcode::End]),
+            ));
+            (imported_globals + global_section.entries().len() - 1) as u32
+        },
+        _ => unreachable!("ensure_section returns section of the requested kind"),
+    };
+
+    for section in module.sections_mut() {
+        if let Section::Code(ref mut code_section) = *section {
+            for body in code_section.bodies_mut() {
+                let opcodes = mem::take(body.code_mut().elements_mut());
+                let mut result = Vec::with_capacity(opcodes.len());
+                for opcode in opcodes {
+                    match opcode {
+                        Opcode::Call(func) if cost(func).is_some() =>
+                            result.extend(instrumented_call(func, cost(func).unwrap_or(0), global, limit)),
+                        opcode => result.push(opcode),
+                    }
+                }
+                *body.code_mut().elements_mut() = result;
+            }
+        }
+    }
+
+    // Entry points of the module get thunks.
+    let mut entries = Vec::new();
+    if let Some(export_section) = module.export_section() {
+        entries.extend(export_section.entries().iter().filter_map(|e| match *e.internal() {
+            Internal::Function(func) => Some(func),
+            _ => None,
+        }));
+    }
+    if let Some(elements_section) = module.elements_section() {
+        for segment in elements_section.entries() {
+            entries.extend(segment.members().iter().cloned());
+        }
+    }
+    entries.extend(module.start_section());
+
+    let mut thunks = BTreeMap::new();
+    for func in entries {
+        let frame_cost = match cost(func) {
+            Some(frame_cost) if !thunks.contains_key(&func) => frame_cost,
+            _ => continue,
+        };
+        let type_ref = signatures.functions[func as usize];
+        let params = signatures.type_ref(type_ref)?.params().len() as u32;
+        let mut opcodes: Vec<Opcode> = (0..params).map(Opcode::GetLocal).collect();
+        opcodes.extend(instrumented_call(func, frame_cost, global, limit));
+        opcodes.push(Opcode::End);
+        let thunk = add_function(module, type_ref, FuncBody::new(Vec::new(), Opcodes::new(opcodes)));
+        thunks.insert(func, thunk);
+    }
+
+    let thunk = |func: &mut u32| if let Some(thunk) = thunks.get(func) { *func = *thunk; };
+    for section in module.sections_mut() {
+        match *section {
+            Section::Export(ref mut export_section) => {
+                for entry in export_section.entries_mut() {
+                    if let Internal::Function(ref mut func) = *entry.internal_mut() {
+                        thunk(func);
+                    }
+                }
+            },
+            Section::Element(ref mut elements_section) => {
+                for segment in elements_section.entries_mut() {
+                    segment.members_mut().iter_mut().for_each(thunk);
+                }
+            },
+            Section::Start(ref mut func) => thunk(func),
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use elements::{Opcodes, BlockType, ExportEntry, Internal, ImportEntry, External, Local, ValueType};
+    use validation::validate_module;
+    use super::{limit_stack_height, max_height, Signatures};
+
+    #[test]
+    fn height() {
+        use elements::Opcode::*;
+
+        let module = module()
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body().build()
+                .build()
+            .build();
+        let signatures = Signatures::new(&module);
+
+        let opcodes = vec![
+            I32Const(1),
+            Block(BlockType::Value(ValueType::I32)),
+                I32Const(2),
+                I32Const(3),
+                Call(0),
+                I32Add,
+                Br(0),
+                I32Const(4),
+            End,
+            I32Add,
+            End,
+        ];
+        assert_eq!(max_height(&opcodes, &signatures).unwrap(), 3);
+
+        let opcodes = vec![
+            I32Const(0),
+            If(BlockType::NoResult),
+                I32Const(1), I32Const(1), Drop, Drop,
+            Else,
+                I32Const(1), Drop,
+            End,
+            End,
+        ];
+        assert_eq!(max_height(&opcodes, &signatures).unwrap(), 2);
+    }
+
+    #[test]
+    fn instrumentation() {
+        use elements::Opcode::*;
+
+        let mut module = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .function()
+                .signature().param().i32().build()
+                .body()
+                    .with_locals(vec![Local::new(2, ValueType::I64)])
+                    .with_opcodes(Opcodes::new(vec![GetLocal(0), Call(0), End]))
+                    .build()
+                .build()
+            .function()
+                .signature().build()
+                .body().with_opcodes(Opcodes::new(vec![I32Const(5), Call(1), End])).build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(2)))
+            .build();
+
+        limit_stack_height(&mut module, 1024).expect("stack height limiter to succeed");
+        validate_module(&module).expect("instrumented module to validate");
+
+        let bodies = module.code_section().unwrap().bodies();
+        assert_eq!(bodies.len(), 3);
+        // call to the import is not instrumented
+        assert_eq!(bodies[0].code().elements(), &[GetLocal(0), Call(0), End][..]);
+        // frame of the function 1: 1 param, 2 locals, height 1
+        assert_eq!(&bodies[1].code().elements()[..4], &[I32Const(5), GetGlobal(0), I32Const(4), I32Add][..]);
+        assert!(bodies[1].code().elements().contains(&Call(1)));
+        // thunk of the function 2 is exported
+        assert_eq!(*module.export_section().unwrap().entries()[0].internal(), Internal::Function(3));
+        assert!(bodies[2].code().elements().contains(&Call(2)));
+        assert_eq!(module.global_section().unwrap().entries().len(), 1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Function entry and exit tracing.
+//!
+//! Instrumented functions call the imported `enter` hook with their index
+//! first thing, and the imported `exit` hook on every way out. The original
+//! body is wrapped into a block with the function result type: returns become
+//! branches to this block and branches to the function label land on its end,
+//! so the `exit` hook is called in exactly one place.
+
+use std::collections::BTreeSet;
+use elements::{
+    Module, Section, Opcode, BlockType, FuncBody, FunctionType, ValueType, Type,
+    ImportSection, ImportEntry, External, VisitorMut, Editor, walk_func_body_mut,
+};
+use super::{Error, ensure_section, ensure_type};
+use super::remap::{Remap, IndexMap, IndexSpace};
+
+/// Tracing instrumentation.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    module: String,
+    enter: String,
+    exit: String,
+    functions: Option<BTreeSet<u32>>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace {
+            module: "env".into(),
+            enter: "trace_enter".into(),
+            exit: "trace_exit".into(),
+            functions: None,
+        }
+    }
+}
+
+impl Trace {
+    /// New instrumentation of every defined function with `env.trace_enter` and `env.trace_exit`.
+    pub fn new() -> Self {
+        Trace::default()
+    }
+
+    /// Import hooks from the `module`.
+    pub fn with_module(mut self, module: &str) -> Self {
+        self.module = module.to_owned();
+        self
+    }
+
+    /// Import hooks with the given field names.
+    pub fn with_hooks(mut self, enter: &str, exit: &str) -> Self {
+        self.enter = enter.to_owned();
+        self.exit = exit.to_owned();
+        self
+    }
+
+    /// Instrument only the given functions (indices in the original module).
+    pub fn with_functions<I: IntoIterator<Item=u32>>(mut self, functions: I) -> Self {
+        self.functions = Some(functions.into_iter().collect());
+        self
+    }
+
+    /// Instrument the module.
+    ///
+    /// Hooks are imported after the other imported functions and receive the
+    /// index of the function in the instrumented module.
+    pub fn apply(&self, mut module: Module) -> Result<Module, Error> {
+        let imported = module.import_section().map(|s| s.functions()).unwrap_or(0) as u32;
+        let type_refs: Vec<u32> = module.function_section()
+            .map(|s| s.entries().iter().map(|f| f.type_ref()).collect())
+            .unwrap_or_default();
+        let types: Vec<FunctionType> = module.type_section()
+            .map(|s| s.types().iter().map(|t| match *t { Type::Function(ref f) => f.clone() }).collect())
+            .unwrap_or_default();
+        let mut results = Vec::with_capacity(type_refs.len());
+        for type_ref in &type_refs {
+            let signature = types.get(*type_ref as usize)
+                .ok_or(Error::InvalidIndex { space: IndexSpace::Type, index: *type_ref })?;
+            results.push(signature.return_type());
+        }
+
+        let functions = imported + type_refs.len() as u32;
+        Remap::new()
+            .with_functions(IndexMap::insert(functions, imported, 2))
+            .apply(&mut module)?;
+
+        let hook_type = ensure_type(&mut module, FunctionType::new(vec![ValueType::I32], None));
+        let position = ensure_section(&mut module, Section::Import(ImportSection::default()));
+        if let Section::Import(ref mut import_section) = module.sections_mut()[position] {
+            for field in &[&self.enter, &self.exit] {
+                import_section.entries_mut().push(
+                    ImportEntry::new(self.module.clone(), (*field).clone(), External::Function(hook_type))
+                );
+            }
+        }
+
+        let mut rewriter = Rewriter {
+            enter: imported,
+            exit: imported + 1,
+            first_defined: imported + 2,
+            results,
+            functions: self.functions.as_ref(),
+        };
+        rewriter.visit_module(&mut module);
+        Ok(module)
+    }
+}
+
+struct Rewriter<'a> {
+    enter: u32,
+    exit: u32,
+    /// Index of the first defined function in the instrumented module.
+    first_defined: u32,
+    /// Result type of every defined function.
+    results: Vec<Option<ValueType>>,
+    functions: Option<&'a BTreeSet<u32>>,
+}
+
+impl<'a> VisitorMut for Rewriter<'a> {
+    fn visit_func_body(&mut self, func: u32, body: &mut FuncBody) {
+        let defined = (func - self.first_defined) as usize;
+        // original index of the function: two hooks were inserted before it
+        if let Some(functions) = self.functions {
+            if !functions.contains(&(func - 2)) {
+                return;
+            }
+        }
+
+        walk_func_body_mut(self, func, body);
+
+        let block_type = match self.results.get(defined).cloned().unwrap_or(None) {
+            Some(value_type) => BlockType::Value(value_type),
+            None => BlockType::NoResult,
+        };
+        let opcodes = body.code_mut().elements_mut();
+        let mut wrapped = vec![Opcode::I32Const(func as i32), Opcode::Call(self.enter), Opcode::Block(block_type)];
+        wrapped.append(opcodes);
+        wrapped.extend(vec![Opcode::I32Const(func as i32), Opcode::Call(self.exit), Opcode::End]);
+        *opcodes = wrapped;
+    }
+
+    fn visit_control(&mut self, editor: &mut Editor, opcode: &mut Opcode) {
+        if *opcode == Opcode::Return {
+            *opcode = Opcode::Br(editor.location().depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use builder::module;
+    use std::collections::BTreeMap;
+    use elements::{
+        Opcodes, BlockType, ExportEntry, Internal, ImportEntry, External,
+        Section, NameSection, NameMap,
+    };
+    use validation::validate_module;
+    use super::Trace;
+
+    #[test]
+    fn every_exit() {
+        use elements::Opcode::*;
+
+        let module = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(1)))
+            .function()
+                .signature().param().i32().return_type().i32().build()
+                .body().with_opcodes(Opcodes::new(vec![
+                    Block(BlockType::NoResult),
+                        GetLocal(0),
+                        If(BlockType::NoResult),
+                            I32Const(1),
+                            Return,
+                        End,
+                        I32Const(2),
+                        GetLocal(0),
+                        BrIf(1),
+                        Drop,
+                    End,
+                    Call(2),
+                    I32Const(3),
+                    End,
+                ])).build()
+                .build()
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .with_export(ExportEntry::new("main".into(), Internal::Function(1)))
+            .build();
+
+        let module = Trace::new().with_functions(vec![1]).apply(module).expect("tracing to succeed");
+        validate_module(&module).expect("instrumented module to validate");
+
+        let imports = module.import_section().unwrap().entries();
+        assert_eq!(imports.len(), 3);
+        assert_eq!(imports[1].field(), "trace_enter");
+        assert_eq!(imports[2].field(), "trace_exit");
+        assert_eq!(*module.export_section().unwrap().entries()[0].internal(), Internal::Function(3));
+
+        let bodies = module.code_section().unwrap().bodies();
+        assert_eq!(bodies[0].code().elements(), &[
+            I32Const(3), Call(1),
+            Block(BlockType::Value(::elements::ValueType::I32)),
+                Block(BlockType::NoResult),
+                    GetLocal(0),
+                    If(BlockType::NoResult),
+                        I32Const(1),
+                        Br(2),
+                    End,
+                    I32Const(2),
+                    GetLocal(0),
+                    BrIf(1),
+                    Drop,
+                End,
+                Call(4),
+                I32Const(3),
+            End,
+            I32Const(3), Call(2),
+            End,
+        ][..]);
+        // not selected
+        assert_eq!(bodies[1].code().elements(), &[End][..]);
+    }
+
+    #[test]
+    fn keeps_names() {
+        let mut names = NameSection::default();
+        *names.functions_mut() = Some(NameMap::new(
+            vec![(0, "log".to_owned()), (1, "main".to_owned())].into_iter().collect::<BTreeMap<_, _>>()
+        ));
+
+        let mut traced = module()
+            .with_import(ImportEntry::new("env".into(), "log".into(), External::Function(0)))
+            .function()
+                .signature().build()
+                .body().build()
+                .build()
+            .build();
+        traced.sections_mut().push(Section::Custom(names.into_custom().unwrap()));
+
+        let traced = Trace::new().apply(traced).expect("tracing to succeed");
+
+        let names = traced.sections().iter()
+            .filter_map(|section| match *section {
+                Section::Custom(ref custom) => NameSection::from_custom(custom),
+                _ => None,
+            })
+            .next()
+            .expect("name section to be kept")
+            .expect("name section to parse");
+        let functions = names.functions().unwrap();
+        assert_eq!(functions.get(0), Some("log"));
+        assert_eq!(functions.get(3), Some("main"));
+        assert_eq!(functions.names().len(), 2);
+    }
+}
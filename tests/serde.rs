@@ -0,0 +1,25 @@
+//! JSON roundtrip of the module with the `serde` feature.
+//!
+//! Kept out of the library tests: linking `serde_json` there makes
+//! comparisons like `assert_eq!(expected, val.into())` ambiguous.
+
+#![cfg(feature = "serde")]
+
+extern crate sophon_wasm;
+extern crate serde_json;
+
+use sophon_wasm::{deserialize_file, serialize};
+use sophon_wasm::elements::Module;
+
+#[test]
+fn json_roundtrip() {
+    let module = deserialize_file("./res/cases/v1/test5.wasm").expect("Should be deserialized");
+    let json = serde_json::to_string(&module).expect("json serialization to succeed");
+    let module_new: Module = serde_json::from_str(&json).expect("json deserialization to succeed");
+
+    assert_eq!(
+        serialize(module).expect("serialization to succeed"),
+        serialize(module_new).expect("serialization to succeed"),
+        "Module should be the same after json roundtrip"
+    );
+}